@@ -0,0 +1,174 @@
+use crate::protocol::RtcPacket;
+use bevy::prelude::*;
+use bevy_matchbox::matchbox_socket::Packet;
+
+/// The size of the length prefix of each packet in a batch.
+const LENGTH_SIZE: usize = 2;
+
+/// Batch the packets sent to each peer on each channel in a frame into as few
+/// datagrams as possible.
+///
+/// Batches are flagged in their header and unpacked automatically by peers,
+/// whether or not they enable batching themselves. Packets larger than the
+/// MTU are sent on their own.
+#[derive(Resource, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Batching {
+    /// The largest datagram to build, in bytes.
+    pub mtu: usize,
+}
+
+impl Default for Batching {
+    fn default() -> Self {
+        // Below the common WebRTC data channel MTU, leaving room for
+        // SCTP, DTLS and UDP overhead.
+        Self { mtu: 1200 }
+    }
+}
+
+/// Builds a batch of packets up to the MTU.
+pub(crate) struct Batch {
+    mtu: usize,
+    /// The protocol name and packet of each packet in the batch.
+    packets: Vec<(&'static str, Packet)>,
+    len: usize,
+}
+
+impl Batch {
+    pub fn new(mtu: usize) -> Self {
+        Self {
+            mtu,
+            packets: vec![],
+            len: RtcPacket::HEADER_SIZE,
+        }
+    }
+
+    /// Returns true if a packet can be added without going over the MTU.
+    pub fn fits(&self, packet: &Packet) -> bool {
+        self.len + LENGTH_SIZE + packet.len() <= self.mtu && packet.len() <= u16::MAX as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.packets.is_empty()
+    }
+
+    pub fn push(&mut self, protocol: &'static str, packet: Packet) {
+        self.len += LENGTH_SIZE + packet.len();
+        self.packets.push((protocol, packet));
+    }
+
+    /// Take the batch as a datagram, with the protocol names of the packets in
+    /// it. A single packet is sent as is.
+    pub fn take(&mut self) -> Option<(Vec<&'static str>, Packet)> {
+        self.len = RtcPacket::HEADER_SIZE;
+        let mut packets = std::mem::take(&mut self.packets);
+        match packets.len() {
+            0 => None,
+            1 => packets
+                .pop()
+                .map(|(protocol, packet)| (vec![protocol], packet)),
+            _ => {
+                let mut buf = Vec::with_capacity(self.mtu);
+                RtcPacket::write_header(0, RtcPacket::BATCHED, &mut buf);
+                let protocols = packets
+                    .into_iter()
+                    .map(|(protocol, packet)| {
+                        buf.extend_from_slice(&(packet.len() as u16).to_le_bytes());
+                        buf.extend_from_slice(&packet);
+                        protocol
+                    })
+                    .collect();
+                Some((protocols, buf.into_boxed_slice()))
+            }
+        }
+    }
+}
+
+/// Split the payload of a batch into its packets, returning `None` if it is
+/// truncated.
+pub(crate) fn unbatch(mut data: &[u8]) -> Option<Vec<&[u8]>> {
+    let mut packets = vec![];
+    while !data.is_empty() {
+        if data.len() < LENGTH_SIZE {
+            return None;
+        }
+        let (len, rest) = data.split_at(LENGTH_SIZE);
+        let len = u16::from_le_bytes([len[0], len[1]]) as usize;
+        if rest.len() < len {
+            return None;
+        }
+        let (packet, rest) = rest.split_at(len);
+        packets.push(packet);
+        data = rest;
+    }
+    Some(packets)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn packet(msg_id: u16, len: usize) -> Packet {
+        let mut buf = vec![];
+        RtcPacket::write_header(msg_id, 0, &mut buf);
+        buf.resize(len, msg_id as u8);
+        buf.into_boxed_slice()
+    }
+
+    #[test]
+    fn batches_round_trip() {
+        let mut batch = Batch::new(1200);
+        batch.push("a", packet(1, 10));
+        batch.push("b", packet(2, 20));
+
+        let (protocols, datagram) = batch.take().unwrap();
+        assert_eq!(protocols, vec!["a", "b"]);
+        assert_eq!(datagram.len(), RtcPacket::HEADER_SIZE + 2 + 10 + 2 + 20);
+        let header = RtcPacket::parse(&datagram).unwrap();
+        assert_eq!(header.flags, RtcPacket::BATCHED);
+        let packets = unbatch(header.data).unwrap();
+        assert_eq!(packets, vec![&packet(1, 10)[..], &packet(2, 20)[..]]);
+        assert!(batch.is_empty());
+        assert_eq!(batch.take(), None);
+    }
+
+    #[test]
+    fn a_single_packet_is_sent_unbatched() {
+        let mut batch = Batch::new(1200);
+        batch.push("a", packet(1, 10));
+
+        assert_eq!(batch.take(), Some((vec!["a"], packet(1, 10))));
+    }
+
+    #[test]
+    fn batches_stop_at_the_mtu() {
+        let mtu = RtcPacket::HEADER_SIZE + 2 * (LENGTH_SIZE + 10);
+        let mut batch = Batch::new(mtu);
+        assert!(batch.fits(&packet(1, 10)));
+        batch.push("a", packet(1, 10));
+        assert!(batch.fits(&packet(2, 10)));
+        batch.push("b", packet(2, 10));
+        assert!(!batch.fits(&packet(3, 10)));
+
+        let (_, datagram) = batch.take().unwrap();
+        assert_eq!(datagram.len(), mtu);
+        // Taking the batch makes room again
+        assert!(batch.fits(&packet(3, 10)));
+    }
+
+    #[test]
+    fn packets_larger_than_the_mtu_never_fit() {
+        let batch = Batch::new(100);
+
+        assert!(!batch.fits(&packet(1, 100)));
+    }
+
+    #[test]
+    fn truncated_batches_are_rejected() {
+        // A length prefix cut in half
+        assert_eq!(unbatch(&[4]), None);
+        // A packet shorter than its length prefix
+        assert_eq!(unbatch(&[4, 0, 1, 2, 3]), None);
+        assert_eq!(unbatch(&[2, 0, 1, 2, 1]), None);
+        assert_eq!(unbatch(&[]), Some(vec![]));
+    }
+}
@@ -0,0 +1,239 @@
+use crate::socket::RtcSocketPlurality;
+use bevy::prelude::*;
+use bevy_matchbox::matchbox_socket::{self, WebRtcSocket, WebRtcSocketBuilder};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// The name of the channel payloads sent with the `unreliable_*` methods use.
+pub const UNRELIABLE_CHANNEL: &str = "unreliable";
+/// The name of the channel payloads sent with the `reliable_*` methods use.
+pub const RELIABLE_CHANNEL: &str = "reliable";
+/// The name of the channel payloads sent with the `unordered_unreliable_*`
+/// methods use.
+pub const UNORDERED_UNRELIABLE_CHANNEL: &str = "unordered_unreliable";
+/// The name of the channel payloads sent with the `reliable_unordered_*`
+/// methods use.
+pub const RELIABLE_UNORDERED_CHANNEL: &str = "reliable_unordered";
+
+/// A data channel of the socket, declared in the `channels` of
+/// `RtcServerPlugin` and `RtcClientPlugin`.
+///
+/// Each channel delivers its packets independently, so a lost packet on one
+/// channel never holds up the packets of another. Protocols are bound to a
+/// channel with [`ProtocolOptions::channel`](crate::protocol::ProtocolOptions::channel).
+///
+/// ```
+/// use bevy_rtc::ChannelConfig;
+///
+/// let channels = vec![
+///     // Late voice samples are useless, so never resend them
+///     ChannelConfig::unreliable("voice"),
+///     // Large transfers shouldn't hold up game state
+///     ChannelConfig::reliable("bulk"),
+/// ];
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChannelConfig {
+    /// The name protocols are bound to the channel with.
+    pub name: String,
+    /// Deliver packets in the order they were sent, waiting for late packets.
+    pub ordered: bool,
+    /// How many times a lost packet is resent before giving up, or `None` to
+    /// resend it until it is delivered.
+    pub max_retransmits: Option<u16>,
+}
+
+impl ChannelConfig {
+    /// A channel which resends lost packets, and delivers packets in order.
+    pub fn reliable(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            ordered: true,
+            max_retransmits: None,
+        }
+    }
+
+    /// A channel which never resends lost packets, and delivers packets as
+    /// soon as they arrive.
+    pub fn unreliable(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            ordered: false,
+            max_retransmits: Some(0),
+        }
+    }
+
+    /// Returns true if every packet sent on this channel is delivered.
+    pub fn is_reliable(&self) -> bool {
+        self.max_retransmits.is_none()
+    }
+}
+
+impl From<&ChannelConfig> for matchbox_socket::ChannelConfig {
+    fn from(config: &ChannelConfig) -> Self {
+        Self {
+            ordered: config.ordered,
+            max_retransmits: config.max_retransmits,
+        }
+    }
+}
+
+impl fmt::Display for ChannelConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let ordering = if self.ordered { "ordered" } else { "unordered" };
+        match self.max_retransmits {
+            None => write!(f, "{} ({ordering}, reliable)", self.name),
+            Some(retransmits) => {
+                write!(f, "{} ({ordering}, {retransmits} retransmits)", self.name)
+            }
+        }
+    }
+}
+
+/// The data channels of the socket, in the order they are opened. The
+/// built-in channels come first, followed by the channels of the plugin.
+#[derive(Resource, Debug, Clone)]
+pub(crate) struct Channels {
+    configs: Vec<ChannelConfig>,
+}
+
+impl Channels {
+    /// The built-in channels followed by extra channels.
+    ///
+    /// # Panics
+    ///
+    /// Panics if two channels have the same name.
+    pub fn new(extra: &[ChannelConfig]) -> Self {
+        let mut configs = vec![
+            // Match UNRELIABLE_CHANNEL_INDEX
+            ChannelConfig {
+                name: UNRELIABLE_CHANNEL.to_string(),
+                ordered: true,
+                max_retransmits: Some(0),
+            },
+            // Match RELIABLE_CHANNEL_INDEX
+            ChannelConfig::reliable(RELIABLE_CHANNEL),
+            // Match UNORDERED_UNRELIABLE_CHANNEL_INDEX
+            ChannelConfig::unreliable(UNORDERED_UNRELIABLE_CHANNEL),
+            // Match RELIABLE_UNORDERED_CHANNEL_INDEX
+            ChannelConfig {
+                name: RELIABLE_UNORDERED_CHANNEL.to_string(),
+                ordered: false,
+                max_retransmits: None,
+            },
+        ];
+        for config in extra {
+            if configs.iter().any(|c| c.name == config.name) {
+                panic!("channel `{}` is declared more than once", config.name);
+            }
+            configs.push(config.clone());
+        }
+        Self { configs }
+    }
+
+    /// Returns the index of a channel by its name.
+    pub fn index(&self, name: &str) -> Option<usize> {
+        self.configs.iter().position(|c| c.name == name)
+    }
+
+    /// Returns the number of channels.
+    pub fn len(&self) -> usize {
+        self.configs.len()
+    }
+
+    /// Returns true if every packet sent on a channel is delivered.
+    pub fn is_reliable(&self, index: usize) -> bool {
+        self.configs
+            .get(index)
+            .is_some_and(ChannelConfig::is_reliable)
+    }
+
+    /// The channel to send a payload on, given the channel its protocol is
+    /// bound to and the channel it was sent with. The bound channel is only
+    /// used if it is as reliable as the channel sent with, so reliable and
+    /// tracked payloads are never sent unreliably, and vice versa.
+    pub fn resolve(&self, bound: Option<usize>, requested: usize) -> usize {
+        bound
+            .filter(|bound| self.is_reliable(*bound) == self.is_reliable(requested))
+            .unwrap_or(requested)
+    }
+
+    /// The channel configurations, in the order they are opened.
+    pub fn configs(&self) -> &[ChannelConfig] {
+        &self.configs
+    }
+
+    /// A socket builder which opens every channel.
+    pub fn socket_builder(
+        &self,
+        room_url: impl Into<String>,
+    ) -> WebRtcSocketBuilder<RtcSocketPlurality> {
+        let [unreliable, reliable, rest @ ..] = self.configs.as_slice() else {
+            unreachable!("the built-in channels are always declared");
+        };
+        rest.iter().fold(
+            WebRtcSocket::builder(room_url)
+                .add_channel(unreliable.into())
+                .add_channel(reliable.into()),
+            |builder, config| builder.add_channel(config.into()),
+        )
+    }
+}
+
+impl Default for Channels {
+    fn default() -> Self {
+        Self::new(&[])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::socket::{
+        RELIABLE_CHANNEL_INDEX, RELIABLE_UNORDERED_CHANNEL_INDEX, UNRELIABLE_CHANNEL_INDEX,
+    };
+
+    #[test]
+    fn bound_channels_replace_channels_as_reliable() {
+        let channels = Channels::new(&[
+            ChannelConfig::reliable("bulk"),
+            ChannelConfig::unreliable("voice"),
+        ]);
+        let bulk = channels.index("bulk");
+        let voice = channels.index("voice");
+
+        assert_eq!(
+            channels.resolve(bulk, RELIABLE_CHANNEL_INDEX),
+            bulk.unwrap()
+        );
+        assert_eq!(
+            channels.resolve(bulk, RELIABLE_UNORDERED_CHANNEL_INDEX),
+            bulk.unwrap()
+        );
+        assert_eq!(
+            channels.resolve(voice, UNRELIABLE_CHANNEL_INDEX),
+            voice.unwrap()
+        );
+        assert_eq!(
+            channels.resolve(None, RELIABLE_CHANNEL_INDEX),
+            RELIABLE_CHANNEL_INDEX
+        );
+    }
+
+    #[test]
+    fn bound_channels_never_change_reliability() {
+        let channels = Channels::new(&[
+            ChannelConfig::reliable("bulk"),
+            ChannelConfig::unreliable("voice"),
+        ]);
+
+        assert_eq!(
+            channels.resolve(channels.index("voice"), RELIABLE_CHANNEL_INDEX),
+            RELIABLE_CHANNEL_INDEX
+        );
+        assert_eq!(
+            channels.resolve(channels.index("bulk"), UNRELIABLE_CHANNEL_INDEX),
+            UNRELIABLE_CHANNEL_INDEX
+        );
+    }
+}
@@ -1,5 +1,5 @@
 use super::router::{IncomingMessages, OutgoingMessages};
-use crate::protocol::Protocol;
+use crate::{protocol::Protocol, receipt::MessageHandle};
 use bevy::{ecs::system::SystemParam, prelude::*};
 
 #[derive(SystemParam, Debug)]
@@ -7,6 +7,8 @@ pub struct RtcClient<'w, M: Protocol> {
     // Option is none if it's send-only or read-only.
     pub(crate) incoming: Option<ResMut<'w, IncomingMessages<M>>>,
     pub(crate) outgoing: Option<ResMut<'w, OutgoingMessages<M>>>,
+    /// The clock of the send rate limiter.
+    pub(crate) time: Res<'w, Time<Real>>,
 }
 
 impl<'w, M: Protocol> RtcClient<'w, M> {
@@ -38,10 +40,20 @@ impl<'w, M: Protocol> RtcClient<'w, M> {
         }
     }
 
-    /// Consumes all messages in the buffer and iterate on them.
+    /// Consumes all messages in the buffer and iterate on them. Tracked
+    /// messages are acknowledged to the host.
     pub fn read(&mut self) -> Vec<M> {
         if let Some(ref mut incoming) = self.incoming {
-            incoming.messages.drain(..).collect()
+            let IncomingMessages {
+                messages, receipts, ..
+            } = &mut **incoming;
+            messages
+                .drain(..)
+                .map(|(payload, receipt)| {
+                    receipts.extend(receipt);
+                    payload
+                })
+                .collect()
         } else {
             panic!(
                 "Attempting to read from `{}` is not allowed, it is registered write only.",
@@ -54,7 +66,9 @@ impl<'w, M: Protocol> RtcClient<'w, M> {
     /// lazy behavior, only when the send rate allows.
     pub fn reliable_to_host_with(&mut self, message_fn: impl Fn() -> M) {
         if let Some(ref mut outgoing) = self.outgoing {
-            outgoing.reliable_to_host.push(message_fn());
+            if outgoing.limiter.try_acquire(None, self.time.elapsed()) {
+                outgoing.reliable_to_host.push(message_fn());
+            }
         } else {
             panic!(
                 "Attempting to write `{}` is not allowed, it is registered read only.",
@@ -67,7 +81,40 @@ impl<'w, M: Protocol> RtcClient<'w, M> {
     /// is created with lazy behavior, only when the send rate allows.
     pub fn unreliable_to_host_with(&mut self, message_fn: impl Fn() -> M) {
         if let Some(ref mut outgoing) = self.outgoing {
-            outgoing.unreliable_to_host.push(message_fn());
+            if outgoing.limiter.try_acquire(None, self.time.elapsed()) {
+                outgoing.unreliable_to_host.push(message_fn());
+            }
+        } else {
+            panic!(
+                "Attempting to write `{}` is not allowed, it is registered read only.",
+                M::reflect_name()
+            );
+        }
+    }
+
+    /// Send a payload to the host with reliability, in any order. The payload
+    /// is created with lazy behavior, only when the send rate allows.
+    pub fn reliable_unordered_to_host_with(&mut self, message_fn: impl Fn() -> M) {
+        if let Some(ref mut outgoing) = self.outgoing {
+            if outgoing.limiter.try_acquire(None, self.time.elapsed()) {
+                outgoing.reliable_unordered_to_host.push(message_fn());
+            }
+        } else {
+            panic!(
+                "Attempting to write `{}` is not allowed, it is registered read only.",
+                M::reflect_name()
+            );
+        }
+    }
+
+    /// Send a payload to the host with no expectation of delivery, in any
+    /// order. The payload is created with lazy behavior, only when the send
+    /// rate allows.
+    pub fn unordered_unreliable_to_host_with(&mut self, message_fn: impl Fn() -> M) {
+        if let Some(ref mut outgoing) = self.outgoing {
+            if outgoing.limiter.try_acquire(None, self.time.elapsed()) {
+                outgoing.unordered_unreliable_to_host.push(message_fn());
+            }
         } else {
             panic!(
                 "Attempting to write `{}` is not allowed, it is registered read only.",
@@ -77,6 +124,10 @@ impl<'w, M: Protocol> RtcClient<'w, M> {
     }
 
     /// Send a payload to the host with reliability.
+    ///
+    /// If the protocol is bound to a reliable channel with
+    /// [`ProtocolOptions::channel`](crate::protocol::ProtocolOptions::channel),
+    /// the payload is sent on that channel instead.
     pub fn reliable_to_host(&mut self, message: M) {
         if let Some(ref mut outgoing) = self.outgoing {
             outgoing.reliable_to_host.push(message);
@@ -88,6 +139,27 @@ impl<'w, M: Protocol> RtcClient<'w, M> {
         }
     }
 
+    /// Send a payload to the host with reliability, and track when the host
+    /// reads it. An [`RtcDelivered`](crate::RtcDelivered) event with the
+    /// returned handle is sent once it does, or an
+    /// [`RtcDeliveryTimeout`](crate::RtcDeliveryTimeout) event if it does not
+    /// within the plugin's `delivery_timeout`, or if the payload fails to
+    /// encode.
+    ///
+    /// If the protocol is bound to a reliable channel with
+    /// [`ProtocolOptions::channel`](crate::protocol::ProtocolOptions::channel),
+    /// the payload is sent on that channel instead.
+    pub fn reliable_to_host_tracked(&mut self, message: M) -> MessageHandle {
+        if let Some(ref mut outgoing) = self.outgoing {
+            outgoing.track(message)
+        } else {
+            panic!(
+                "Attempting to write `{}` is not allowed, it is registered read only.",
+                M::reflect_name()
+            );
+        }
+    }
+
     /// Send a payload to the host with no expectation of delivery.
     pub fn unreliable_to_host(&mut self, message: M) {
         if let Some(ref mut outgoing) = self.outgoing {
@@ -99,4 +171,28 @@ impl<'w, M: Protocol> RtcClient<'w, M> {
             );
         }
     }
+
+    /// Send a payload to the host with reliability, in any order.
+    pub fn reliable_unordered_to_host(&mut self, message: M) {
+        if let Some(ref mut outgoing) = self.outgoing {
+            outgoing.reliable_unordered_to_host.push(message);
+        } else {
+            panic!(
+                "Attempting to write `{}` is not allowed, it is registered read only.",
+                M::reflect_name()
+            );
+        }
+    }
+
+    /// Send a payload to the host with no expectation of delivery, in any order.
+    pub fn unordered_unreliable_to_host(&mut self, message: M) {
+        if let Some(ref mut outgoing) = self.outgoing {
+            outgoing.unordered_unreliable_to_host.push(message);
+        } else {
+            panic!(
+                "Attempting to write `{}` is not allowed, it is registered read only.",
+                M::reflect_name()
+            );
+        }
+    }
 }
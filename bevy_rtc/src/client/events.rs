@@ -1,6 +1,8 @@
 use bevy::prelude::Event;
 use bevy_matchbox::matchbox_socket::PeerId;
 
+use crate::ProtocolMismatch;
+
 /// Socket events that are possible to subscribe to in Bevy
 #[derive(Debug, Clone, Event)]
 pub enum RtcClientEvent {
@@ -10,6 +12,9 @@ pub enum RtcClientEvent {
     ConnectedToHost(PeerId),
     /// The socket disconnected from the host
     DisconnectedFromHost { reason: Option<String> },
+    /// The host registered protocols or an encoding incompatible with this
+    /// client. The client disconnects from the host after this event.
+    ProtocolMismatch(ProtocolMismatch),
 }
 
 // TODO: This should be a command, e.g. Commands.connect_rtc(addr), Commands.disconnect_rtc
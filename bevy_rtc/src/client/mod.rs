@@ -3,6 +3,7 @@ mod client;
 mod events;
 mod plugin;
 mod router;
+mod snapshot;
 mod state;
 mod systems;
 
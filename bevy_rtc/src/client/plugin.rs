@@ -1,10 +1,19 @@
 use super::{
-    systems, AddClientProtocolExt, RtcClientEvent, RtcClientRequestEvent, RtcClientState,
-    RtcClientStatus,
+    router::IncomingMessages, systems, AddClientProtocolExt, RtcClientEvent, RtcClientRequestEvent,
+    RtcClientState, RtcClientStatus,
 };
 use crate::{
-    events::SocketRecvEvent,
+    batching::Batching,
+    channel::{ChannelConfig, Channels},
+    compression::Compression,
+    dispatch::{dispatch_packets, ReceivedPackets},
+    events::{RtcDecodeError, RtcDelivered, RtcDeliveryTimeout, RtcSendError, SocketRecvEvent},
+    fragmentation::{expire_fragments, Fragmentation, FragmentationStats, Reassembly},
     latency::LatencyTracerPayload,
+    outbox::{flush_outbox, Backpressure, Outbox, RtcSendQueues},
+    receipt::{expire_receipts, DeliveryReceipt, Receipts, DEFAULT_DELIVERY_TIMEOUT},
+    registry::RtcProtocolRegistry,
+    sequence::Sequences,
     socket::{common_socket_reader, RtcSocket},
     transport_encoding::TransportEncoding,
 };
@@ -18,18 +27,85 @@ pub struct RtcClientPlugin {
     /// # Available encodings:
     /// - JSON: with the `json` cargo feature
     /// - Binary: with the `binary` cargo feature
+    /// - MessagePack: with the `msgpack` cargo feature
+    /// - Postcard: with the `postcard` cargo feature
+    /// - BitPacked: bit-level packing which honors [`Quantized`](crate::quantize::Quantized) precision
+    /// - Custom: any user-defined [`Codec`](crate::codec::Codec)
     pub encoding: TransportEncoding,
+    /// Compress large payloads before sending them, or `None` to never compress.
+    /// Compressed payloads are always decompressed when received.
+    pub compression: Option<Compression>,
+    /// Batch small payloads into fewer datagrams, or `None` to send every
+    /// payload in its own datagram. Batches are always unpacked when received.
+    pub batching: Option<Batching>,
+    /// Split large unreliable payloads into fragments, or `None` to send them
+    /// whole. Fragments are always reassembled when received.
+    pub fragmentation: Option<Fragmentation>,
+    /// Data channels to open in addition to the built-in channels of the
+    /// `reliable_*`, `unreliable_*`, `reliable_unordered_*` and
+    /// `unordered_unreliable_*` methods. Protocols are bound to them by name with
+    /// [`ProtocolOptions::channel`](crate::protocol::ProtocolOptions::channel).
+    /// Peers must declare the same channels.
+    pub channels: Vec<ChannelConfig>,
+    /// How long to wait for a peer to read a tracked payload before sending
+    /// [`RtcDeliveryTimeout`](crate::RtcDeliveryTimeout).
+    pub delivery_timeout: Duration,
+    /// Limit the bytes sent to each peer on each channel, queueing or dropping
+    /// packets over the limit, or `None` to send every packet immediately.
+    /// Queues can be watched in [`RtcSendQueues`](crate::RtcSendQueues).
+    pub backpressure: Option<Backpressure>,
+}
+
+impl Default for RtcClientPlugin {
+    fn default() -> Self {
+        Self {
+            encoding: TransportEncoding::Json,
+            compression: None,
+            batching: None,
+            fragmentation: None,
+            channels: vec![],
+            delivery_timeout: DEFAULT_DELIVERY_TIMEOUT,
+            backpressure: None,
+        }
+    }
 }
 
 impl Plugin for RtcClientPlugin {
     fn build(&self, app: &mut App) {
-        app.insert_resource(self.encoding)
+        if let Some(compression) = self.compression {
+            app.insert_resource(compression);
+        }
+        if let Some(batching) = self.batching {
+            app.insert_resource(batching);
+        }
+        if let Some(fragmentation) = self.fragmentation {
+            app.insert_resource(fragmentation);
+        }
+        if let Some(backpressure) = self.backpressure {
+            app.insert_resource(backpressure);
+        }
+        let channels = Channels::new(&self.channels);
+        app.insert_resource(self.encoding.clone())
+            .insert_resource(RtcSendQueues::new(&channels))
+            .insert_resource(channels)
+            .init_resource::<RtcProtocolRegistry>()
+            .init_resource::<ReceivedPackets>()
+            .init_resource::<Outbox>()
+            .init_resource::<Sequences>()
+            .insert_resource(Receipts::new(self.delivery_timeout))
+            .init_resource::<Reassembly>()
+            .init_resource::<FragmentationStats>()
             .add_event::<SocketRecvEvent>()
             .insert_resource(RtcClientState::default())
             .add_client_rw_protocol::<LatencyTracerPayload>(2)
+            .add_client_rw_unbounded_protocol::<DeliveryReceipt>()
             .init_state::<RtcClientStatus>()
             .add_event::<RtcClientRequestEvent>()
             .add_event::<RtcClientEvent>()
+            .add_event::<RtcSendError>()
+            .add_event::<RtcDecodeError>()
+            .add_event::<RtcDelivered>()
+            .add_event::<RtcDeliveryTimeout>()
             .add_systems(OnEnter(RtcClientStatus::Establishing), systems::init_socket)
             .add_systems(
                 OnEnter(RtcClientStatus::Disconnected),
@@ -38,7 +114,15 @@ impl Plugin for RtcClientPlugin {
             .add_systems(First, systems::connection_request_handler)
             .add_systems(
                 First,
-                (common_socket_reader, systems::client_event_writer)
+                (
+                    common_socket_reader,
+                    expire_fragments,
+                    dispatch_packets,
+                    systems::client_event_writer,
+                    systems::send_handshake,
+                    systems::read_handshake,
+                    systems::read_hang_up,
+                )
                     .chain()
                     .run_if(resource_exists::<RtcSocket>),
             )
@@ -55,6 +139,27 @@ impl Plugin for RtcClientPlugin {
                     systems::send_latency_tracers.run_if(on_timer(Duration::from_millis(100))),
                 )
                     .run_if(in_state(RtcClientStatus::Connected)),
-            );
+            )
+            .add_systems(
+                First,
+                (systems::read_delivery_receipts, expire_receipts)
+                    .chain()
+                    .after(IncomingMessages::<DeliveryReceipt>::receive_payloads)
+                    .run_if(resource_exists::<RtcSocket>),
+            )
+            .add_systems(
+                Last,
+                (flush_outbox, systems::disconnect_when_too_slow)
+                    .chain()
+                    .run_if(resource_exists::<RtcSocket>),
+            )
+            .add_systems(Last, systems::count_decode_failures);
+    }
+
+    fn finish(&self, app: &mut App) {
+        let channels = app.world.resource::<Channels>();
+        app.world
+            .resource::<RtcProtocolRegistry>()
+            .check_channels(channels);
     }
 }
@@ -1,9 +1,16 @@
 mod receive;
 mod send;
 
+use super::{snapshot::ClientSnapshots, RtcClientStatus};
 use crate::{
-    protocol::Protocol,
-    socket::{common_socket_reader, RtcSocket},
+    dispatch::dispatch_packets,
+    outbox::flush_outbox,
+    protocol::{Protocol, ProtocolDirection, ProtocolOptions},
+    rate::SendLimiter,
+    receipt::DeliveryReceipt,
+    registry::RtcProtocolRegistry,
+    snapshot::{Snapshot, SnapshotAck, SnapshotPayload},
+    socket::RtcSocket,
 };
 use bevy::prelude::*;
 use std::collections::VecDeque;
@@ -26,6 +33,13 @@ pub trait AddClientProtocolExt {
     /// Register a protocol for sending and receiving. Use a growable buffer
     /// for reading.
     fn add_client_rw_unbounded_protocol<M: Protocol>(&mut self) -> &mut Self;
+    /// Register a snapshot sent by the server as deltas, which are applied to
+    /// their baselines and acknowledged. Allocate a bounded buffer for reading
+    /// `RtcClient<SnapshotPayload<S>>`.
+    fn add_client_snapshot_protocol<S: Snapshot>(&mut self, bound: usize) -> &mut Self;
+    /// Set the options of a protocol, e.g. to encode it differently than the
+    /// plugin's transport encoding. The server must use the same options.
+    fn configure_client_protocol<M: Protocol>(&mut self, options: ProtocolOptions) -> &mut Self;
 }
 
 impl AddClientProtocolExt for App {
@@ -33,13 +47,27 @@ impl AddClientProtocolExt for App {
         if self.world.contains_resource::<OutgoingMessages<M>>() {
             panic!("client already contains resource: {}", M::reflect_name());
         }
+        // Read by the send rate limiter of `RtcClient`
+        self.init_resource::<Time<Real>>();
+        let mut registry = self
+            .world
+            .get_resource_or_insert_with(RtcProtocolRegistry::default);
+        registry.register::<M>(ProtocolDirection::WriteOnly, None);
+        let send_rate = registry.send_rate(M::id());
         self.insert_resource(OutgoingMessages::<M> {
             reliable_to_host: vec![],
             unreliable_to_host: vec![],
+            reliable_unordered_to_host: vec![],
+            unordered_unreliable_to_host: vec![],
+            reliable_to_host_tracked: vec![],
+            next_receipt: 0,
+            limiter: SendLimiter::new(send_rate),
         })
         .add_systems(
             Last,
-            OutgoingMessages::<M>::send_payloads.run_if(resource_exists::<RtcSocket>),
+            OutgoingMessages::<M>::send_payloads
+                .before(flush_outbox)
+                .run_if(resource_exists::<RtcSocket>),
         );
         self
     }
@@ -48,14 +76,25 @@ impl AddClientProtocolExt for App {
         if self.world.contains_resource::<IncomingMessages<M>>() {
             panic!("client already contains resource: {}", M::reflect_name());
         }
+        self.init_resource::<Time<Real>>();
+        self.world
+            .get_resource_or_insert_with(RtcProtocolRegistry::default)
+            .register::<M>(ProtocolDirection::ReadOnly, Some(bound));
         self.insert_resource(IncomingMessages::<M> {
             bound,
             messages: VecDeque::new(),
+            receipts: vec![],
         })
         .add_systems(
             First,
             IncomingMessages::<M>::receive_payloads
-                .after(common_socket_reader)
+                .after(dispatch_packets)
+                .run_if(resource_exists::<RtcSocket>),
+        )
+        .add_systems(
+            Last,
+            IncomingMessages::<M>::send_receipts
+                .before(OutgoingMessages::<DeliveryReceipt>::send_payloads)
                 .run_if(resource_exists::<RtcSocket>),
         );
         self
@@ -65,32 +104,49 @@ impl AddClientProtocolExt for App {
         self.add_client_ro_protocol::<M>(usize::MAX)
     }
 
-    fn add_client_rw_protocol<M>(&mut self, bound: usize) -> &mut Self
-    where
-        M: Protocol,
-    {
+    fn add_client_rw_protocol<M: Protocol>(&mut self, bound: usize) -> &mut Self {
         if self.world.contains_resource::<IncomingMessages<M>>()
             || self.world.contains_resource::<OutgoingMessages<M>>()
         {
             panic!("client already contains resource: {}", M::reflect_name());
         }
+        self.init_resource::<Time<Real>>();
+        let mut registry = self
+            .world
+            .get_resource_or_insert_with(RtcProtocolRegistry::default);
+        registry.register::<M>(ProtocolDirection::ReadWrite, Some(bound));
+        let send_rate = registry.send_rate(M::id());
         self.insert_resource(IncomingMessages::<M> {
             bound,
             messages: VecDeque::new(),
+            receipts: vec![],
         })
         .insert_resource(OutgoingMessages::<M> {
             reliable_to_host: vec![],
             unreliable_to_host: vec![],
+            reliable_unordered_to_host: vec![],
+            unordered_unreliable_to_host: vec![],
+            reliable_to_host_tracked: vec![],
+            next_receipt: 0,
+            limiter: SendLimiter::new(send_rate),
         })
         .add_systems(
             First,
             IncomingMessages::<M>::receive_payloads
-                .after(common_socket_reader)
+                .after(dispatch_packets)
                 .run_if(resource_exists::<RtcSocket>),
         )
         .add_systems(
             Last,
-            OutgoingMessages::<M>::send_payloads.run_if(resource_exists::<RtcSocket>),
+            IncomingMessages::<M>::send_receipts
+                .before(OutgoingMessages::<DeliveryReceipt>::send_payloads)
+                .run_if(resource_exists::<RtcSocket>),
+        )
+        .add_systems(
+            Last,
+            OutgoingMessages::<M>::send_payloads
+                .before(flush_outbox)
+                .run_if(resource_exists::<RtcSocket>),
         );
         self
     }
@@ -98,4 +154,43 @@ impl AddClientProtocolExt for App {
     fn add_client_rw_unbounded_protocol<M: Protocol>(&mut self) -> &mut Self {
         self.add_client_rw_protocol::<M>(usize::MAX)
     }
+
+    fn add_client_snapshot_protocol<S: Snapshot>(&mut self, bound: usize) -> &mut Self {
+        if !self
+            .world
+            .contains_resource::<OutgoingMessages<SnapshotAck>>()
+        {
+            // Acknowledgements of every snapshot share one protocol
+            self.add_client_wo_protocol::<SnapshotAck>();
+        }
+        self.add_client_ro_protocol::<SnapshotPayload<S>>(bound)
+            .init_resource::<ClientSnapshots<S>>()
+            .add_systems(
+                First,
+                ClientSnapshots::<S>::apply_snapshots
+                    .after(IncomingMessages::<SnapshotPayload<S>>::receive_payloads)
+                    .run_if(resource_exists::<RtcSocket>),
+            )
+            .add_systems(
+                OnEnter(RtcClientStatus::Disconnected),
+                ClientSnapshots::<S>::reset,
+            );
+        // The generic tracer cannot explore the body nested in the payload
+        #[cfg(feature = "schema")]
+        self.world.resource_mut::<RtcProtocolRegistry>().set_tracer(
+            SnapshotPayload::<S>::id(),
+            crate::schema::trace_snapshot::<S>,
+        );
+        self
+    }
+
+    fn configure_client_protocol<M: Protocol>(&mut self, options: ProtocolOptions) -> &mut Self {
+        self.world
+            .get_resource_or_insert_with(RtcProtocolRegistry::default)
+            .configure::<M>(options.clone());
+        if let Some(mut outgoing) = self.world.get_resource_mut::<OutgoingMessages<M>>() {
+            outgoing.limiter = SendLimiter::new(options.send_rate);
+        }
+        self
+    }
 }
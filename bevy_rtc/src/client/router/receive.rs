@@ -1,27 +1,74 @@
-use crate::{events::SocketRecvEvent, protocol::Protocol, transport_encoding::TransportEncoding};
+use super::OutgoingMessages;
+use crate::{
+    dispatch::{ReceivedPacket, ReceivedPackets},
+    events::RtcDecodeError,
+    protocol::Protocol,
+    receipt::DeliveryReceipt,
+    registry::RtcProtocolRegistry,
+    sequence::Sequences,
+    transport_encoding::TransportEncoding,
+};
 use bevy::prelude::*;
 use std::collections::VecDeque;
 
 #[derive(Default, Debug, Resource)]
 pub struct IncomingMessages<M: Protocol> {
     pub bound: usize,
-    pub messages: VecDeque<M>,
+    /// Payloads waiting to be read, with their receipt ID if tracked.
+    pub messages: VecDeque<(M, Option<u32>)>,
+    /// Receipt IDs of tracked payloads read since the last receipts were sent.
+    pub receipts: Vec<u32>,
 }
 
 impl<M: Protocol> IncomingMessages<M> {
-    pub fn receive_payloads(
+    pub(crate) fn receive_payloads(
         mut incoming: ResMut<Self>,
-        mut events: EventReader<SocketRecvEvent>,
+        mut received: ResMut<ReceivedPackets>,
         encoding: Res<TransportEncoding>,
+        registry: Res<RtcProtocolRegistry>,
+        mut sequences: ResMut<Sequences>,
+        mut decode_errors: EventWriter<RtcDecodeError>,
     ) {
+        let encoding = registry.encoding(M::id()).unwrap_or(&encoding);
         let bound = incoming.bound;
-        let packets: Vec<_> = events
-            .read()
-            .map(|&SocketRecvEvent((_peer_id, ref packet))| packet)
-            .filter_map(|p| M::from_packet(p, &encoding))
-            .enumerate()
-            .take_while(|(read, _)| *read <= bound)
-            .map(|(_, packet)| packet)
+        let packets: Vec<_> = received
+            .take(M::id())
+            .into_iter()
+            .filter(|received| match received.seq {
+                Some(seq) if !sequences.accept(M::id(), received.peer, seq) => {
+                    registry.count_stale(M::id());
+                    false
+                }
+                _ => true,
+            })
+            .filter_map(
+                |ReceivedPacket {
+                     peer: peer_id,
+                     receipt,
+                     packet,
+                     ..
+                 }| match M::from_packet(&packet, encoding) {
+                    Ok(payload) => {
+                        registry.count_received(M::id(), packet.len());
+                        Some((payload, receipt))
+                    }
+                    Err(err) => {
+                        registry.count_decode_failure(M::id());
+                        warn!(
+                            "Failed to decode `{}` from {peer_id}: {err}",
+                            M::reflect_name()
+                        );
+                        decode_errors.send(RtcDecodeError {
+                            peer: peer_id,
+                            msg_id: Some(M::id()),
+                            protocol: Some(M::reflect_name().to_string()),
+                            len: packet.len(),
+                            reason: err.to_string(),
+                        });
+                        None
+                    }
+                },
+            )
             .collect();
         trace!("Read {} {} packets", packets.len(), M::reflect_name());
         for packet in packets.into_iter() {
@@ -32,9 +79,25 @@ impl<M: Protocol> IncomingMessages<M> {
                 "The `{}` protocol is overflowing its bounded buffer ({bound}) and dropping packets! Is it being read?",
                 M::reflect_name()
             );
-            while incoming.messages.len() > bound {
-                incoming.messages.pop_front();
-            }
+            // Never acknowledged, so tracked payloads time out
+            let dropped = incoming.messages.len() - bound;
+            incoming.messages.drain(..dropped);
+            registry.count_dropped(M::id(), dropped);
         }
     }
+
+    /// Acknowledges the tracked payloads read since the last receipts were
+    /// sent.
+    pub(crate) fn send_receipts(
+        mut incoming: ResMut<Self>,
+        mut outgoing: ResMut<OutgoingMessages<DeliveryReceipt>>,
+    ) {
+        if incoming.receipts.is_empty() {
+            return;
+        }
+        outgoing.reliable_to_host.push(DeliveryReceipt {
+            protocol: M::id(),
+            receipts: std::mem::take(&mut incoming.receipts),
+        });
+    }
 }
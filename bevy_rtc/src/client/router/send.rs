@@ -1,15 +1,35 @@
 use crate::{
+    channel::Channels,
     client::state::RtcClientState,
+    compression::Compression,
+    events::{RtcDeliveryTimeout, RtcSendError},
+    outbox::Outbox,
     protocol::Protocol,
-    socket::{RtcSocket, RELIABLE_CHANNEL_INDEX, UNRELIABLE_CHANNEL_INDEX},
+    rate::SendLimiter,
+    receipt::{self, MessageHandle, Receipts},
+    registry::RtcProtocolRegistry,
+    sequence::{self, Sequences},
+    socket::{
+        RELIABLE_CHANNEL_INDEX, RELIABLE_UNORDERED_CHANNEL_INDEX,
+        UNORDERED_UNRELIABLE_CHANNEL_INDEX, UNRELIABLE_CHANNEL_INDEX,
+    },
     transport_encoding::TransportEncoding,
 };
 use bevy::prelude::*;
+use bevy_matchbox::prelude::PeerId;
 
 #[derive(Default, Debug, Resource)]
 pub struct OutgoingMessages<M: Protocol> {
     pub reliable_to_host: Vec<M>,
     pub unreliable_to_host: Vec<M>,
+    pub reliable_unordered_to_host: Vec<M>,
+    pub unordered_unreliable_to_host: Vec<M>,
+    /// Payloads sent with reliability and tracked, with their receipt ID.
+    pub reliable_to_host_tracked: Vec<(M, u32)>,
+    /// The receipt ID of the next tracked payload.
+    pub next_receipt: u32,
+    /// Limits the payloads built by the lazy `*_with` methods.
+    pub(crate) limiter: SendLimiter,
 }
 
 impl<M: Protocol> OutgoingMessages<M> {
@@ -18,24 +38,83 @@ impl<M: Protocol> OutgoingMessages<M> {
     pub fn flush(&mut self) {
         self.reliable_to_host.clear();
         self.unreliable_to_host.clear();
+        self.reliable_unordered_to_host.clear();
+        self.unordered_unreliable_to_host.clear();
+        self.reliable_to_host_tracked.clear();
     }
 
+    /// Queue a tracked payload to the host with reliability.
+    pub(crate) fn track(&mut self, message: M) -> MessageHandle {
+        let id = self.next_receipt;
+        self.next_receipt = self.next_receipt.wrapping_add(1);
+        self.reliable_to_host_tracked.push((message, id));
+        MessageHandle::new(M::id(), id)
+    }
+
+    #[allow(clippy::too_many_arguments)]
     pub(crate) fn send_payloads(
         mut queue: ResMut<Self>,
-        mut socket: ResMut<RtcSocket>,
         state: Res<RtcClientState>,
         encoding: Res<TransportEncoding>,
+        registry: Res<RtcProtocolRegistry>,
+        channels: Res<Channels>,
+        compression: Option<Res<Compression>>,
+        mut send_errors: EventWriter<RtcSendError>,
+        mut timeouts: EventWriter<RtcDeliveryTimeout>,
+        mut outbox: ResMut<Outbox>,
+        mut sequences: ResMut<Sequences>,
+        mut receipts: ResMut<Receipts>,
+        time: Res<Time<Real>>,
     ) {
+        let encoding = registry.encoding(M::id()).unwrap_or(&encoding);
+        let compression = compression.filter(|_| !registry.skip_compression(M::id()));
+        let sequenced = registry.sequenced(M::id());
+        // Checked when the plugin is finished
+        let bound_channel = registry
+            .channel(M::id())
+            .and_then(|name| channels.index(name));
+        let mut send = |channel: usize, host: PeerId, message: &M, receipt: Option<u32>| {
+            let channel = channels.resolve(bound_channel, channel);
+            let packet = match message.to_packet(encoding) {
+                Ok(packet) => packet,
+                Err(err) => {
+                    error!("failed to encode packet for {host}: {err}");
+                    send_errors.send(RtcSendError {
+                        peer: host,
+                        protocol: M::reflect_name(),
+                        reason: format!("failed to encode: {err}"),
+                    });
+                    // The handle was already returned, so it must resolve
+                    if let Some(receipt) = receipt {
+                        timeouts.send(RtcDeliveryTimeout(MessageHandle::new(M::id(), receipt)));
+                    }
+                    return;
+                }
+            };
+            let packet = match compression {
+                Some(ref compression) => compression.compress(packet),
+                None => packet,
+            };
+            let packet = match receipt {
+                Some(receipt) => receipt::stamp(packet, receipt),
+                None => packet,
+            };
+            let packet = if sequenced {
+                sequence::stamp(packet, sequences.next(M::id(), host))
+            } else {
+                packet
+            };
+            registry.count_sent(M::id(), packet.len());
+            outbox.push(host, channel, M::reflect_name(), packet);
+            if let Some(receipt) = receipt {
+                receipts.track(host, MessageHandle::new(M::id(), receipt), time.elapsed());
+            }
+        };
+
         if let Some(host) = state.host_peer_id {
             // Client is sending
             for message in queue.reliable_to_host.iter() {
-                if socket
-                    .channel_mut(RELIABLE_CHANNEL_INDEX)
-                    .try_send(message.to_packet(&encoding), host)
-                    .is_err()
-                {
-                    error!("failed to send reliable packet to {host}: {message:?}");
-                }
+                send(RELIABLE_CHANNEL_INDEX, host, message, None);
             }
             if !queue.reliable_to_host.is_empty() {
                 trace!(
@@ -45,13 +124,7 @@ impl<M: Protocol> OutgoingMessages<M> {
                 );
             }
             for message in queue.unreliable_to_host.iter() {
-                if socket
-                    .channel_mut(UNRELIABLE_CHANNEL_INDEX)
-                    .try_send(message.to_packet(&encoding), host)
-                    .is_err()
-                {
-                    error!("failed to send unreliable packet to {host}: {message:?}");
-                }
+                send(UNRELIABLE_CHANNEL_INDEX, host, message, None);
             }
             if !queue.unreliable_to_host.is_empty() {
                 trace!(
@@ -60,7 +133,87 @@ impl<M: Protocol> OutgoingMessages<M> {
                     M::reflect_name()
                 );
             }
+            for message in queue.reliable_unordered_to_host.iter() {
+                send(RELIABLE_UNORDERED_CHANNEL_INDEX, host, message, None);
+            }
+            if !queue.reliable_unordered_to_host.is_empty() {
+                trace!(
+                    "sent {} [RU] {} packets",
+                    queue.reliable_unordered_to_host.len(),
+                    M::reflect_name()
+                );
+            }
+            for message in queue.unordered_unreliable_to_host.iter() {
+                send(UNORDERED_UNRELIABLE_CHANNEL_INDEX, host, message, None);
+            }
+            if !queue.unordered_unreliable_to_host.is_empty() {
+                trace!(
+                    "sent {} [UU] {} packets",
+                    queue.unordered_unreliable_to_host.len(),
+                    M::reflect_name()
+                );
+            }
+            for (message, receipt) in queue.reliable_to_host_tracked.iter() {
+                send(RELIABLE_CHANNEL_INDEX, host, message, Some(*receipt));
+            }
+            if !queue.reliable_to_host_tracked.is_empty() {
+                trace!(
+                    "sent {} [R;T] {} packets",
+                    queue.reliable_to_host_tracked.len(),
+                    M::reflect_name()
+                );
+            }
         }
         queue.flush();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::receipt::DEFAULT_DELIVERY_TIMEOUT;
+    use bevy::{ecs::system::RunSystemOnce, utils::Uuid};
+    use serde::{Deserialize, Serialize};
+    use std::collections::BTreeMap;
+
+    /// JSON maps need string keys, so this fails to encode as JSON.
+    #[derive(Protocol, Serialize, Deserialize, Debug, Clone, Default)]
+    #[protocol(crate = "crate", id = 1)]
+    struct Inventory {
+        slots: BTreeMap<(u8, u8), u32>,
+    }
+
+    #[test]
+    fn tracked_payloads_which_fail_to_encode_time_out() {
+        let host = PeerId(Uuid::from_u128(1));
+        let mut world = World::new();
+        world.insert_resource(RtcClientState {
+            host_peer_id: Some(host),
+            ..default()
+        });
+        world.insert_resource(TransportEncoding::Json);
+        world.insert_resource(Receipts::new(DEFAULT_DELIVERY_TIMEOUT));
+        world.init_resource::<OutgoingMessages<Inventory>>();
+        world.init_resource::<RtcProtocolRegistry>();
+        world.init_resource::<Channels>();
+        world.init_resource::<Outbox>();
+        world.init_resource::<Sequences>();
+        world.init_resource::<Time<Real>>();
+        world.init_resource::<Events<RtcSendError>>();
+        world.init_resource::<Events<RtcDeliveryTimeout>>();
+
+        let slots = BTreeMap::from([((0, 0), 1)]);
+        let handle = world
+            .resource_mut::<OutgoingMessages<Inventory>>()
+            .track(Inventory { slots });
+        world.run_system_once(OutgoingMessages::<Inventory>::send_payloads);
+
+        assert_eq!(world.resource::<Events<RtcSendError>>().len(), 1);
+        let timeouts = world
+            .resource_mut::<Events<RtcDeliveryTimeout>>()
+            .drain()
+            .map(|timeout| timeout.0)
+            .collect::<Vec<_>>();
+        assert_eq!(timeouts, [handle]);
+    }
+}
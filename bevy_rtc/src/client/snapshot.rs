@@ -0,0 +1,202 @@
+use super::router::{IncomingMessages, OutgoingMessages};
+use crate::snapshot::{Snapshot, SnapshotAck, SnapshotBody, SnapshotPayload, SNAPSHOT_HISTORY};
+use bevy::prelude::*;
+use std::collections::VecDeque;
+
+/// The latest snapshots received from the server, which may be the baseline
+/// of the next delta.
+#[derive(Resource, Debug)]
+pub(crate) struct ClientSnapshots<S: Snapshot> {
+    /// Received snapshots, oldest first.
+    received: VecDeque<(u32, S)>,
+}
+
+impl<S: Snapshot> Default for ClientSnapshots<S> {
+    fn default() -> Self {
+        Self {
+            received: VecDeque::new(),
+        }
+    }
+}
+
+impl<S: Snapshot> ClientSnapshots<S> {
+    /// Applies the deltas received this frame to their baselines, and
+    /// acknowledges the latest snapshot. Snapshots older than the latest, or
+    /// with a forgotten baseline, are dropped.
+    pub(crate) fn apply_snapshots(
+        mut snapshots: ResMut<Self>,
+        mut incoming: ResMut<IncomingMessages<SnapshotPayload<S>>>,
+        mut acks: ResMut<OutgoingMessages<SnapshotAck>>,
+    ) {
+        let payloads = std::mem::take(&mut incoming.messages);
+        let mut accepted = false;
+        for (SnapshotPayload { seq, body }, receipt) in payloads {
+            let latest = snapshots.received.back().map(|(latest, _)| *latest);
+            if latest.is_some_and(|latest| seq <= latest) {
+                if matches!(body, SnapshotBody::Full(_))
+                    && snapshots
+                        .received
+                        .iter()
+                        .any(|(received, _)| *received == seq)
+                {
+                    // Applied in an earlier frame, but not read yet
+                    incoming
+                        .messages
+                        .push_back((SnapshotPayload { seq, body }, receipt));
+                    continue;
+                }
+                trace!("dropped stale {} #{seq}", S::PROTOCOL_NAME);
+                continue;
+            }
+            let state = match body {
+                SnapshotBody::Full(state) => state,
+                SnapshotBody::Delta { baseline, delta } => {
+                    let Some((_, base)) = snapshots
+                        .received
+                        .iter()
+                        .find(|(received, _)| *received == baseline)
+                    else {
+                        warn!(
+                            "dropped {} #{seq}, its baseline #{baseline} is unknown",
+                            S::PROTOCOL_NAME
+                        );
+                        continue;
+                    };
+                    base.apply(&delta)
+                }
+            };
+            snapshots.received.push_back((seq, state.clone()));
+            if snapshots.received.len() > SNAPSHOT_HISTORY {
+                snapshots.received.pop_front();
+            }
+            incoming.messages.push_back((
+                SnapshotPayload {
+                    seq,
+                    body: SnapshotBody::Full(state),
+                },
+                receipt,
+            ));
+            accepted = true;
+        }
+        if let Some((seq, _)) = snapshots.received.back().filter(|_| accepted) {
+            acks.unreliable_to_host.push(SnapshotAck {
+                id: S::PROTOCOL_ID,
+                seq: *seq,
+            });
+        }
+    }
+
+    /// Forget the snapshots of the last connection.
+    pub(crate) fn reset(mut snapshots: ResMut<Self>) {
+        snapshots.received.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::ecs::system::RunSystemOnce;
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Snapshot, Serialize, Deserialize, Debug, Clone, PartialEq)]
+    #[snapshot(crate = "crate")]
+    struct Score {
+        #[snapshot(diff)]
+        points: u32,
+    }
+
+    fn world() -> World {
+        let mut world = World::new();
+        world.init_resource::<ClientSnapshots<Score>>();
+        world.insert_resource(IncomingMessages::<SnapshotPayload<Score>> {
+            bound: 8,
+            messages: VecDeque::new(),
+            receipts: vec![],
+        });
+        world.insert_resource(OutgoingMessages::<SnapshotAck> {
+            reliable_to_host: vec![],
+            unreliable_to_host: vec![],
+            reliable_unordered_to_host: vec![],
+            unordered_unreliable_to_host: vec![],
+            reliable_to_host_tracked: vec![],
+            next_receipt: 0,
+            limiter: default(),
+        });
+        world
+    }
+
+    /// Receive snapshots, returning the states read and the acknowledged
+    /// sequence numbers.
+    fn apply(world: &mut World, payloads: Vec<(u32, SnapshotBody<Score>)>) -> (Vec<u32>, Vec<u32>) {
+        world
+            .resource_mut::<IncomingMessages<SnapshotPayload<Score>>>()
+            .messages
+            .extend(
+                payloads
+                    .into_iter()
+                    .map(|(seq, body)| (SnapshotPayload { seq, body }, None)),
+            );
+        world.run_system_once(ClientSnapshots::<Score>::apply_snapshots);
+        let read = world
+            .resource_mut::<IncomingMessages<SnapshotPayload<Score>>>()
+            .messages
+            .drain(..)
+            .map(|(payload, _)| payload.into_state().points)
+            .collect();
+        let acks = world
+            .resource_mut::<OutgoingMessages<SnapshotAck>>()
+            .unreliable_to_host
+            .drain(..)
+            .map(|ack| ack.seq)
+            .collect();
+        (read, acks)
+    }
+
+    fn full(points: u32) -> SnapshotBody<Score> {
+        SnapshotBody::Full(Score { points })
+    }
+
+    fn delta(baseline: u32, from: u32, to: u32) -> SnapshotBody<Score> {
+        SnapshotBody::Delta {
+            baseline,
+            delta: Score { points: to }.diff(&Score { points: from }),
+        }
+    }
+
+    #[test]
+    fn deltas_are_applied_to_their_baseline() {
+        let mut world = world();
+
+        assert_eq!(apply(&mut world, vec![(0, full(1))]), (vec![1], vec![0]));
+        assert_eq!(
+            apply(&mut world, vec![(1, delta(0, 1, 4))]),
+            (vec![4], vec![1])
+        );
+    }
+
+    #[test]
+    fn deltas_with_a_missing_baseline_are_dropped() {
+        let mut world = world();
+        apply(&mut world, vec![(0, full(1))]);
+
+        assert_eq!(
+            apply(&mut world, vec![(2, delta(1, 2, 3))]),
+            (vec![], vec![])
+        );
+        assert_eq!(
+            world.resource::<ClientSnapshots<Score>>().received.back(),
+            Some(&(0, Score { points: 1 }))
+        );
+    }
+
+    #[test]
+    fn stale_snapshots_are_dropped() {
+        let mut world = world();
+        apply(&mut world, vec![(3, full(3))]);
+
+        assert_eq!(
+            apply(&mut world, vec![(2, full(2)), (1, delta(3, 3, 1))]),
+            (vec![], vec![])
+        );
+    }
+}
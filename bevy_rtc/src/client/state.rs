@@ -26,6 +26,8 @@ pub struct RtcClientState {
     pub(crate) latency: Option<Duration>,
     /// The smooth latency to the server
     pub(crate) smoothed_latency: Option<Duration>,
+    /// The number of packets from the server which failed to decode
+    pub(crate) decode_failures: u64,
 }
 
 impl RtcClientState {
@@ -53,4 +55,9 @@ impl RtcClientState {
     pub fn smoothed_latency(&self) -> Option<Duration> {
         self.smoothed_latency
     }
+
+    /// Return the number of packets from the server which failed to decode
+    pub fn decode_failures(&self) -> u64 {
+        self.decode_failures
+    }
 }
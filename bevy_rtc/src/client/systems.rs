@@ -4,33 +4,34 @@ use super::{
     RtcClient,
 };
 use crate::{
+    channel::Channels,
+    dispatch::{ReceivedPacket, ReceivedPackets},
+    events::{RtcDecodeError, RtcDelivered},
+    handshake::{HandshakePayload, HangUpPayload, HANDSHAKE_ENCODING},
     latency::{LatencyTracer, LatencyTracerPayload},
-    socket::{RtcSocket, RtcSocketPlurality},
+    outbox::Outbox,
+    protocol::Protocol,
+    receipt::{DeliveryReceipt, MessageHandle, Receipts},
+    registry::RtcProtocolRegistry,
+    sequence::Sequences,
+    socket::{RtcSocket, RtcSocketPlurality, RELIABLE_CHANNEL_INDEX},
+    transport_encoding::TransportEncoding,
 };
 use bevy::prelude::*;
-use bevy_matchbox::{
-    matchbox_socket::{self, WebRtcSocket},
-    prelude::*,
-};
+use bevy_matchbox::{matchbox_socket, prelude::*};
 use instant::Duration;
 
 /// Initialize the socket
-pub(crate) fn init_socket(mut commands: Commands, socket_res: Res<RtcClientState>) {
+pub(crate) fn init_socket(
+    mut commands: Commands,
+    socket_res: Res<RtcClientState>,
+    channels: Res<Channels>,
+) {
     if let Some(addr) = socket_res.addr.as_ref() {
         debug!("connecting to: {addr:?}");
 
-        // Create matchbox socket
-        let socker_builder = WebRtcSocket::builder(addr)
-            // Match UNRELIABLE_CHANNEL_INDEX
-            .add_channel(ChannelConfig {
-                ordered: true,
-                max_retransmits: Some(0),
-            })
-            // Match RELIABLE_CHANNEL_INDEX
-            .add_channel(ChannelConfig::reliable());
-
         // Open socket
-        commands.open_socket(socker_builder);
+        commands.open_socket(channels.socket_builder(addr));
     } else {
         panic!("state set to connecting without config");
     }
@@ -41,8 +42,12 @@ pub(crate) fn reset_socket(
     mut commands: Commands,
     tracer_query: Query<Entity, With<LatencyTracer>>,
     mut state: ResMut<RtcClientState>,
+    mut sequences: ResMut<Sequences>,
+    mut outbox: ResMut<Outbox>,
 ) {
     commands.close_socket::<RtcSocketPlurality>();
+    sequences.clear();
+    outbox.clear();
     if let Ok(entity) = tracer_query.get_single() {
         commands.entity(entity).despawn();
     }
@@ -53,9 +58,31 @@ pub(crate) fn reset_socket(
         peer_id: None,
         latency: None,
         smoothed_latency: None,
+        decode_failures: 0,
     };
 }
 
+/// Sends an event for each tracked payload acknowledged by the host
+pub(crate) fn read_delivery_receipts(
+    state: Res<RtcClientState>,
+    mut client: RtcClient<DeliveryReceipt>,
+    mut receipts: ResMut<Receipts>,
+    mut delivered: EventWriter<RtcDelivered>,
+) {
+    let acks = client.read();
+    let Some(host) = state.host_peer_id else {
+        return;
+    };
+    for ack in acks {
+        for id in ack.receipts {
+            let handle = MessageHandle::new(ack.protocol, id);
+            if receipts.deliver(host, handle) {
+                delivered.send(RtcDelivered(handle));
+            }
+        }
+    }
+}
+
 /// Reads and handles connection request events
 pub(crate) fn connection_request_handler(
     mut request_reader: EventReader<RtcClientRequestEvent>,
@@ -140,6 +167,117 @@ pub(crate) fn client_event_writer(
     }
 }
 
+/// Sends our protocol manifest to the host once connected
+pub(crate) fn send_handshake(
+    mut socket: ResMut<RtcSocket>,
+    mut events: EventReader<RtcClientEvent>,
+    encoding: Res<TransportEncoding>,
+    registry: Res<RtcProtocolRegistry>,
+    channels: Res<Channels>,
+) {
+    for ev in events.read() {
+        if let RtcClientEvent::ConnectedToHost(host) = ev {
+            let manifest = HandshakePayload::new(&encoding, &registry, &channels);
+            if socket
+                .channel_mut(RELIABLE_CHANNEL_INDEX)
+                .try_send(
+                    manifest
+                        .to_packet(&HANDSHAKE_ENCODING)
+                        .expect("protocol manifests always encode"),
+                    *host,
+                )
+                .is_err()
+            {
+                error!("failed to send protocol manifest to {host}");
+            }
+        }
+    }
+}
+
+/// Compares the protocol manifest sent by the host against our own
+pub(crate) fn read_handshake(
+    mut received: ResMut<ReceivedPackets>,
+    encoding: Res<TransportEncoding>,
+    registry: Res<RtcProtocolRegistry>,
+    channels: Res<Channels>,
+    mut event_wtr: EventWriter<RtcClientEvent>,
+    mut next_connection_state: ResMut<NextState<RtcClientStatus>>,
+    mut decode_errors: EventWriter<RtcDecodeError>,
+) {
+    let local = HandshakePayload::new(&encoding, &registry, &channels);
+    for ReceivedPacket { peer, packet, .. } in received.take(HandshakePayload::id()) {
+        let remote = match HandshakePayload::from_packet(&packet, &HANDSHAKE_ENCODING) {
+            Ok(remote) => remote,
+            Err(err) => {
+                warn!("Failed to decode protocol manifest from {peer}: {err}");
+                decode_errors.send(RtcDecodeError {
+                    peer,
+                    msg_id: Some(HandshakePayload::id()),
+                    protocol: Some(HandshakePayload::reflect_name().to_string()),
+                    len: packet.len(),
+                    reason: err.to_string(),
+                });
+                continue;
+            }
+        };
+        match local.check(&remote) {
+            Some(mismatch) => {
+                warn!("Protocol mismatch with host: {mismatch}");
+                next_connection_state.set(RtcClientStatus::Disconnected);
+                event_wtr.send(RtcClientEvent::ProtocolMismatch(mismatch.clone()));
+                event_wtr.send(RtcClientEvent::DisconnectedFromHost {
+                    reason: Some(format!("Protocol mismatch: {mismatch}")),
+                });
+            }
+            None => debug!("Protocol manifest accepted from host"),
+        }
+    }
+}
+
+/// Disconnects when the host asks us to
+pub(crate) fn read_hang_up(
+    mut received: ResMut<ReceivedPackets>,
+    mut event_wtr: EventWriter<RtcClientEvent>,
+    mut next_connection_state: ResMut<NextState<RtcClientStatus>>,
+    mut decode_errors: EventWriter<RtcDecodeError>,
+) {
+    for ReceivedPacket { peer, packet, .. } in received.take(HangUpPayload::id()) {
+        let hang_up = match HangUpPayload::from_packet(&packet, &HANDSHAKE_ENCODING) {
+            Ok(hang_up) => hang_up,
+            Err(err) => {
+                warn!("Failed to decode hang up from {peer}: {err}");
+                decode_errors.send(RtcDecodeError {
+                    peer,
+                    msg_id: Some(HangUpPayload::id()),
+                    protocol: Some(HangUpPayload::reflect_name().to_string()),
+                    len: packet.len(),
+                    reason: err.to_string(),
+                });
+                continue;
+            }
+        };
+        warn!("Host hung up: {}", hang_up.reason);
+        next_connection_state.set(RtcClientStatus::Disconnected);
+        event_wtr.send(RtcClientEvent::DisconnectedFromHost {
+            reason: Some(format!("Host hung up: {}", hang_up.reason)),
+        });
+    }
+}
+
+/// Disconnects when we fell too far behind sending to the host
+pub(crate) fn disconnect_when_too_slow(
+    mut outbox: ResMut<Outbox>,
+    mut event_wtr: EventWriter<RtcClientEvent>,
+    mut next_connection_state: ResMut<NextState<RtcClientStatus>>,
+) {
+    if !outbox.take_too_slow().is_empty() {
+        next_connection_state.set(RtcClientStatus::Disconnected);
+        event_wtr.send(RtcClientEvent::DisconnectedFromHost {
+            reason: Some("Send queue to host overflowed".to_string()),
+        });
+    }
+}
+
 pub fn send_latency_tracers(
     state: Res<RtcClientState>,
     mut client: RtcClient<LatencyTracerPayload>,
@@ -199,3 +337,51 @@ pub fn calculate_latency(
         }
     }
 }
+
+/// Counts packets from the server which failed to decode
+pub(crate) fn count_decode_failures(
+    mut decode_errors: EventReader<RtcDecodeError>,
+    mut state: ResMut<RtcClientState>,
+) {
+    state.decode_failures += decode_errors.read().count() as u64;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        outbox::tests::{flush, world},
+        Backpressure, BackpressurePolicy,
+    };
+    use bevy::ecs::system::RunSystemOnce;
+
+    #[test]
+    fn client_disconnects_when_too_slow() {
+        let mut world = world(Some(Backpressure {
+            max_queued_bytes: 100,
+            policy: BackpressurePolicy::Disconnect,
+            ..default()
+        }));
+        world.init_resource::<Events<RtcClientEvent>>();
+        world.init_resource::<NextState<RtcClientStatus>>();
+
+        flush(&mut world, 1, 100);
+        world.run_system_once(disconnect_when_too_slow);
+        assert!(world.resource::<Events<RtcClientEvent>>().is_empty());
+
+        flush(&mut world, 1, 100);
+        world.run_system_once(disconnect_when_too_slow);
+        let events = world
+            .resource_mut::<Events<RtcClientEvent>>()
+            .drain()
+            .collect::<Vec<_>>();
+        assert!(matches!(
+            &events[..],
+            [RtcClientEvent::DisconnectedFromHost { reason: Some(_) }]
+        ));
+        assert_eq!(
+            world.resource::<NextState<RtcClientStatus>>().0,
+            Some(RtcClientStatus::Disconnected)
+        );
+    }
+}
@@ -0,0 +1,253 @@
+use crate::bitpack;
+use bevy::reflect::erased_serde;
+use serde::de::DeserializeOwned;
+
+/// An error raised by a [`Codec`].
+pub type CodecError = Box<dyn std::error::Error + Send + Sync>;
+
+/// A serialization format for payloads on the wire.
+///
+/// The built-in encodings of [`TransportEncoding`](crate::TransportEncoding)
+/// are codecs, and any other format can be used by implementing this trait and
+/// installing it with [`TransportEncoding::custom`](crate::TransportEncoding::custom).
+///
+/// ```
+/// use bevy::reflect::erased_serde;
+/// use bevy_rtc::{
+///     codec::{Codec, CodecError},
+///     TransportEncoding,
+/// };
+///
+/// /// JSON, but pretty printed for reading network captures.
+/// struct PrettyJsonCodec;
+///
+/// impl Codec for PrettyJsonCodec {
+///     fn name(&self) -> &str {
+///         "PrettyJson"
+///     }
+///
+///     fn encode(
+///         &self,
+///         value: &dyn erased_serde::Serialize,
+///         buf: &mut Vec<u8>,
+///     ) -> Result<(), CodecError> {
+///         Ok(serde_json::to_writer_pretty(buf, value)?)
+///     }
+///
+///     fn decode(
+///         &self,
+///         bytes: &[u8],
+///         deserialize: &mut dyn FnMut(
+///             &mut dyn erased_serde::Deserializer<'_>,
+///         ) -> Result<(), erased_serde::Error>,
+///     ) -> Result<(), CodecError> {
+///         let mut de = serde_json::Deserializer::from_slice(bytes);
+///         deserialize(&mut <dyn erased_serde::Deserializer>::erase(&mut de))?;
+///         Ok(de.end()?)
+///     }
+/// }
+///
+/// let encoding = TransportEncoding::custom(PrettyJsonCodec);
+/// ```
+pub trait Codec: Send + Sync + 'static {
+    /// The name of this codec. Peers must use codecs with the same name, which
+    /// is checked when they connect.
+    fn name(&self) -> &str;
+
+    /// Serialize a value, appending it to `buf`.
+    fn encode(
+        &self,
+        value: &dyn erased_serde::Serialize,
+        buf: &mut Vec<u8>,
+    ) -> Result<(), CodecError>;
+
+    /// Create a deserializer over `bytes` and pass it to `deserialize`.
+    fn decode(
+        &self,
+        bytes: &[u8],
+        deserialize: &mut dyn FnMut(
+            &mut dyn erased_serde::Deserializer<'_>,
+        ) -> Result<(), erased_serde::Error>,
+    ) -> Result<(), CodecError>;
+}
+
+/// Decode a value of a concrete type with a codec.
+pub(crate) fn decode_with<T: DeserializeOwned>(
+    codec: &dyn Codec,
+    bytes: &[u8],
+) -> Result<T, CodecError> {
+    let mut value = None;
+    codec.decode(bytes, &mut |de| {
+        value.replace(erased_serde::deserialize::<T>(de)?);
+        Ok(())
+    })?;
+    value.ok_or_else(|| format!("`{}` codec did not deserialize a value", codec.name()).into())
+}
+
+/// JSON, see [`TransportEncoding::Json`](crate::TransportEncoding::Json).
+pub struct JsonCodec;
+
+impl Codec for JsonCodec {
+    fn name(&self) -> &str {
+        "Json"
+    }
+
+    fn encode(
+        &self,
+        value: &dyn erased_serde::Serialize,
+        buf: &mut Vec<u8>,
+    ) -> Result<(), CodecError> {
+        Ok(serde_json::to_writer(buf, value)?)
+    }
+
+    fn decode(
+        &self,
+        bytes: &[u8],
+        deserialize: &mut dyn FnMut(
+            &mut dyn erased_serde::Deserializer<'_>,
+        ) -> Result<(), erased_serde::Error>,
+    ) -> Result<(), CodecError> {
+        let mut de = serde_json::Deserializer::from_slice(bytes);
+        deserialize(&mut <dyn erased_serde::Deserializer>::erase(&mut de))?;
+        Ok(de.end()?)
+    }
+}
+
+/// Bit-packed binary, see [`TransportEncoding::BitPacked`](crate::TransportEncoding::BitPacked).
+pub struct BitPackedCodec;
+
+impl Codec for BitPackedCodec {
+    fn name(&self) -> &str {
+        "BitPacked"
+    }
+
+    fn encode(
+        &self,
+        value: &dyn erased_serde::Serialize,
+        buf: &mut Vec<u8>,
+    ) -> Result<(), CodecError> {
+        let mut ser = bitpack::Serializer::new(buf);
+        erased_serde::serialize(value, &mut ser)?;
+        Ok(())
+    }
+
+    fn decode(
+        &self,
+        bytes: &[u8],
+        deserialize: &mut dyn FnMut(
+            &mut dyn erased_serde::Deserializer<'_>,
+        ) -> Result<(), erased_serde::Error>,
+    ) -> Result<(), CodecError> {
+        let mut de = bitpack::Deserializer::new(bytes);
+        deserialize(&mut <dyn erased_serde::Deserializer>::erase(&mut de))?;
+        Ok(de.end()?)
+    }
+}
+
+/// Bincode, see [`TransportEncoding::Binary`](crate::TransportEncoding::Binary).
+#[cfg(feature = "binary")]
+#[cfg_attr(docsrs, doc(cfg(feature = "binary")))]
+pub struct BinaryCodec;
+
+#[cfg(feature = "binary")]
+impl Codec for BinaryCodec {
+    fn name(&self) -> &str {
+        "Binary"
+    }
+
+    fn encode(
+        &self,
+        value: &dyn erased_serde::Serialize,
+        buf: &mut Vec<u8>,
+    ) -> Result<(), CodecError> {
+        Ok(bincode::serialize_into(buf, value)?)
+    }
+
+    fn decode(
+        &self,
+        bytes: &[u8],
+        deserialize: &mut dyn FnMut(
+            &mut dyn erased_serde::Deserializer<'_>,
+        ) -> Result<(), erased_serde::Error>,
+    ) -> Result<(), CodecError> {
+        use bincode::Options;
+
+        // The same options as `bincode::deserialize`
+        let options = bincode::DefaultOptions::new()
+            .with_fixint_encoding()
+            .allow_trailing_bytes();
+        let mut de = bincode::Deserializer::from_slice(bytes, options);
+        Ok(deserialize(&mut <dyn erased_serde::Deserializer>::erase(
+            &mut de,
+        ))?)
+    }
+}
+
+/// MessagePack, see [`TransportEncoding::MessagePack`](crate::TransportEncoding::MessagePack).
+#[cfg(feature = "msgpack")]
+#[cfg_attr(docsrs, doc(cfg(feature = "msgpack")))]
+pub struct MessagePackCodec;
+
+#[cfg(feature = "msgpack")]
+impl Codec for MessagePackCodec {
+    fn name(&self) -> &str {
+        "MessagePack"
+    }
+
+    fn encode(
+        &self,
+        value: &dyn erased_serde::Serialize,
+        buf: &mut Vec<u8>,
+    ) -> Result<(), CodecError> {
+        // Named fields, so non-Rust tooling can read payloads as maps
+        Ok(rmp_serde::encode::write_named(buf, value)?)
+    }
+
+    fn decode(
+        &self,
+        bytes: &[u8],
+        deserialize: &mut dyn FnMut(
+            &mut dyn erased_serde::Deserializer<'_>,
+        ) -> Result<(), erased_serde::Error>,
+    ) -> Result<(), CodecError> {
+        let mut de = rmp_serde::Deserializer::new(bytes);
+        Ok(deserialize(&mut <dyn erased_serde::Deserializer>::erase(
+            &mut de,
+        ))?)
+    }
+}
+
+/// Postcard, see [`TransportEncoding::Postcard`](crate::TransportEncoding::Postcard).
+#[cfg(feature = "postcard")]
+#[cfg_attr(docsrs, doc(cfg(feature = "postcard")))]
+pub struct PostcardCodec;
+
+#[cfg(feature = "postcard")]
+impl Codec for PostcardCodec {
+    fn name(&self) -> &str {
+        "Postcard"
+    }
+
+    fn encode(
+        &self,
+        value: &dyn erased_serde::Serialize,
+        buf: &mut Vec<u8>,
+    ) -> Result<(), CodecError> {
+        // Variable length integers, so small values take fewer bytes
+        postcard::to_io(value, buf)?;
+        Ok(())
+    }
+
+    fn decode(
+        &self,
+        bytes: &[u8],
+        deserialize: &mut dyn FnMut(
+            &mut dyn erased_serde::Deserializer<'_>,
+        ) -> Result<(), erased_serde::Error>,
+    ) -> Result<(), CodecError> {
+        let mut de = postcard::Deserializer::from_bytes(bytes);
+        Ok(deserialize(&mut <dyn erased_serde::Deserializer>::erase(
+            &mut de,
+        ))?)
+    }
+}
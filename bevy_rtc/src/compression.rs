@@ -0,0 +1,177 @@
+use crate::protocol::RtcPacket;
+use bevy::prelude::*;
+use bevy_matchbox::matchbox_socket::Packet;
+
+/// The largest size a payload may claim to decompress to. Larger payloads are
+/// rejected, rather than trusting a peer with the size of an allocation.
+const MAX_DECOMPRESSED_SIZE: usize = 16 * 1024 * 1024;
+
+/// Compress large payloads with LZ4 before sending them.
+///
+/// Compressed packets are flagged in their header, so peers decompress them
+/// automatically whether or not they enable compression themselves. Payloads
+/// which do not shrink are sent uncompressed. Protocols can opt out with
+/// [`ProtocolOptions::skip_compression`](crate::protocol::ProtocolOptions::skip_compression).
+#[derive(Resource, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Compression {
+    /// Payloads larger than this many bytes are compressed.
+    pub threshold: usize,
+}
+
+impl Default for Compression {
+    fn default() -> Self {
+        Self { threshold: 1024 }
+    }
+}
+
+impl Compression {
+    /// Compress a packet if its payload is over the threshold.
+    pub(crate) fn compress(&self, packet: Packet) -> Packet {
+        let Some(header) = RtcPacket::parse(&packet) else {
+            return packet;
+        };
+        if header.data.len() <= self.threshold || header.flags & RtcPacket::COMPRESSED != 0 {
+            return packet;
+        }
+        let compressed = lz4_flex::compress_prepend_size(header.data);
+        if compressed.len() >= header.data.len() {
+            return packet;
+        }
+        let mut buf = Vec::with_capacity(RtcPacket::HEADER_SIZE + compressed.len());
+        RtcPacket::write_header(
+            header.msg_id,
+            header.flags | RtcPacket::COMPRESSED,
+            &mut buf,
+        );
+        buf.extend_from_slice(&compressed);
+        buf.into_boxed_slice()
+    }
+}
+
+/// Decompress a compressed packet, returning `None` if it is corrupt.
+pub(crate) fn decompress(packet: &RtcPacket) -> Option<Packet> {
+    if packet.data.len() < 4 {
+        return None;
+    }
+    let (size, data) = packet.data.split_at(4);
+    let size = u32::from_le_bytes([size[0], size[1], size[2], size[3]]) as usize;
+    if size > MAX_DECOMPRESSED_SIZE {
+        return None;
+    }
+    let decompressed = lz4_flex::decompress(data, size).ok()?;
+    let mut buf = Vec::with_capacity(RtcPacket::HEADER_SIZE + decompressed.len());
+    RtcPacket::write_header(
+        packet.msg_id,
+        packet.flags & !RtcPacket::COMPRESSED,
+        &mut buf,
+    );
+    buf.extend_from_slice(&decompressed);
+    Some(buf.into_boxed_slice())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn packet(flags: u8, data: &[u8]) -> Packet {
+        let mut buf = vec![];
+        RtcPacket::write_header(7, flags, &mut buf);
+        buf.extend_from_slice(data);
+        buf.into_boxed_slice()
+    }
+
+    #[test]
+    fn payloads_over_the_threshold_round_trip() {
+        let compression = Compression { threshold: 16 };
+        let original = packet(0, &[b'a'; 100]);
+
+        let compressed = compression.compress(original.clone());
+        let header = RtcPacket::parse(&compressed).unwrap();
+        assert!(compressed.len() < original.len());
+        assert_eq!(header.msg_id, 7);
+        assert_ne!(header.flags & RtcPacket::COMPRESSED, 0);
+        assert_eq!(decompress(&header), Some(original));
+    }
+
+    #[test]
+    fn other_flags_are_kept() {
+        let compression = Compression { threshold: 16 };
+        let original = packet(RtcPacket::SEQUENCED, &[b'a'; 100]);
+
+        let compressed = compression.compress(original.clone());
+        let header = RtcPacket::parse(&compressed).unwrap();
+        assert_eq!(header.flags, RtcPacket::SEQUENCED | RtcPacket::COMPRESSED);
+        assert_eq!(decompress(&header), Some(original));
+    }
+
+    #[test]
+    fn payloads_at_the_threshold_are_not_compressed() {
+        let compression = Compression { threshold: 100 };
+        let original = packet(0, &[b'a'; 100]);
+
+        assert_eq!(compression.compress(original.clone()), original);
+    }
+
+    #[test]
+    fn payloads_which_do_not_shrink_are_not_compressed() {
+        let compression = Compression { threshold: 0 };
+        let original = packet(0, b"abcdefgh");
+
+        assert_eq!(compression.compress(original.clone()), original);
+    }
+
+    #[test]
+    fn compressed_payloads_are_not_compressed_again() {
+        let compression = Compression { threshold: 0 };
+        let original = packet(RtcPacket::COMPRESSED, &[b'a'; 100]);
+
+        assert_eq!(compression.compress(original.clone()), original);
+    }
+
+    #[test]
+    fn corrupt_payloads_are_rejected() {
+        let compression = Compression { threshold: 16 };
+        let compressed = compression.compress(packet(0, &[b'a'; 100]));
+        let truncated = &compressed[..compressed.len() - 2];
+        let no_size = packet(RtcPacket::COMPRESSED, &[1, 2]);
+
+        assert_eq!(decompress(&RtcPacket::parse(truncated).unwrap()), None);
+        assert_eq!(decompress(&RtcPacket::parse(&no_size).unwrap()), None);
+    }
+
+    #[test]
+    fn oversized_payloads_are_rejected() {
+        let mut data = (MAX_DECOMPRESSED_SIZE as u32 + 1).to_le_bytes().to_vec();
+        data.extend_from_slice(&[0; 16]);
+        let bomb = packet(RtcPacket::COMPRESSED, &data);
+
+        assert_eq!(decompress(&RtcPacket::parse(&bomb).unwrap()), None);
+    }
+
+    #[cfg(feature = "server")]
+    #[test]
+    fn protocols_can_skip_compression() {
+        use crate::{prelude::*, registry::RtcProtocolRegistry};
+        use serde::{Deserialize, Serialize};
+
+        #[derive(Protocol, Serialize, Deserialize, Debug, Clone)]
+        #[protocol(crate = "crate", id = 1)]
+        struct Compressed;
+
+        #[derive(Protocol, Serialize, Deserialize, Debug, Clone)]
+        #[protocol(crate = "crate", id = 2)]
+        struct Incompressible;
+
+        let mut app = App::new();
+        app.configure_server_protocol::<Incompressible>(ProtocolOptions {
+            skip_compression: true,
+            ..default()
+        })
+        .add_server_wo_protocol::<Compressed>()
+        .add_server_wo_protocol::<Incompressible>();
+
+        let registry = app.world.resource::<RtcProtocolRegistry>();
+        assert!(!registry.skip_compression(Compressed::id()));
+        assert!(registry.skip_compression(Incompressible::id()));
+    }
+}
@@ -0,0 +1,334 @@
+use crate::{
+    batching, compression,
+    events::{RtcDecodeError, SocketRecvEvent},
+    fragmentation::{Fragmentation, FragmentationStats, Reassembly},
+    protocol::RtcPacket,
+    receipt,
+    registry::RtcProtocolRegistry,
+    sequence,
+};
+use bevy::{
+    prelude::*,
+    utils::{HashMap, HashSet},
+};
+use bevy_matchbox::matchbox_socket::{Packet, PeerId};
+
+/// A packet routed to its protocol.
+#[derive(Debug)]
+pub(crate) struct ReceivedPacket {
+    pub peer: PeerId,
+    /// The sequence number, if sequenced.
+    pub seq: Option<u16>,
+    /// The receipt ID to acknowledge once read, if tracked.
+    pub receipt: Option<u32>,
+    pub packet: Packet,
+}
+
+/// Packets received this frame, routed by protocol ID. Each protocol takes its
+/// packets out to decode them, so payloads are only decoded by one protocol.
+#[derive(Resource, Default, Debug)]
+pub(crate) struct ReceivedPackets {
+    by_id: HashMap<u16, Vec<ReceivedPacket>>,
+    /// Peers the server hung up on, whose packets are no longer routed.
+    refused: HashSet<PeerId>,
+}
+
+impl ReceivedPackets {
+    /// Take all packets received this frame for a protocol.
+    pub fn take(&mut self, msg_id: u16) -> Vec<ReceivedPacket> {
+        self.by_id.remove(&msg_id).unwrap_or_default()
+    }
+
+    /// Stop routing packets from a peer.
+    #[cfg(feature = "server")]
+    pub fn refuse(&mut self, peer: PeerId) {
+        self.refused.insert(peer);
+        for packets in self.by_id.values_mut() {
+            packets.retain(|packet| packet.peer != peer);
+        }
+    }
+
+    /// Forget a disconnected peer.
+    #[cfg(feature = "server")]
+    pub fn forget(&mut self, peer: PeerId) {
+        self.refused.remove(&peer);
+    }
+}
+
+/// Reads the header of each received packet and routes it to its protocol,
+/// unpacking batches, reassembling fragments, reading sequence numbers and
+/// receipt IDs, and decompressing payloads as needed.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn dispatch_packets(
+    mut events: EventReader<SocketRecvEvent>,
+    registry: Res<RtcProtocolRegistry>,
+    time: Res<Time<Real>>,
+    fragmentation: Option<Res<Fragmentation>>,
+    mut received: ResMut<ReceivedPackets>,
+    mut reassembly: ResMut<Reassembly>,
+    mut stats: ResMut<FragmentationStats>,
+    mut decode_errors: EventWriter<RtcDecodeError>,
+) {
+    // Packets not taken last frame have no reader
+    received.by_id.clear();
+    let fragmentation = fragmentation.map_or_else(Fragmentation::default, |f| *f);
+
+    for SocketRecvEvent((peer, packet)) in events.read() {
+        if received.refused.contains(peer) {
+            continue;
+        }
+        match RtcPacket::parse(packet) {
+            Some(header) if header.flags & RtcPacket::BATCHED != 0 => {
+                let Some(packets) = batching::unbatch(header.data) else {
+                    reject(
+                        &mut decode_errors,
+                        &registry,
+                        *peer,
+                        None,
+                        packet,
+                        "corrupt batch",
+                    );
+                    continue;
+                };
+                for packet in packets {
+                    route(*peer, packet, &registry, &mut received, &mut decode_errors);
+                }
+            }
+            Some(header) if header.flags & RtcPacket::FRAGMENT != 0 => {
+                match reassembly.insert(*peer, &header, time.elapsed(), &fragmentation, &mut stats)
+                {
+                    Ok(Some(packet)) => {
+                        route(*peer, &packet, &registry, &mut received, &mut decode_errors)
+                    }
+                    Ok(None) => {}
+                    Err(reason) => reject(
+                        &mut decode_errors,
+                        &registry,
+                        *peer,
+                        Some(header.msg_id),
+                        packet,
+                        reason,
+                    ),
+                }
+            }
+            _ => route(*peer, packet, &registry, &mut received, &mut decode_errors),
+        }
+    }
+}
+
+/// Route a single packet to its protocol.
+fn route(
+    peer: PeerId,
+    packet: &[u8],
+    registry: &RtcProtocolRegistry,
+    received: &mut ReceivedPackets,
+    decode_errors: &mut EventWriter<RtcDecodeError>,
+) {
+    let Some(header) = RtcPacket::parse(packet) else {
+        reject(
+            decode_errors,
+            registry,
+            peer,
+            None,
+            packet,
+            "packet is shorter than its header",
+        );
+        return;
+    };
+    let msg_id = header.msg_id;
+    if header.flags & RtcPacket::BATCHED != 0 {
+        reject(decode_errors, registry, peer, None, packet, "nested batch");
+        return;
+    }
+    if header.flags & RtcPacket::FRAGMENT != 0 {
+        reject(
+            decode_errors,
+            registry,
+            peer,
+            None,
+            packet,
+            "nested fragment",
+        );
+        return;
+    }
+    if !registry.is_claimed(msg_id) {
+        reject(
+            decode_errors,
+            registry,
+            peer,
+            Some(msg_id),
+            packet,
+            "unknown protocol id",
+        );
+        return;
+    }
+    let (seq, packet) = if header.flags & RtcPacket::SEQUENCED != 0 {
+        let Some((seq, packet)) = sequence::unstamp(&header) else {
+            reject(
+                decode_errors,
+                registry,
+                peer,
+                Some(msg_id),
+                packet,
+                "missing sequence number",
+            );
+            return;
+        };
+        (Some(seq), packet)
+    } else {
+        (None, Packet::from(packet))
+    };
+    let (receipt, packet) = match RtcPacket::parse(&packet) {
+        Some(header) if header.flags & RtcPacket::TRACKED != 0 => {
+            let Some((receipt, packet)) = receipt::unstamp(&header) else {
+                reject(
+                    decode_errors,
+                    registry,
+                    peer,
+                    Some(msg_id),
+                    &packet,
+                    "missing receipt id",
+                );
+                return;
+            };
+            (Some(receipt), packet)
+        }
+        _ => (None, packet),
+    };
+    let packet = match RtcPacket::parse(&packet) {
+        Some(header) if header.flags & RtcPacket::COMPRESSED != 0 => {
+            let Some(decompressed) = compression::decompress(&header) else {
+                reject(
+                    decode_errors,
+                    registry,
+                    peer,
+                    Some(msg_id),
+                    &packet,
+                    "corrupt compressed payload",
+                );
+                return;
+            };
+            decompressed
+        }
+        _ => packet,
+    };
+    received
+        .by_id
+        .entry(msg_id)
+        .or_default()
+        .push(ReceivedPacket {
+            peer,
+            seq,
+            receipt,
+            packet,
+        });
+}
+
+/// Drop a packet which could not be routed.
+fn reject(
+    decode_errors: &mut EventWriter<RtcDecodeError>,
+    registry: &RtcProtocolRegistry,
+    peer: PeerId,
+    msg_id: Option<u16>,
+    packet: &[u8],
+    reason: &str,
+) {
+    warn!(
+        "Dropped packet from {peer} ({} bytes): {reason}",
+        packet.len()
+    );
+    decode_errors.send(RtcDecodeError {
+        peer,
+        msg_id,
+        protocol: msg_id.and_then(|id| registry.name(id)).map(str::to_string),
+        len: packet.len(),
+        reason: reason.to_string(),
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{batching::Batch, handshake::HandshakePayload, protocol::Protocol};
+    use bevy::{ecs::system::RunSystemOnce, utils::Uuid};
+
+    const PEER: PeerId = PeerId(Uuid::from_u128(1));
+
+    fn world() -> World {
+        let mut world = World::new();
+        world.init_resource::<Events<SocketRecvEvent>>();
+        world.init_resource::<Events<RtcDecodeError>>();
+        world.init_resource::<RtcProtocolRegistry>();
+        world.init_resource::<Time<Real>>();
+        world.init_resource::<ReceivedPackets>();
+        world.init_resource::<Reassembly>();
+        world.init_resource::<FragmentationStats>();
+        world
+    }
+
+    /// Dispatch datagrams, returning the packets routed to the handshake
+    /// protocol and the reasons of any rejected datagrams.
+    fn dispatch(world: &mut World, datagrams: Vec<Packet>) -> (Vec<Packet>, Vec<String>) {
+        world.send_event_batch(datagrams.into_iter().map(|d| SocketRecvEvent((PEER, d))));
+        world.run_system_once(dispatch_packets);
+        let routed = world
+            .resource_mut::<ReceivedPackets>()
+            .take(HandshakePayload::id())
+            .into_iter()
+            .map(|received| received.packet)
+            .collect();
+        let rejected = world
+            .resource_mut::<Events<RtcDecodeError>>()
+            .drain()
+            .map(|error| error.reason)
+            .collect();
+        (routed, rejected)
+    }
+
+    fn packet(flags: u8, data: &[u8]) -> Packet {
+        let mut buf = vec![];
+        RtcPacket::write_header(HandshakePayload::id(), flags, &mut buf);
+        buf.extend_from_slice(data);
+        buf.into_boxed_slice()
+    }
+
+    fn batch(packets: Vec<Packet>) -> Packet {
+        let mut batch = Batch::new(1200);
+        for packet in packets {
+            batch.push("handshake", packet);
+        }
+        batch.take().unwrap().1
+    }
+
+    #[test]
+    fn batched_packets_are_routed_in_order() {
+        let mut world = world();
+        let packets = vec![packet(0, b"one"), packet(0, b"two")];
+
+        let (routed, rejected) = dispatch(&mut world, vec![batch(packets.clone())]);
+        assert_eq!(routed, packets);
+        assert!(rejected.is_empty());
+    }
+
+    #[test]
+    fn nested_batches_are_rejected() {
+        let mut world = world();
+        let inner = batch(vec![packet(0, b"one"), packet(0, b"two")]);
+        let outer = batch(vec![inner, packet(0, b"three")]);
+
+        let (routed, rejected) = dispatch(&mut world, vec![outer]);
+        assert_eq!(routed, vec![packet(0, b"three")]);
+        assert_eq!(rejected, vec!["nested batch".to_string()]);
+    }
+
+    #[test]
+    fn truncated_batches_are_rejected() {
+        let mut world = world();
+        let mut truncated = batch(vec![packet(0, b"one"), packet(0, b"two")]).into_vec();
+        truncated.pop();
+
+        let (routed, rejected) = dispatch(&mut world, vec![truncated.into_boxed_slice()]);
+        assert!(routed.is_empty());
+        assert_eq!(rejected, vec!["corrupt batch".to_string()]);
+    }
+}
@@ -1,6 +1,44 @@
+use crate::receipt::MessageHandle;
 use bevy::prelude::Event;
 use bevy_matchbox::matchbox_socket::{Packet, PeerId};
 
 /// The raw event to receive from a socket
 #[derive(Debug, Clone, Event)]
 pub struct SocketRecvEvent(pub (PeerId, Packet));
+
+/// A payload which could not be sent to a peer, e.g. because it failed to
+/// encode. Other payloads and peers are unaffected.
+#[derive(Debug, Clone, Event)]
+pub struct RtcSendError {
+    /// The peer the payload was sent to
+    pub peer: PeerId,
+    /// The protocol name, see [`Protocol::reflect_name`](crate::protocol::Protocol::reflect_name)
+    pub protocol: &'static str,
+    /// Why the payload was not sent
+    pub reason: String,
+}
+
+/// A packet received from a peer which could not be decoded, and was dropped.
+#[derive(Debug, Clone, Event)]
+pub struct RtcDecodeError {
+    /// The peer the packet was received from
+    pub peer: PeerId,
+    /// The protocol ID, if the header could be read
+    pub msg_id: Option<u16>,
+    /// The protocol name, if the protocol is registered
+    pub protocol: Option<String>,
+    /// The length of the packet in bytes
+    pub len: usize,
+    /// Why the packet could not be decoded
+    pub reason: String,
+}
+
+/// A tracked payload was read by the peer it was sent to.
+#[derive(Debug, Clone, Copy, Event)]
+pub struct RtcDelivered(pub MessageHandle);
+
+/// A tracked payload was not read by the peer it was sent to before the
+/// delivery timeout of the plugin, or it could not be encoded and was never
+/// sent.
+#[derive(Debug, Clone, Copy, Event)]
+pub struct RtcDeliveryTimeout(pub MessageHandle);
@@ -0,0 +1,508 @@
+use crate::protocol::RtcPacket;
+use bevy::{prelude::*, utils::HashMap};
+use bevy_matchbox::matchbox_socket::{Packet, PeerId};
+use instant::Duration;
+
+/// The size of the fragment header after the packet header: the message
+/// number, the index of the fragment and the number of fragments, each as a
+/// little-endian `u16`.
+const FRAGMENT_HEADER_SIZE: usize = 6;
+
+/// The largest message a peer may send in fragments. Larger messages are
+/// rejected, rather than buffering whatever a peer sends.
+const MAX_MESSAGE_SIZE: usize = 16 * 1024 * 1024;
+
+/// The most incomplete messages buffered per peer. When a peer has more, its
+/// oldest incomplete message is discarded.
+const MAX_PENDING_PER_PEER: usize = 64;
+
+/// The most bytes of incomplete messages buffered per peer.
+const MAX_BUFFERED_PER_PEER: usize = 2 * MAX_MESSAGE_SIZE;
+
+/// Split large packets on the unreliable channel into fragments, which are
+/// reassembled by the receiving peer.
+///
+/// Fragments are flagged in their header and reassembled automatically by
+/// peers, whether or not they enable fragmentation themselves. If any fragment
+/// is lost, the whole message is discarded after the timeout. Reliable packets
+/// are never fragmented.
+///
+/// Receivers reject messages split into more fragments than their own
+/// `max_size` would need for the largest message, so peers should agree on it.
+#[derive(Resource, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Fragmentation {
+    /// Packets larger than this many bytes are sent in fragments of at most
+    /// this many bytes.
+    pub max_size: usize,
+    /// How long to wait for the rest of a message after its first fragment
+    /// arrives, before discarding it.
+    pub timeout: Duration,
+}
+
+impl Default for Fragmentation {
+    fn default() -> Self {
+        Self {
+            max_size: 1200,
+            timeout: Duration::from_secs(1),
+        }
+    }
+}
+
+/// Counters of fragmented messages sent and received.
+#[derive(Resource, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct FragmentationStats {
+    /// Messages which were sent in fragments.
+    pub messages_fragmented: u64,
+    /// Fragments sent.
+    pub fragments_sent: u64,
+    /// Fragments received.
+    pub fragments_received: u64,
+    /// Messages which were reassembled from all of their fragments.
+    pub messages_reassembled: u64,
+    /// Incomplete messages which were discarded, because a fragment was lost
+    /// or arrived too late.
+    pub messages_expired: u64,
+    /// Incomplete messages waiting for more fragments.
+    pub messages_pending: usize,
+}
+
+impl Fragmentation {
+    /// The most bytes of a packet carried by each fragment.
+    fn chunk_size(&self) -> usize {
+        self.max_size
+            .saturating_sub(RtcPacket::HEADER_SIZE + FRAGMENT_HEADER_SIZE)
+            .max(1)
+    }
+
+    /// The most fragments accepted for a single message.
+    pub(crate) fn max_fragments(&self) -> usize {
+        MAX_MESSAGE_SIZE
+            .div_ceil(self.chunk_size())
+            .min(u16::MAX as usize)
+    }
+
+    /// Split a packet into fragments, returning `None` if it needs more than
+    /// `u16::MAX` fragments. The packet header is kept on every fragment so
+    /// failures can be attributed to a protocol.
+    pub(crate) fn fragment(&self, message: u16, packet: &[u8]) -> Option<Vec<Packet>> {
+        let header = RtcPacket::parse(packet)?;
+        let chunk_size = self.chunk_size();
+        let count = u16::try_from(packet.len().div_ceil(chunk_size)).ok()?;
+        let fragments = packet
+            .chunks(chunk_size)
+            .enumerate()
+            .map(|(index, chunk)| {
+                let mut buf =
+                    Vec::with_capacity(RtcPacket::HEADER_SIZE + FRAGMENT_HEADER_SIZE + chunk.len());
+                RtcPacket::write_header(header.msg_id, RtcPacket::FRAGMENT, &mut buf);
+                buf.extend_from_slice(&message.to_le_bytes());
+                buf.extend_from_slice(&(index as u16).to_le_bytes());
+                buf.extend_from_slice(&count.to_le_bytes());
+                buf.extend_from_slice(chunk);
+                buf.into_boxed_slice()
+            })
+            .collect();
+        Some(fragments)
+    }
+}
+
+/// A message missing some of its fragments.
+#[derive(Debug)]
+struct Partial {
+    fragments: Vec<Option<Box<[u8]>>>,
+    received: usize,
+    len: usize,
+    started: Duration,
+}
+
+/// Fragments received from each peer, waiting for the rest of their message.
+#[derive(Resource, Default, Debug)]
+pub(crate) struct Reassembly {
+    pending: HashMap<(PeerId, u16), Partial>,
+}
+
+impl Reassembly {
+    /// Add a fragment received at `now`, returning the packet it was split
+    /// from once every fragment has arrived.
+    pub fn insert(
+        &mut self,
+        peer: PeerId,
+        fragment: &RtcPacket,
+        now: Duration,
+        fragmentation: &Fragmentation,
+        stats: &mut FragmentationStats,
+    ) -> Result<Option<Packet>, &'static str> {
+        let data = fragment.data;
+        if data.len() < FRAGMENT_HEADER_SIZE {
+            return Err("fragment is shorter than its header");
+        }
+        let (header, chunk) = data.split_at(FRAGMENT_HEADER_SIZE);
+        let message = u16::from_le_bytes([header[0], header[1]]);
+        let index = u16::from_le_bytes([header[2], header[3]]) as usize;
+        let count = u16::from_le_bytes([header[4], header[5]]) as usize;
+        if index >= count {
+            return Err("fragment index out of range");
+        }
+        if count > fragmentation.max_fragments() {
+            return Err("too many fragments");
+        }
+        stats.fragments_received += 1;
+
+        if !self.pending.contains_key(&(peer, message)) {
+            self.evict_oldest(peer, stats);
+        }
+        let partial = self
+            .pending
+            .entry((peer, message))
+            .or_insert_with(|| Partial {
+                fragments: vec![None; count],
+                received: 0,
+                len: 0,
+                started: now,
+            });
+        if partial.fragments.len() != count {
+            self.pending.remove(&(peer, message));
+            return Err("fragment count changed within a message");
+        }
+        if partial.fragments[index].is_some() {
+            return Err("duplicate fragment");
+        }
+        partial.len += chunk.len();
+        if partial.len > MAX_MESSAGE_SIZE {
+            self.pending.remove(&(peer, message));
+            return Err("fragmented message is too large");
+        }
+        partial.fragments[index] = Some(chunk.into());
+        if self.buffered(peer) > MAX_BUFFERED_PER_PEER {
+            self.pending.remove(&(peer, message));
+            return Err("too many fragments buffered for peer");
+        }
+        let partial = self
+            .pending
+            .get_mut(&(peer, message))
+            .expect("message is pending");
+        partial.received += 1;
+        if partial.received < count {
+            return Ok(None);
+        }
+
+        let partial = self
+            .pending
+            .remove(&(peer, message))
+            .expect("message is pending");
+        let mut buf = Vec::with_capacity(partial.len);
+        for chunk in partial.fragments.into_iter().flatten() {
+            buf.extend_from_slice(&chunk);
+        }
+        stats.messages_reassembled += 1;
+        Ok(Some(buf.into_boxed_slice()))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    /// Discard incomplete messages which started before `deadline`.
+    pub fn expire(&mut self, deadline: Duration, stats: &mut FragmentationStats) {
+        let before = self.pending.len();
+        self.pending
+            .retain(|_, partial| partial.started >= deadline);
+        stats.messages_expired += (before - self.pending.len()) as u64;
+        stats.messages_pending = self.pending.len();
+    }
+
+    /// The bytes of incomplete messages buffered for a peer.
+    fn buffered(&self, peer: PeerId) -> usize {
+        self.pending
+            .iter()
+            .filter(|((p, _), _)| *p == peer)
+            .map(|(_, partial)| partial.len)
+            .sum()
+    }
+
+    /// Make room for another incomplete message from a peer.
+    fn evict_oldest(&mut self, peer: PeerId, stats: &mut FragmentationStats) {
+        let from_peer = self
+            .pending
+            .iter()
+            .filter(|((p, _), _)| *p == peer)
+            .map(|(key, partial)| (*key, partial.started))
+            .collect::<Vec<_>>();
+        if from_peer.len() < MAX_PENDING_PER_PEER {
+            return;
+        }
+        if let Some((key, _)) = from_peer.into_iter().min_by_key(|(_, started)| *started) {
+            self.pending.remove(&key);
+            stats.messages_expired += 1;
+        }
+    }
+}
+
+/// Discards incomplete messages which have waited longer than the timeout.
+pub(crate) fn expire_fragments(
+    time: Res<Time<Real>>,
+    fragmentation: Option<Res<Fragmentation>>,
+    mut reassembly: ResMut<Reassembly>,
+    mut stats: ResMut<FragmentationStats>,
+) {
+    if reassembly.is_empty() && stats.messages_pending == 0 {
+        return;
+    }
+    let timeout = fragmentation.map_or(Fragmentation::default().timeout, |f| f.timeout);
+    let deadline = time.elapsed().saturating_sub(timeout);
+    reassembly.expire(deadline, &mut stats);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::{ecs::system::RunSystemOnce, utils::Uuid};
+
+    const PEER: PeerId = PeerId(Uuid::from_u128(1));
+
+    fn fragmentation() -> Fragmentation {
+        Fragmentation {
+            max_size: 16,
+            ..default()
+        }
+    }
+
+    fn packet(len: usize) -> Packet {
+        let mut buf = vec![];
+        RtcPacket::write_header(7, 0, &mut buf);
+        buf.extend((0..len).map(|i| i as u8));
+        buf.into_boxed_slice()
+    }
+
+    /// A fragment with a hand-written fragment header.
+    fn fragment(message: u16, index: u16, count: u16, chunk: &[u8]) -> Packet {
+        let mut buf = vec![];
+        RtcPacket::write_header(7, RtcPacket::FRAGMENT, &mut buf);
+        buf.extend_from_slice(&message.to_le_bytes());
+        buf.extend_from_slice(&index.to_le_bytes());
+        buf.extend_from_slice(&count.to_le_bytes());
+        buf.extend_from_slice(chunk);
+        buf.into_boxed_slice()
+    }
+
+    fn insert(
+        reassembly: &mut Reassembly,
+        fragment: &[u8],
+        now: Duration,
+        stats: &mut FragmentationStats,
+    ) -> Result<Option<Packet>, &'static str> {
+        let header = RtcPacket::parse(fragment).unwrap();
+        reassembly.insert(PEER, &header, now, &fragmentation(), stats)
+    }
+
+    #[test]
+    fn fragments_fit_max_size() {
+        let fragments = fragmentation().fragment(0, &packet(40)).unwrap();
+        assert_eq!(fragments.len(), 7);
+        assert!(fragments.iter().all(|fragment| fragment.len() <= 16));
+        let header = RtcPacket::parse(&fragments[0]).unwrap();
+        assert_eq!(header.msg_id, 7);
+        assert_eq!(header.flags, RtcPacket::FRAGMENT);
+    }
+
+    #[test]
+    fn fragments_are_reassembled() {
+        let packet = packet(40);
+        let fragments = fragmentation().fragment(0, &packet).unwrap();
+        let mut reassembly = Reassembly::default();
+        let mut stats = FragmentationStats::default();
+
+        let (last, rest) = fragments.split_last().unwrap();
+        for fragment in rest {
+            let result = insert(&mut reassembly, fragment, Duration::ZERO, &mut stats);
+            assert_eq!(result, Ok(None));
+        }
+        let result = insert(&mut reassembly, last, Duration::ZERO, &mut stats);
+        assert_eq!(result, Ok(Some(packet)));
+        assert!(reassembly.is_empty());
+        assert_eq!(stats.fragments_received, fragments.len() as u64);
+        assert_eq!(stats.messages_reassembled, 1);
+    }
+
+    #[test]
+    fn fragments_are_reassembled_out_of_order() {
+        let packet = packet(40);
+        let mut fragments = fragmentation().fragment(0, &packet).unwrap();
+        fragments.reverse();
+        fragments.swap(1, 3);
+        let mut reassembly = Reassembly::default();
+        let mut stats = FragmentationStats::default();
+
+        let results = fragments
+            .iter()
+            .map(|fragment| insert(&mut reassembly, fragment, Duration::ZERO, &mut stats))
+            .collect::<Vec<_>>();
+        assert!(results[..results.len() - 1].iter().all(|r| *r == Ok(None)));
+        assert_eq!(results.last().unwrap(), &Ok(Some(packet)));
+    }
+
+    #[test]
+    fn index_out_of_range_is_rejected() {
+        let mut reassembly = Reassembly::default();
+        let mut stats = FragmentationStats::default();
+
+        let result = insert(
+            &mut reassembly,
+            &fragment(0, 2, 2, b"abc"),
+            Duration::ZERO,
+            &mut stats,
+        );
+        assert_eq!(result, Err("fragment index out of range"));
+        assert!(reassembly.is_empty());
+    }
+
+    #[test]
+    fn changed_count_is_rejected() {
+        let mut reassembly = Reassembly::default();
+        let mut stats = FragmentationStats::default();
+
+        let first = insert(
+            &mut reassembly,
+            &fragment(0, 0, 3, b"abc"),
+            Duration::ZERO,
+            &mut stats,
+        );
+        assert_eq!(first, Ok(None));
+        let second = insert(
+            &mut reassembly,
+            &fragment(0, 1, 2, b"def"),
+            Duration::ZERO,
+            &mut stats,
+        );
+        assert_eq!(second, Err("fragment count changed within a message"));
+        assert!(reassembly.is_empty());
+    }
+
+    #[test]
+    fn duplicate_fragment_is_rejected() {
+        let mut reassembly = Reassembly::default();
+        let mut stats = FragmentationStats::default();
+        let fragment = fragment(0, 0, 2, b"abc");
+
+        let first = insert(&mut reassembly, &fragment, Duration::ZERO, &mut stats);
+        assert_eq!(first, Ok(None));
+        let second = insert(&mut reassembly, &fragment, Duration::ZERO, &mut stats);
+        assert_eq!(second, Err("duplicate fragment"));
+    }
+
+    #[test]
+    fn too_many_fragments_are_rejected() {
+        let fragmentation = Fragmentation::default();
+        let count = fragmentation.max_fragments() as u16 + 1;
+        let fragment = fragment(0, 0, count, b"abc");
+        let header = RtcPacket::parse(&fragment).unwrap();
+        let mut reassembly = Reassembly::default();
+        let mut stats = FragmentationStats::default();
+
+        let result = reassembly.insert(PEER, &header, Duration::ZERO, &fragmentation, &mut stats);
+        assert_eq!(result, Err("too many fragments"));
+        assert!(reassembly.is_empty());
+    }
+
+    #[test]
+    fn buffered_bytes_are_capped_per_peer() {
+        let fragmentation = Fragmentation {
+            max_size: 64 * 1024,
+            ..default()
+        };
+        let chunk = vec![0; fragmentation.chunk_size()];
+        let count = fragmentation.max_fragments() as u16;
+        let mut reassembly = Reassembly::default();
+        let mut stats = FragmentationStats::default();
+
+        // Two messages missing only their last fragment fill the buffer
+        let mut results = vec![];
+        for message in 0..3 {
+            for index in 0..count - 1 {
+                let fragment = fragment(message, index, count, &chunk);
+                let header = RtcPacket::parse(&fragment).unwrap();
+                let result =
+                    reassembly.insert(PEER, &header, Duration::ZERO, &fragmentation, &mut stats);
+                if result.is_err() {
+                    results.push((message, result));
+                    break;
+                }
+            }
+        }
+        assert_eq!(
+            results,
+            vec![(2, Err("too many fragments buffered for peer"))]
+        );
+        assert!(reassembly.buffered(PEER) <= MAX_BUFFERED_PER_PEER);
+    }
+
+    #[test]
+    fn oldest_message_is_evicted() {
+        let mut reassembly = Reassembly::default();
+        let mut stats = FragmentationStats::default();
+
+        for message in 0..=MAX_PENDING_PER_PEER as u16 {
+            let now = Duration::from_millis(message as u64);
+            let result = insert(
+                &mut reassembly,
+                &fragment(message, 0, 2, b"abc"),
+                now,
+                &mut stats,
+            );
+            assert_eq!(result, Ok(None));
+        }
+        assert_eq!(reassembly.pending.len(), MAX_PENDING_PER_PEER);
+        assert!(!reassembly.pending.contains_key(&(PEER, 0)));
+        assert!(reassembly.pending.contains_key(&(PEER, 1)));
+        assert_eq!(stats.messages_expired, 1);
+
+        // Other peers have their own allowance
+        let other = PeerId(Uuid::from_u128(2));
+        let fragment = fragment(0, 0, 2, b"abc");
+        let header = RtcPacket::parse(&fragment).unwrap();
+        let result =
+            reassembly.insert(other, &header, Duration::ZERO, &fragmentation(), &mut stats);
+        assert_eq!(result, Ok(None));
+        assert_eq!(reassembly.pending.len(), MAX_PENDING_PER_PEER + 1);
+    }
+
+    #[test]
+    fn incomplete_messages_expire_after_timeout() {
+        let mut world = World::new();
+        world.insert_resource(Fragmentation {
+            timeout: Duration::from_secs(1),
+            ..fragmentation()
+        });
+        world.init_resource::<Time<Real>>();
+        world.init_resource::<FragmentationStats>();
+        // The first update starts the clock without advancing it
+        world
+            .resource_mut::<Time<Real>>()
+            .update_with_duration(Duration::ZERO);
+        let mut reassembly = Reassembly::default();
+        let mut stats = FragmentationStats::default();
+        insert(
+            &mut reassembly,
+            &fragment(0, 0, 2, b"abc"),
+            Duration::ZERO,
+            &mut stats,
+        )
+        .unwrap();
+        world.insert_resource(reassembly);
+
+        world
+            .resource_mut::<Time<Real>>()
+            .update_with_duration(Duration::from_millis(500));
+        world.run_system_once(expire_fragments);
+        assert!(!world.resource::<Reassembly>().is_empty());
+
+        world
+            .resource_mut::<Time<Real>>()
+            .update_with_duration(Duration::from_millis(600));
+        world.run_system_once(expire_fragments);
+        assert!(world.resource::<Reassembly>().is_empty());
+        let stats = world.resource::<FragmentationStats>();
+        assert_eq!(stats.messages_expired, 1);
+        assert_eq!(stats.messages_pending, 0);
+    }
+}
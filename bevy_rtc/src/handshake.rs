@@ -0,0 +1,318 @@
+use crate::{
+    channel::{ChannelConfig, Channels},
+    protocol::Protocol,
+    registry::{ProtocolEntry, RtcProtocolRegistry},
+    transport_encoding::TransportEncoding,
+};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// The encoding used for handshakes, regardless of the transport encoding.
+/// This must never change, or mismatched peers could not be told apart from
+/// broken ones.
+pub(crate) const HANDSHAKE_ENCODING: TransportEncoding = TransportEncoding::Json;
+
+/// A manifest of the protocols, encoding and channels a peer has registered,
+/// exchanged once a peer connects.
+#[derive(Protocol, Serialize, Deserialize, Debug, Clone)]
+#[protocol(crate = "crate")]
+pub struct HandshakePayload {
+    pub encoding: String,
+    pub protocols: Vec<ProtocolEntry>,
+    pub channels: Vec<ChannelConfig>,
+}
+
+impl HandshakePayload {
+    pub fn new(
+        encoding: &TransportEncoding,
+        registry: &RtcProtocolRegistry,
+        channels: &Channels,
+    ) -> Self {
+        Self {
+            encoding: encoding.to_string(),
+            protocols: registry.manifest(),
+            channels: channels.configs().to_vec(),
+        }
+    }
+
+    /// Compare our manifest against one sent by a remote peer, returning the
+    /// first incompatibility found.
+    pub fn check(&self, remote: &Self) -> Option<ProtocolMismatch> {
+        if self.encoding != remote.encoding {
+            return Some(ProtocolMismatch::Encoding {
+                local: self.encoding.clone(),
+                remote: remote.encoding.clone(),
+            });
+        }
+        if self.channels != remote.channels {
+            return Some(ProtocolMismatch::Channels {
+                local: self.channels.clone(),
+                remote: remote.channels.clone(),
+            });
+        }
+        for local in self.protocols.iter() {
+            let entry = remote.protocols.iter().find(|p| p.id == local.id);
+            match entry {
+                Some(entry) if entry.name != local.name => {
+                    return Some(ProtocolMismatch::IdConflict {
+                        id: local.id,
+                        local: local.name.clone(),
+                        remote: entry.name.clone(),
+                    });
+                }
+                Some(entry) if self.encoding_of(local) != remote.encoding_of(entry) => {
+                    return Some(ProtocolMismatch::ProtocolEncoding {
+                        name: local.name.clone(),
+                        local: self.encoding_of(local).to_string(),
+                        remote: remote.encoding_of(entry).to_string(),
+                    });
+                }
+                Some(entry) if local.direction.writes() && !entry.direction.reads() => {
+                    return Some(ProtocolMismatch::UnreadByRemote {
+                        name: local.name.clone(),
+                    });
+                }
+                None if local.direction.writes() => {
+                    return Some(ProtocolMismatch::UnreadByRemote {
+                        name: local.name.clone(),
+                    });
+                }
+                _ => {}
+            }
+        }
+        for remote in remote.protocols.iter().filter(|p| p.direction.writes()) {
+            let local = self.protocols.iter().find(|p| p.id == remote.id);
+            if !local.is_some_and(|local| local.direction.reads()) {
+                return Some(ProtocolMismatch::UnreadByLocal {
+                    name: remote.name.clone(),
+                });
+            }
+        }
+        None
+    }
+
+    /// The encoding a protocol in this manifest is sent with.
+    fn encoding_of<'a>(&'a self, entry: &'a ProtocolEntry) -> &'a str {
+        entry.encoding.as_deref().unwrap_or(&self.encoding)
+    }
+}
+
+/// Asks a client to disconnect, since the host cannot drop a single peer.
+#[derive(Protocol, Serialize, Deserialize, Debug, Clone)]
+#[protocol(crate = "crate")]
+pub struct HangUpPayload {
+    pub reason: String,
+}
+
+/// An incompatibility found while comparing protocol manifests with a peer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProtocolMismatch {
+    /// The peers use different transport encodings.
+    Encoding { local: String, remote: String },
+    /// The peers declare different data channels.
+    Channels {
+        local: Vec<ChannelConfig>,
+        remote: Vec<ChannelConfig>,
+    },
+    /// The peers encode a protocol differently.
+    ProtocolEncoding {
+        name: String,
+        local: String,
+        remote: String,
+    },
+    /// The same protocol ID is registered under different names.
+    IdConflict {
+        id: u16,
+        local: String,
+        remote: String,
+    },
+    /// A protocol written locally is never read by the remote peer.
+    UnreadByRemote { name: String },
+    /// A protocol written by the remote peer is never read locally.
+    UnreadByLocal { name: String },
+}
+
+impl fmt::Display for ProtocolMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProtocolMismatch::Encoding { local, remote } => {
+                write!(f, "encoding mismatch (local: {local}, remote: {remote})")
+            }
+            ProtocolMismatch::Channels { local, remote } => {
+                let list = |channels: &[ChannelConfig]| {
+                    channels
+                        .iter()
+                        .map(ChannelConfig::to_string)
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                };
+                write!(
+                    f,
+                    "channel mismatch (local: [{}], remote: [{}])",
+                    list(local),
+                    list(remote)
+                )
+            }
+            ProtocolMismatch::ProtocolEncoding {
+                name,
+                local,
+                remote,
+            } => write!(
+                f,
+                "`{name}` encoding mismatch (local: {local}, remote: {remote})"
+            ),
+            ProtocolMismatch::IdConflict { id, local, remote } => write!(
+                f,
+                "protocol id {id} is `{local}` locally but `{remote}` remotely"
+            ),
+            ProtocolMismatch::UnreadByRemote { name } => {
+                write!(f, "`{name}` is written but the remote never reads it")
+            }
+            ProtocolMismatch::UnreadByLocal { name } => {
+                write!(f, "`{name}` is written by the remote but never read")
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::ProtocolDirection;
+
+    fn entry(id: u16, name: &str, direction: ProtocolDirection) -> ProtocolEntry {
+        ProtocolEntry {
+            id,
+            name: name.to_string(),
+            direction,
+            encoding: None,
+        }
+    }
+
+    fn manifest(protocols: Vec<ProtocolEntry>) -> HandshakePayload {
+        HandshakePayload {
+            encoding: "Json".to_string(),
+            protocols,
+            channels: vec![],
+        }
+    }
+
+    #[test]
+    fn matching_manifests_are_accepted() {
+        let server = manifest(vec![
+            entry(1, "Chat", ProtocolDirection::ReadWrite),
+            entry(2, "Ping", ProtocolDirection::ReadOnly),
+        ]);
+        let client = manifest(vec![
+            entry(1, "Chat", ProtocolDirection::ReadWrite),
+            entry(2, "Ping", ProtocolDirection::WriteOnly),
+            // Registered but never written, so the server need not know it
+            entry(3, "Pong", ProtocolDirection::ReadOnly),
+        ]);
+
+        assert_eq!(server.check(&client), None);
+        assert_eq!(client.check(&server), None);
+    }
+
+    #[test]
+    fn different_encodings_mismatch() {
+        let local = manifest(vec![]);
+        let remote = HandshakePayload {
+            encoding: "Binary".to_string(),
+            ..manifest(vec![])
+        };
+
+        assert_eq!(
+            local.check(&remote),
+            Some(ProtocolMismatch::Encoding {
+                local: "Json".to_string(),
+                remote: "Binary".to_string(),
+            })
+        );
+    }
+
+    #[test]
+    fn different_channels_mismatch() {
+        let local = manifest(vec![]);
+        let remote = HandshakePayload {
+            channels: vec![ChannelConfig::unreliable("voice")],
+            ..manifest(vec![])
+        };
+
+        assert_eq!(
+            local.check(&remote),
+            Some(ProtocolMismatch::Channels {
+                local: vec![],
+                remote: vec![ChannelConfig::unreliable("voice")],
+            })
+        );
+    }
+
+    #[test]
+    fn different_protocol_encodings_mismatch() {
+        let local = manifest(vec![entry(1, "Chat", ProtocolDirection::ReadWrite)]);
+        let mut chat = entry(1, "Chat", ProtocolDirection::ReadWrite);
+        chat.encoding = Some("Postcard".to_string());
+        let remote = manifest(vec![chat]);
+
+        assert_eq!(
+            local.check(&remote),
+            Some(ProtocolMismatch::ProtocolEncoding {
+                name: "Chat".to_string(),
+                local: "Json".to_string(),
+                remote: "Postcard".to_string(),
+            })
+        );
+    }
+
+    #[test]
+    fn an_override_matching_the_remote_encoding_is_accepted() {
+        let mut chat = entry(1, "Chat", ProtocolDirection::ReadWrite);
+        chat.encoding = Some("Json".to_string());
+        let local = manifest(vec![chat]);
+        let remote = manifest(vec![entry(1, "Chat", ProtocolDirection::ReadWrite)]);
+
+        assert_eq!(local.check(&remote), None);
+    }
+
+    #[test]
+    fn the_same_id_with_different_names_mismatches() {
+        let local = manifest(vec![entry(1, "Chat", ProtocolDirection::ReadWrite)]);
+        let remote = manifest(vec![entry(1, "Spawn", ProtocolDirection::ReadWrite)]);
+
+        assert_eq!(
+            local.check(&remote),
+            Some(ProtocolMismatch::IdConflict {
+                id: 1,
+                local: "Chat".to_string(),
+                remote: "Spawn".to_string(),
+            })
+        );
+    }
+
+    #[test]
+    fn a_written_protocol_the_remote_does_not_read_mismatches() {
+        let local = manifest(vec![entry(1, "Chat", ProtocolDirection::WriteOnly)]);
+        let missing = manifest(vec![]);
+        let write_only = manifest(vec![entry(1, "Chat", ProtocolDirection::WriteOnly)]);
+        let mismatch = ProtocolMismatch::UnreadByRemote {
+            name: "Chat".to_string(),
+        };
+
+        assert_eq!(local.check(&missing), Some(mismatch.clone()));
+        assert_eq!(local.check(&write_only), Some(mismatch));
+    }
+
+    #[test]
+    fn a_protocol_written_by_the_remote_we_do_not_read_mismatches() {
+        let local = manifest(vec![]);
+        let remote = manifest(vec![entry(1, "Chat", ProtocolDirection::WriteOnly)]);
+
+        assert_eq!(
+            local.check(&remote),
+            Some(ProtocolMismatch::UnreadByLocal {
+                name: "Chat".to_string(),
+            })
+        );
+    }
+}
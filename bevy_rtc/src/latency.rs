@@ -1,15 +1,12 @@
+use crate::protocol::Protocol;
 use bevy::prelude::*;
 use bevy_matchbox::matchbox_socket::PeerId;
 use instant::Duration;
 use serde::{Deserialize, Serialize};
 
-// A name import hack to ensure the Payload macro works correctly.
-mod bevy_rtc {
-    pub use crate::protocol;
-}
-
 /// A packet containing information to track a peer's latency
-#[derive(proc_macro_protocol::Protocol, Serialize, Deserialize, Debug, Clone)]
+#[derive(Protocol, Serialize, Deserialize, Debug, Clone)]
+#[protocol(crate = "crate")]
 pub struct LatencyTracerPayload {
     pub from: PeerId,
     pub sent: f64,
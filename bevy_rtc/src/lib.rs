@@ -3,14 +3,49 @@
 #[cfg(all(target_arch = "wasm32", feature = "server"))]
 compile_error!("The 'server' feature is not supported on the wasm32 target architecture.");
 
+pub(crate) mod bitpack;
+pub(crate) mod channel;
+pub mod codec;
+pub(crate) mod dispatch;
 pub(crate) mod events;
+pub(crate) mod handshake;
 pub(crate) mod latency;
+pub(crate) mod outbox;
 pub mod protocol;
+pub mod quantize;
+pub(crate) mod rate;
+pub(crate) mod receipt;
+pub(crate) mod registry;
+#[cfg(feature = "schema")]
+#[cfg_attr(docsrs, doc(cfg(feature = "schema")))]
+pub mod schema;
+pub(crate) mod sequence;
+pub mod snapshot;
 pub(crate) mod socket;
 
 mod transport_encoding;
 pub use transport_encoding::TransportEncoding;
 
+mod compression;
+pub use compression::Compression;
+
+mod batching;
+pub use batching::Batching;
+
+mod fragmentation;
+pub use fragmentation::{Fragmentation, FragmentationStats};
+
+pub use channel::{
+    ChannelConfig, RELIABLE_CHANNEL, RELIABLE_UNORDERED_CHANNEL, UNORDERED_UNRELIABLE_CHANNEL,
+    UNRELIABLE_CHANNEL,
+};
+pub use events::{RtcDecodeError, RtcDelivered, RtcDeliveryTimeout, RtcSendError};
+pub use handshake::ProtocolMismatch;
+pub use outbox::{Backpressure, BackpressurePolicy, RtcSendQueues, SendQueueStats};
+pub use rate::SendRate;
+pub use receipt::MessageHandle;
+pub use registry::{ProtocolStats, RegisteredProtocol, RtcProtocolRegistry};
+
 // Re-exports
 pub use bevy_matchbox;
 
@@ -20,7 +55,16 @@ pub mod prelude {
     #[cfg(feature = "server")]
     pub use crate::server::*;
 
-    pub use crate::{protocol::Protocol, TransportEncoding};
+    pub use crate::{
+        codec::Codec,
+        protocol::{Protocol, ProtocolOptions},
+        quantize::Quantized,
+        snapshot::{Snapshot, SnapshotPayload},
+        Backpressure, BackpressurePolicy, Batching, ChannelConfig, Compression, Fragmentation,
+        FragmentationStats, MessageHandle, ProtocolMismatch, RtcDecodeError, RtcDelivered,
+        RtcDeliveryTimeout, RtcProtocolRegistry, RtcSendError, RtcSendQueues, SendQueueStats,
+        SendRate, TransportEncoding,
+    };
 }
 
 #[cfg(feature = "server")]
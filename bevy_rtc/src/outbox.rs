@@ -0,0 +1,677 @@
+use crate::{
+    batching::{Batch, Batching},
+    channel::Channels,
+    events::RtcSendError,
+    fragmentation::{Fragmentation, FragmentationStats},
+    socket::RtcSocket,
+};
+use bevy::{
+    prelude::*,
+    utils::{HashMap, HashSet},
+};
+use bevy_matchbox::matchbox_socket::{Packet, PeerId};
+use instant::Duration;
+use std::collections::VecDeque;
+
+/// Limit the bytes written to each peer on each channel, and decide what
+/// happens to packets over the limit.
+///
+/// The socket accepts packets faster than a slow connection can deliver them,
+/// and buffers the rest out of sight. With backpressure, packets over the
+/// budget wait in a queue per peer and channel instead, which can be watched in
+/// [`RtcSendQueues`].
+///
+/// Packets the socket can't send, because its channel closed, are handled by
+/// the policy too. Without backpressure, they are retried with the default
+/// policy and `max_queued_bytes`.
+#[derive(Resource, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Backpressure {
+    /// Bytes written to each peer on each channel per second, including
+    /// headers. Up to a second of unused budget can be spent at once.
+    pub bytes_per_second: usize,
+    /// The most bytes kept queued to a peer on a channel, see
+    /// [`BackpressurePolicy`].
+    pub max_queued_bytes: usize,
+    /// What happens to packets over the budget.
+    pub policy: BackpressurePolicy,
+}
+
+impl Default for Backpressure {
+    fn default() -> Self {
+        Self {
+            bytes_per_second: 1024 * 1024,
+            max_queued_bytes: 256 * 1024,
+            policy: BackpressurePolicy::default(),
+        }
+    }
+}
+
+/// What happens to packets which can't be sent immediately, because they are
+/// over the [`Backpressure`] budget.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum BackpressurePolicy {
+    /// Drop them, with an [`RtcSendError`] for each.
+    Drop,
+    /// Keep them queued and send them first in later frames. When more than
+    /// `max_queued_bytes` are queued, the oldest packets are dropped, with an
+    /// [`RtcSendError`] for each.
+    #[default]
+    Retry,
+    /// Keep them queued like `Retry`, but when more than `max_queued_bytes`
+    /// are queued, disconnect the peer and drop everything queued to it. A
+    /// client disconnects from the host, while the server stops sending to the
+    /// client and asks it to disconnect.
+    Disconnect,
+}
+
+/// The outgoing queue to a peer on a channel, see [`RtcSendQueues`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct SendQueueStats {
+    /// Bytes of packets waiting to be sent.
+    pub queued_bytes: usize,
+    /// Packets waiting to be sent.
+    pub queued_packets: usize,
+    /// How long the oldest packet waiting has waited, if any.
+    pub oldest_age: Option<Duration>,
+    /// Packets dropped, by the [`BackpressurePolicy`] or because the socket
+    /// failed to send them.
+    pub dropped: u64,
+}
+
+/// The outgoing queue to each peer on each channel, updated every frame.
+/// Packets wait in a queue while they are over the [`Backpressure`] budget, or
+/// the socket can't send them.
+///
+/// ```
+/// use bevy::prelude::*;
+/// use bevy_rtc::RtcSendQueues;
+///
+/// fn show_backlog(queues: Res<RtcSendQueues>) {
+///     for (peer, channel, stats) in queues.iter() {
+///         if let Some(age) = stats.oldest_age {
+///             info!("{peer} is {age:?} behind on {channel}");
+///         }
+///     }
+/// }
+/// ```
+#[derive(Resource, Debug, Default)]
+pub struct RtcSendQueues {
+    /// The names of the channels, by index.
+    channels: Vec<String>,
+    queues: HashMap<(PeerId, usize), SendQueueStats>,
+}
+
+impl RtcSendQueues {
+    pub(crate) fn new(channels: &Channels) -> Self {
+        Self {
+            channels: channels.configs().iter().map(|c| c.name.clone()).collect(),
+            queues: HashMap::new(),
+        }
+    }
+
+    /// Returns the queue to a peer on a channel, by the channel's name.
+    pub fn get(&self, peer: PeerId, channel: &str) -> Option<SendQueueStats> {
+        let index = self.channels.iter().position(|c| c == channel)?;
+        self.queues.get(&(peer, index)).copied()
+    }
+
+    /// Iterate over the queue to each peer on each channel, with the name of
+    /// the channel.
+    pub fn iter(&self) -> impl Iterator<Item = (PeerId, &str, SendQueueStats)> {
+        self.queues.iter().map(|((peer, channel), stats)| {
+            let name = self.channels.get(*channel).map_or("?", String::as_str);
+            (*peer, name, *stats)
+        })
+    }
+
+    /// Returns the bytes waiting to be sent to a peer on every channel.
+    pub fn queued_bytes(&self, peer: PeerId) -> usize {
+        self.queues
+            .iter()
+            .filter(|((p, _), _)| *p == peer)
+            .map(|(_, stats)| stats.queued_bytes)
+            .sum()
+    }
+}
+
+/// The protocol name and packet of each packet to a peer on a channel.
+type Packets = Vec<(&'static str, Packet)>;
+
+/// The protocol name, packet and when it was queued.
+type Queued = (&'static str, Packet, Duration);
+
+/// The packets waiting to be sent to a peer on a channel.
+#[derive(Debug, Default)]
+struct SendQueue {
+    /// The packets waiting, oldest first.
+    packets: VecDeque<Queued>,
+    queued_bytes: usize,
+    /// Bytes which may be written before the budget runs out. This goes
+    /// negative after a packet larger than the rest of the budget.
+    allowance: f64,
+    dropped: u64,
+}
+
+impl SendQueue {
+    fn push(&mut self, protocol: &'static str, packet: Packet, now: Duration) {
+        self.queued_bytes += packet.len();
+        self.packets.push_back((protocol, packet, now));
+    }
+
+    fn pop(&mut self) -> Option<Queued> {
+        let queued = self.packets.pop_front()?;
+        self.queued_bytes -= queued.1.len();
+        Some(queued)
+    }
+
+    /// Put packets taken with [`SendQueue::take_ready`] back at the front of
+    /// the queue, refunding their budget.
+    fn requeue(&mut self, packets: Vec<Queued>) {
+        for queued in packets.into_iter().rev() {
+            self.queued_bytes += queued.1.len();
+            self.allowance += queued.1.len() as f64;
+            self.packets.push_front(queued);
+        }
+    }
+
+    /// Take the packets which fit in the budget, oldest first.
+    fn take_ready(&mut self, delta: Duration, backpressure: Option<&Backpressure>) -> Vec<Queued> {
+        let Some(backpressure) = backpressure else {
+            return std::iter::from_fn(|| self.pop()).collect();
+        };
+        let budget = backpressure.bytes_per_second as f64;
+        self.allowance = (self.allowance + budget * delta.as_secs_f64()).min(budget);
+        let mut ready = vec![];
+        while self.allowance > 0.0 {
+            let Some(queued) = self.pop() else {
+                break;
+            };
+            self.allowance -= queued.1.len() as f64;
+            ready.push(queued);
+        }
+        ready
+    }
+
+    fn stats(&self, now: Duration) -> SendQueueStats {
+        SendQueueStats {
+            queued_bytes: self.queued_bytes,
+            queued_packets: self.packets.len(),
+            oldest_age: self
+                .packets
+                .front()
+                .map(|(_, _, queued)| now.saturating_sub(*queued)),
+            dropped: self.dropped,
+        }
+    }
+}
+
+/// Packets waiting to be written to the socket. Protocols queue their packets
+/// here, so packets to the same peer and channel can be batched together and
+/// held back by [`Backpressure`].
+#[derive(Resource, Default, Debug)]
+pub(crate) struct Outbox {
+    /// The protocol name and packet, in the order they were sent this frame.
+    sent: HashMap<(PeerId, usize), Packets>,
+    queues: HashMap<(PeerId, usize), SendQueue>,
+    /// Peers disconnected for being too slow, whose packets are discarded.
+    disconnected: HashSet<PeerId>,
+    /// Peers disconnected this frame, not yet handled by the plugin.
+    too_slow: Vec<PeerId>,
+}
+
+impl Outbox {
+    /// Queue a packet of a protocol to a peer.
+    pub fn push(&mut self, peer: PeerId, channel: usize, protocol: &'static str, packet: Packet) {
+        self.sent
+            .entry((peer, channel))
+            .or_default()
+            .push((protocol, packet));
+    }
+
+    /// Put packets the socket couldn't send back at the front of their queue.
+    fn requeue(&mut self, peer: PeerId, channel: usize, packets: Vec<Queued>) {
+        self.queues
+            .entry((peer, channel))
+            .or_default()
+            .requeue(packets);
+    }
+
+    /// Take the peers disconnected for being too slow since the last call.
+    pub fn take_too_slow(&mut self) -> Vec<PeerId> {
+        std::mem::take(&mut self.too_slow)
+    }
+
+    /// Stop sending to a peer the server hung up on, dropping everything
+    /// queued to it.
+    #[cfg(feature = "server")]
+    pub fn hang_up(&mut self, peer: PeerId) {
+        for ((p, channel), packets) in self.sent.iter_mut() {
+            if *p == peer {
+                let queue = self.queues.entry((peer, *channel)).or_default();
+                queue.dropped += std::mem::take(packets).len() as u64;
+            }
+        }
+        for ((_, _), queue) in self.queues.iter_mut().filter(|((p, _), _)| *p == peer) {
+            while queue.pop().is_some() {
+                queue.dropped += 1;
+            }
+        }
+        self.disconnected.insert(peer);
+    }
+
+    /// Forget the queues of a disconnected peer.
+    #[cfg(feature = "server")]
+    pub fn forget(&mut self, peer: PeerId) {
+        self.sent.retain(|(p, _), _| *p != peer);
+        self.queues.retain(|(p, _), _| *p != peer);
+        self.disconnected.remove(&peer);
+    }
+
+    /// Forget the queues of every peer.
+    #[cfg(feature = "client")]
+    pub fn clear(&mut self) {
+        self.sent.clear();
+        self.queues.clear();
+        self.disconnected.clear();
+        self.too_slow.clear();
+    }
+
+    /// Queue the packets sent this frame, and take the packets to write to the
+    /// socket now.
+    fn schedule(
+        &mut self,
+        now: Duration,
+        delta: Duration,
+        backpressure: Option<&Backpressure>,
+    ) -> Vec<((PeerId, usize), Vec<Queued>)> {
+        for ((peer, channel), packets) in self.sent.drain() {
+            let queue = self
+                .queues
+                .entry((peer, channel))
+                .or_insert_with(|| SendQueue {
+                    allowance: backpressure.map_or(0.0, |b| b.bytes_per_second as f64),
+                    ..default()
+                });
+            if self.disconnected.contains(&peer) {
+                queue.dropped += packets.len() as u64;
+                continue;
+            }
+            for (protocol, packet) in packets {
+                queue.push(protocol, packet, now);
+            }
+        }
+
+        self.queues
+            .iter_mut()
+            .map(|(key, queue)| (*key, queue.take_ready(delta, backpressure)))
+            .filter(|(_, packets)| !packets.is_empty())
+            .collect()
+    }
+
+    /// Apply the policy to the packets left in the queues once the ready
+    /// packets were written. Packets dropped by the policy are added to
+    /// `dropped`.
+    fn enforce(
+        &mut self,
+        backpressure: Option<&Backpressure>,
+        dropped: &mut Vec<(PeerId, &'static str, &'static str)>,
+    ) {
+        // Without backpressure, only packets the socket couldn't send are
+        // queued, and the default policy applies to them
+        let limits = backpressure.copied().unwrap_or_default();
+        for (&(peer, _), queue) in self.queues.iter_mut() {
+            match limits.policy {
+                BackpressurePolicy::Drop => {
+                    while let Some((protocol, _, _)) = queue.pop() {
+                        queue.dropped += 1;
+                        dropped.push((peer, protocol, "over the send budget"));
+                    }
+                }
+                BackpressurePolicy::Retry => {
+                    while queue.queued_bytes > limits.max_queued_bytes {
+                        let Some((protocol, _, _)) = queue.pop() else {
+                            break;
+                        };
+                        queue.dropped += 1;
+                        dropped.push((peer, protocol, "send queue overflowed"));
+                    }
+                }
+                BackpressurePolicy::Disconnect => {
+                    if queue.queued_bytes > limits.max_queued_bytes
+                        && self.disconnected.insert(peer)
+                    {
+                        warn!("Disconnecting {peer}, its send queue overflowed");
+                        self.too_slow.push(peer);
+                    }
+                }
+            }
+        }
+
+        for peer in self.too_slow.iter() {
+            for ((_, _), queue) in self.queues.iter_mut().filter(|((p, _), _)| p == peer) {
+                while let Some((protocol, _, _)) = queue.pop() {
+                    queue.dropped += 1;
+                    dropped.push((*peer, protocol, "peer is too slow"));
+                }
+            }
+        }
+    }
+}
+
+/// Writes the packets queued this frame to the socket, holding back packets
+/// over the backpressure budget, fragmenting large packets on unreliable
+/// channels and batching small ones if enabled.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn flush_outbox(
+    mut outbox: ResMut<Outbox>,
+    mut socket: ResMut<RtcSocket>,
+    channels: Res<Channels>,
+    batching: Option<Res<Batching>>,
+    fragmentation: Option<Res<Fragmentation>>,
+    backpressure: Option<Res<Backpressure>>,
+    time: Res<Time<Real>>,
+    mut stats: ResMut<FragmentationStats>,
+    mut send_queues: ResMut<RtcSendQueues>,
+    mut next_message: Local<u16>,
+    mut send_errors: EventWriter<RtcSendError>,
+) {
+    let now = time.elapsed();
+    let policy = backpressure.as_deref().copied().unwrap_or_default().policy;
+    let ready = outbox.schedule(now, time.delta(), backpressure.as_deref());
+
+    for ((peer, channel), packets) in ready {
+        // The socket only fails to send once the channel has closed, and a
+        // failed packet can't be taken back, so hold packets back beforehand
+        if policy != BackpressurePolicy::Drop && socket.channel(channel).is_closed() {
+            warn!(
+                "Holding back {} packets to {peer}, the channel is closed",
+                packets.len()
+            );
+            outbox.requeue(peer, channel, packets);
+            continue;
+        }
+        let mut lost = 0;
+        let mut batch = batching.as_ref().map(|batching| Batch::new(batching.mtu));
+        for (protocol, packet, _) in packets {
+            let fragmentation = fragmentation
+                .as_ref()
+                .filter(|f| !channels.is_reliable(channel) && packet.len() > f.max_size);
+            if let Some(fragmentation) = fragmentation {
+                // Keep fragments in order with the packets batched before them
+                if let Some((protocols, datagram)) = batch.as_mut().and_then(Batch::take) {
+                    lost += send(
+                        &mut socket,
+                        &mut send_errors,
+                        peer,
+                        channel,
+                        protocols,
+                        datagram,
+                    );
+                }
+                let Some(fragments) = fragmentation.fragment(*next_message, &packet) else {
+                    error!("{protocol} packet to {peer} is too large to fragment");
+                    send_errors.send(RtcSendError {
+                        peer,
+                        protocol,
+                        reason: format!("{} bytes is too large to fragment", packet.len()),
+                    });
+                    continue;
+                };
+                *next_message = next_message.wrapping_add(1);
+                stats.messages_fragmented += 1;
+                stats.fragments_sent += fragments.len() as u64;
+                for fragment in fragments {
+                    lost += send(
+                        &mut socket,
+                        &mut send_errors,
+                        peer,
+                        channel,
+                        vec![protocol],
+                        fragment,
+                    );
+                }
+                continue;
+            }
+            let Some(batch) = batch.as_mut() else {
+                lost += send(
+                    &mut socket,
+                    &mut send_errors,
+                    peer,
+                    channel,
+                    vec![protocol],
+                    packet,
+                );
+                continue;
+            };
+            if !batch.fits(&packet) {
+                if let Some((protocols, datagram)) = batch.take() {
+                    lost += send(
+                        &mut socket,
+                        &mut send_errors,
+                        peer,
+                        channel,
+                        protocols,
+                        datagram,
+                    );
+                }
+            }
+            if batch.is_empty() && !batch.fits(&packet) {
+                // Too large to batch
+                lost += send(
+                    &mut socket,
+                    &mut send_errors,
+                    peer,
+                    channel,
+                    vec![protocol],
+                    packet,
+                );
+                continue;
+            }
+            batch.push(protocol, packet);
+        }
+        if let Some((protocols, datagram)) = batch.as_mut().and_then(Batch::take) {
+            lost += send(
+                &mut socket,
+                &mut send_errors,
+                peer,
+                channel,
+                protocols,
+                datagram,
+            );
+        }
+        if let Some(queue) = outbox.queues.get_mut(&(peer, channel)) {
+            queue.dropped += lost;
+        }
+    }
+
+    let mut dropped = vec![];
+    outbox.enforce(backpressure.as_deref(), &mut dropped);
+    if !dropped.is_empty() {
+        warn!(
+            "Dropped {} packets held back by backpressure",
+            dropped.len()
+        );
+    }
+    send_errors.send_batch(
+        dropped
+            .into_iter()
+            .map(|(peer, protocol, reason)| RtcSendError {
+                peer,
+                protocol,
+                reason: reason.to_string(),
+            }),
+    );
+
+    send_queues.queues = outbox
+        .queues
+        .iter()
+        .map(|(key, queue)| (*key, queue.stats(now)))
+        .collect();
+}
+
+/// Write a datagram to the socket, reporting a send error for each protocol in
+/// it if the socket fails. Returns the number of packets lost, which only
+/// happens under [`BackpressurePolicy::Drop`] or if the channel closes during
+/// the frame.
+fn send(
+    socket: &mut RtcSocket,
+    send_errors: &mut EventWriter<RtcSendError>,
+    peer: PeerId,
+    channel: usize,
+    protocols: Vec<&'static str>,
+    packet: Packet,
+) -> u64 {
+    let Err(err) = socket.channel_mut(channel).try_send(packet, peer) else {
+        return 0;
+    };
+    error!("failed to send packet to {peer}: {err}");
+    let lost = protocols.len() as u64;
+    send_errors.send_batch(protocols.into_iter().map(|protocol| RtcSendError {
+        peer,
+        protocol,
+        reason: format!("failed to send: {err}"),
+    }));
+    lost
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::socket::{closed_socket, UNRELIABLE_CHANNEL_INDEX};
+    use bevy::{ecs::system::RunSystemOnce, utils::Uuid};
+
+    pub(crate) const PEER: PeerId = PeerId(Uuid::from_u128(1));
+
+    /// A world to flush the outbox in, with a socket which fails to send.
+    pub(crate) fn world(backpressure: Option<Backpressure>) -> World {
+        let channels = Channels::default();
+        let mut world = World::new();
+        world.insert_resource(closed_socket(&channels));
+        world.insert_resource(RtcSendQueues::new(&channels));
+        world.insert_resource(channels);
+        world.init_resource::<Outbox>();
+        world.init_resource::<Time<Real>>();
+        world.init_resource::<FragmentationStats>();
+        world.init_resource::<Events<RtcSendError>>();
+        if let Some(backpressure) = backpressure {
+            world.insert_resource(backpressure);
+        }
+        world
+    }
+
+    /// Queue packets of `len` bytes to `PEER` and flush the outbox, returning
+    /// the reasons of any send errors.
+    pub(crate) fn flush(world: &mut World, packets: usize, len: usize) -> Vec<String> {
+        let mut outbox = world.resource_mut::<Outbox>();
+        for _ in 0..packets {
+            let packet = vec![0; len].into_boxed_slice();
+            outbox.push(PEER, UNRELIABLE_CHANNEL_INDEX, "test", packet);
+        }
+        world.run_system_once(flush_outbox);
+        world
+            .resource_mut::<Events<RtcSendError>>()
+            .drain()
+            .map(|error| error.reason)
+            .collect()
+    }
+
+    fn stats(world: &World) -> SendQueueStats {
+        world
+            .resource::<RtcSendQueues>()
+            .get(PEER, "unreliable")
+            .unwrap()
+    }
+
+    fn backpressure(policy: BackpressurePolicy) -> Backpressure {
+        Backpressure {
+            max_queued_bytes: 250,
+            policy,
+            ..default()
+        }
+    }
+
+    #[test]
+    fn queues_are_reported_without_backpressure() {
+        let mut world = world(None);
+
+        assert!(flush(&mut world, 2, 100).is_empty());
+        let stats = stats(&world);
+        assert_eq!(stats.queued_packets, 2);
+        assert_eq!(stats.queued_bytes, 200);
+        assert_eq!(stats.dropped, 0);
+        assert_eq!(stats.oldest_age, Some(Duration::ZERO));
+    }
+
+    #[test]
+    fn drop_policy_drops_packets_the_socket_fails_to_send() {
+        let mut world = world(Some(backpressure(BackpressurePolicy::Drop)));
+
+        let errors = flush(&mut world, 2, 100);
+        assert_eq!(errors.len(), 2);
+        assert!(errors.iter().all(|e| e.starts_with("failed to send")));
+        let stats = stats(&world);
+        assert_eq!(stats.queued_packets, 0);
+        assert_eq!(stats.dropped, 2);
+    }
+
+    #[test]
+    fn drop_policy_drops_packets_over_the_budget() {
+        let mut world = world(Some(Backpressure {
+            bytes_per_second: 100,
+            ..backpressure(BackpressurePolicy::Drop)
+        }));
+
+        // The first packet is sent (and fails), the second is over the budget
+        let errors = flush(&mut world, 2, 100);
+        assert_eq!(errors.len(), 2);
+        assert!(errors.contains(&"over the send budget".to_string()));
+        assert_eq!(stats(&world).dropped, 2);
+    }
+
+    #[test]
+    fn retry_policy_requeues_packets_the_socket_fails_to_send() {
+        let mut world = world(Some(backpressure(BackpressurePolicy::Retry)));
+
+        assert!(flush(&mut world, 2, 100).is_empty());
+        assert_eq!(stats(&world).queued_packets, 2);
+
+        world
+            .resource_mut::<Time<Real>>()
+            .advance_by(Duration::from_millis(100));
+        assert!(flush(&mut world, 0, 100).is_empty());
+        let stats = stats(&world);
+        assert_eq!(stats.queued_packets, 2);
+        assert_eq!(stats.oldest_age, Some(Duration::from_millis(100)));
+    }
+
+    #[test]
+    fn retry_policy_drops_the_oldest_packets_on_overflow() {
+        let mut world = world(Some(backpressure(BackpressurePolicy::Retry)));
+
+        assert!(flush(&mut world, 2, 100).is_empty());
+        let errors = flush(&mut world, 2, 100);
+        assert_eq!(errors, vec!["send queue overflowed".to_string(); 2]);
+        let stats = stats(&world);
+        assert_eq!(stats.queued_bytes, 200);
+        assert_eq!(stats.dropped, 2);
+    }
+
+    #[test]
+    fn disconnect_policy_marks_the_peer_too_slow_on_overflow() {
+        let mut world = world(Some(backpressure(BackpressurePolicy::Disconnect)));
+
+        assert!(flush(&mut world, 2, 100).is_empty());
+        assert!(world.resource_mut::<Outbox>().take_too_slow().is_empty());
+
+        let errors = flush(&mut world, 1, 100);
+        assert_eq!(errors, vec!["peer is too slow".to_string(); 3]);
+        assert_eq!(world.resource_mut::<Outbox>().take_too_slow(), vec![PEER]);
+        assert_eq!(stats(&world).queued_packets, 0);
+
+        // Later packets to the peer are discarded
+        assert!(flush(&mut world, 1, 100).is_empty());
+        assert_eq!(stats(&world).queued_packets, 0);
+        assert_eq!(stats(&world).dropped, 4);
+        assert!(world.resource_mut::<Outbox>().take_too_slow().is_empty());
+    }
+}
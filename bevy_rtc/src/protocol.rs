@@ -1,32 +1,230 @@
 use bevy_matchbox::matchbox_socket::Packet;
-use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde::{Deserialize, Serialize};
 use std::fmt::Debug;
 
 // Note: Intentional name collision with the trait Payload!
 // This is done commonly, like `serde::Serialize` is a trait and a derive macro.
 pub use proc_macro_protocol::Protocol;
 
-use crate::transport_encoding::TransportEncoding;
+use crate::{codec::CodecError, rate::SendRate, transport_encoding::TransportEncoding};
 
-#[derive(Deserialize, Serialize)]
-#[serde(bound = "M: DeserializeOwned")]
-pub(crate) struct RtcPacket<M: Protocol> {
+/// The envelope of every packet on the wire: the protocol ID as a little-endian
+/// `u16`, a byte of flags, followed by the payload in the transport encoding.
+pub(crate) struct RtcPacket<'a> {
     pub msg_id: u16,
-    pub data: M,
+    pub flags: u8,
+    pub data: &'a [u8],
+}
+
+impl<'a> RtcPacket<'a> {
+    /// The size of the header before the payload
+    pub const HEADER_SIZE: usize = 3;
+
+    /// The payload is compressed, see [`Compression`](crate::Compression). The
+    /// bytes after the extensions are an LZ4 block prefixed with its
+    /// uncompressed size as a little-endian `u32`.
+    pub const COMPRESSED: u8 = 1 << 0;
+
+    /// The payload is a batch of packets, see [`Batching`](crate::Batching).
+    /// The protocol ID of a batch is unused.
+    pub const BATCHED: u8 = 1 << 1;
+
+    /// The payload is a fragment of a larger packet, see
+    /// [`Fragmentation`](crate::Fragmentation). The header is followed by the
+    /// message number, the index of the fragment and the number of fragments,
+    /// each a little-endian `u16`, then a chunk of the packet. The protocol ID
+    /// is that of the packet which was split.
+    pub const FRAGMENT: u8 = 1 << 2;
+
+    /// The header is followed by a little-endian `u16` sequence number, and
+    /// the receiver drops the payload if it is older than one it already
+    /// received. See [`ProtocolOptions::sequenced`].
+    pub const SEQUENCED: u8 = 1 << 3;
+
+    /// The header (and sequence number, if any) is followed by a little-endian
+    /// `u32` receipt ID, which the receiver acknowledges once the payload is
+    /// read. See `RtcServer::reliable_to_peer_tracked`.
+    pub const TRACKED: u8 = 1 << 4;
+
+    /// Read the header of a packet, returning `None` if it is too short.
+    pub fn parse(packet: &'a [u8]) -> Option<Self> {
+        if packet.len() < Self::HEADER_SIZE {
+            return None;
+        }
+        let (header, data) = packet.split_at(Self::HEADER_SIZE);
+        Some(Self {
+            msg_id: u16::from_le_bytes([header[0], header[1]]),
+            flags: header[2],
+            data,
+        })
+    }
+
+    /// Write the header for a payload of the given protocol.
+    pub fn write_header(msg_id: u16, flags: u8, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(&msg_id.to_le_bytes());
+        buf.push(flags);
+    }
+
+    /// Insert bytes between the header and the data of a packet, and set the
+    /// flag announcing them.
+    pub fn insert_extension(packet: Packet, flag: u8, bytes: &[u8]) -> Packet {
+        let Some(header) = RtcPacket::parse(&packet) else {
+            return packet;
+        };
+        let mut buf = Vec::with_capacity(packet.len() + bytes.len());
+        Self::write_header(header.msg_id, header.flags | flag, &mut buf);
+        buf.extend_from_slice(bytes);
+        buf.extend_from_slice(header.data);
+        buf.into_boxed_slice()
+    }
+
+    /// Remove the bytes inserted with [`RtcPacket::insert_extension`], and
+    /// clear their flag. Returns `None` if the packet is too short.
+    pub fn remove_extension<const N: usize>(&self, flag: u8) -> Option<([u8; N], Packet)> {
+        let bytes = self.data.get(..N)?.try_into().ok()?;
+        let mut buf = Vec::with_capacity(Self::HEADER_SIZE + self.data.len() - N);
+        Self::write_header(self.msg_id, self.flags & !flag, &mut buf);
+        buf.extend_from_slice(&self.data[N..]);
+        Some((bytes, buf.into_boxed_slice()))
+    }
 }
 
 pub trait Protocol:
     Debug + Clone + Send + Sync + for<'a> Deserialize<'a> + Serialize + 'static
 {
+    /// The ID of this protocol on the wire. The derive macro hashes the fully
+    /// qualified type path with [`protocol_id`], or uses a pinned ID given with
+    /// `#[protocol(id = 42)]`.
+    ///
+    /// Generic protocols also hash the [`TypePath`](bevy::reflect::TypePath)
+    /// of each type argument and the value of each const argument, so
+    /// `Spawn<Player>` and `Spawn<Npc>` have different IDs. Their type
+    /// arguments must implement `TypePath`, e.g. with `#[derive(TypePath)]`.
     fn id() -> u16;
 
+    /// The name of this protocol. The derive macro uses the name of the type,
+    /// with the short type path of each argument for generic protocols, e.g.
+    /// `Spawn<Player>`.
     fn reflect_name() -> &'static str;
 
-    fn from_packet(packet: &Packet, deserializer: &TransportEncoding) -> Option<Self> {
+    fn from_packet(packet: &Packet, deserializer: &TransportEncoding) -> Result<Self, CodecError> {
         deserializer.decode_packet(packet)
     }
 
-    fn to_packet(&self, serializer: &TransportEncoding) -> Packet {
+    fn to_packet(&self, serializer: &TransportEncoding) -> Result<Packet, CodecError> {
         serializer.encode_packet(self)
     }
 }
+
+/// The bounds of every [`Protocol`], which the derive macro requires of generic
+/// protocols.
+#[doc(hidden)]
+pub trait ProtocolBounds:
+    Debug + Clone + Send + Sync + for<'a> Deserialize<'a> + Serialize + 'static
+{
+}
+
+impl<T> ProtocolBounds for T where
+    T: Debug + Clone + Send + Sync + for<'a> Deserialize<'a> + Serialize + 'static
+{
+}
+
+/// Used by the derive macro to name the instantiations of generic protocols.
+#[doc(hidden)]
+pub use bevy::reflect::{utility::GenericTypePathCell, TypePath};
+
+/// Per-protocol settings, applied with `configure_server_protocol` or
+/// `configure_client_protocol`. These must match between the client and server,
+/// except for the `send_rate`.
+#[derive(Debug, Clone, Default)]
+pub struct ProtocolOptions {
+    /// Encode this protocol with a different encoding than the plugin's, e.g.
+    /// JSON for a chat protocol while everything else is binary.
+    pub encoding: Option<TransportEncoding>,
+    /// Never compress this protocol, e.g. for data which is already compressed.
+    /// This has no effect unless the plugin enables [`Compression`](crate::Compression).
+    pub skip_compression: bool,
+    /// Send this protocol on a channel declared in the plugin's `channels`,
+    /// e.g. so bulk transfers don't hold up game state. By default, payloads
+    /// are sent on the reliable or unreliable channel they are sent with.
+    ///
+    /// The bound channel only replaces channels which are as reliable as it
+    /// is. Payloads sent with the `reliable_*` methods of a protocol bound to an
+    /// unreliable channel still go out on the built-in reliable channels, and
+    /// payloads sent with the `unreliable_*` methods of a protocol bound to a
+    /// reliable channel on the built-in unreliable channels.
+    pub channel: Option<String>,
+    /// Number the payloads sent to each peer, and drop received payloads which
+    /// are older than the newest one received from the same peer, e.g. for
+    /// positions where only the newest matters. Dropped payloads are counted in
+    /// [`ProtocolStats::stale`](crate::ProtocolStats::stale).
+    pub sequenced: bool,
+    /// Limit how often the lazy `*_with` send methods build a payload, e.g. to
+    /// send positions at 20 Hz however fast the app runs. By default, every
+    /// call builds and sends a payload.
+    pub send_rate: Option<SendRate>,
+}
+
+/// The direction a protocol was registered with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum ProtocolDirection {
+    /// The protocol is only read, never written.
+    ReadOnly,
+    /// The protocol is only written, never read.
+    WriteOnly,
+    /// The protocol is read and written.
+    ReadWrite,
+}
+
+impl ProtocolDirection {
+    /// Returns true if payloads of this protocol are read.
+    pub fn reads(self) -> bool {
+        matches!(self, Self::ReadOnly | Self::ReadWrite)
+    }
+
+    /// Returns true if payloads of this protocol are written.
+    pub fn writes(self) -> bool {
+        matches!(self, Self::WriteOnly | Self::ReadWrite)
+    }
+
+    /// Combine two registrations of the same protocol, e.g. read only and
+    /// write only registrations become read-write.
+    pub(crate) fn merge(self, other: Self) -> Self {
+        if (self.reads() || other.reads()) && (self.writes() || other.writes()) {
+            Self::ReadWrite
+        } else {
+            self
+        }
+    }
+}
+
+impl std::fmt::Display for ProtocolDirection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::ReadOnly => write!(f, "ro"),
+            Self::WriteOnly => write!(f, "wo"),
+            Self::ReadWrite => write!(f, "rw"),
+        }
+    }
+}
+
+/// Hash a fully qualified type path (e.g. `my_crate::net::ChatPayload`) into a
+/// protocol ID. This is used by the `Protocol` derive and is part of the wire
+/// format, so it is stable across Rust toolchains and targets.
+///
+/// The hash is 32-bit FNV-1a over the UTF-8 bytes of the path, with the upper
+/// and lower 16 bits xor-folded together.
+pub const fn protocol_id(path: &str) -> u16 {
+    const FNV_OFFSET_BASIS: u32 = 0x811c_9dc5;
+    const FNV_PRIME: u32 = 0x0100_0193;
+
+    let bytes = path.as_bytes();
+    let mut hash = FNV_OFFSET_BASIS;
+    let mut i = 0;
+    while i < bytes.len() {
+        hash ^= bytes[i] as u32;
+        hash = hash.wrapping_mul(FNV_PRIME);
+        i += 1;
+    }
+    ((hash >> 16) ^ (hash & 0xffff)) as u16
+}
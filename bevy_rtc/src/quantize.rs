@@ -0,0 +1,221 @@
+//! Floats sent at reduced precision.
+//!
+//! Many payloads carry floats with a known range, e.g. normalized cursor
+//! positions in `0.0..=1.0`, which rarely need all 32 bits. A [`Quantized`]
+//! float is rounded to one of `2^BITS` evenly spaced steps over its range and
+//! sent as an integer: a `u8`, `u16` or `u32` with most encodings, and exactly
+//! `BITS` bits with [`TransportEncoding::BitPacked`](crate::TransportEncoding::BitPacked).
+//!
+//! ```
+//! use bevy_rtc::quantize::{Quantized, Unit};
+//!
+//! let x = Quantized::<Unit, 12>::new(0.3);
+//! // Off by at most half a step
+//! assert!((x.get() - 0.3).abs() <= Quantized::<Unit, 12>::MAX_ERROR);
+//! ```
+//!
+//! Ranges other than [`Unit`] and [`SignedUnit`] are declared with
+//! [`quantized_range!`](crate::quantized_range).
+
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::{fmt, marker::PhantomData};
+
+/// The inclusive range of a [`Quantized`] float.
+pub trait QuantizedRange: Send + Sync + 'static {
+    const MIN: f32;
+    const MAX: f32;
+}
+
+/// The range `0.0..=1.0`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Unit;
+
+impl QuantizedRange for Unit {
+    const MIN: f32 = 0.0;
+    const MAX: f32 = 1.0;
+}
+
+/// The range `-1.0..=1.0`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SignedUnit;
+
+impl QuantizedRange for SignedUnit {
+    const MIN: f32 = -1.0;
+    const MAX: f32 = 1.0;
+}
+
+/// Declare a [`QuantizedRange`].
+///
+/// ```
+/// use bevy_rtc::{quantize::Quantized, quantized_range};
+///
+/// quantized_range!(pub Angle, 0.0, std::f32::consts::TAU);
+///
+/// let heading = Quantized::<Angle, 10>::new(1.5);
+/// ```
+#[macro_export]
+macro_rules! quantized_range {
+    ($vis:vis $name:ident, $min:expr, $max:expr) => {
+        #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+        $vis struct $name;
+
+        impl $crate::quantize::QuantizedRange for $name {
+            const MIN: f32 = $min;
+            const MAX: f32 = $max;
+        }
+    };
+}
+
+/// The newtype names of quantized values by their number of bits, which tell
+/// the bit-packed encoding how many bits to write. Other encodings ignore them.
+const NAMES: [&str; 33] = {
+    macro_rules! names {
+        ($($bits:literal)*) => {
+            [$(concat!("bevy_rtc::Quantized<", $bits, ">")),*]
+        };
+    }
+    names!(0 1 2 3 4 5 6 7 8 9 10 11 12 13 14 15 16 17 18 19 20 21 22 23 24 25 26 27 28 29 30 31 32)
+};
+
+/// Returns the number of bits of a quantized value from its newtype name.
+pub(crate) fn quantized_bits(name: &str) -> Option<u32> {
+    name.strip_prefix("bevy_rtc::Quantized<")?
+        .strip_suffix('>')?
+        .parse()
+        .ok()
+}
+
+/// A float in the range `R`, rounded to `BITS` bits of precision.
+///
+/// Values outside of the range are clamped to it, and `NaN` becomes the
+/// minimum. `BITS` must be between 1 and 32.
+pub struct Quantized<R: QuantizedRange, const BITS: u32> {
+    step: u32,
+    range: PhantomData<R>,
+}
+
+impl<R: QuantizedRange, const BITS: u32> Quantized<R, BITS> {
+    const VALID: () = assert!(BITS >= 1 && BITS <= 32, "BITS must be between 1 and 32");
+
+    /// The largest step.
+    const STEPS: u32 = (u32::MAX >> (32 - BITS));
+
+    /// The largest difference between a value and its quantized value, within
+    /// the range.
+    pub const MAX_ERROR: f32 = (R::MAX - R::MIN) / Self::STEPS as f32 / 2.0;
+
+    /// Round a value to the nearest step.
+    pub fn new(value: f32) -> Self {
+        #[allow(clippy::let_unit_value)]
+        let _ = Self::VALID;
+        let t = ((value - R::MIN) / (R::MAX - R::MIN)).clamp(0.0, 1.0);
+        // NaN saturates to 0
+        let step = (t as f64 * Self::STEPS as f64).round() as u32;
+        Self {
+            step,
+            range: PhantomData,
+        }
+    }
+
+    /// Returns the quantized value.
+    pub fn get(&self) -> f32 {
+        let t = self.step as f64 / Self::STEPS as f64;
+        (R::MIN as f64 + t * (R::MAX - R::MIN) as f64) as f32
+    }
+
+    /// Returns the step of the value, which is what is sent on the wire.
+    pub fn step(&self) -> u32 {
+        self.step
+    }
+}
+
+impl<R: QuantizedRange, const BITS: u32> Clone for Quantized<R, BITS> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<R: QuantizedRange, const BITS: u32> Copy for Quantized<R, BITS> {}
+
+impl<R: QuantizedRange, const BITS: u32> PartialEq for Quantized<R, BITS> {
+    fn eq(&self, other: &Self) -> bool {
+        self.step == other.step
+    }
+}
+
+impl<R: QuantizedRange, const BITS: u32> Eq for Quantized<R, BITS> {}
+
+impl<R: QuantizedRange, const BITS: u32> Default for Quantized<R, BITS> {
+    fn default() -> Self {
+        Self::new(0.0)
+    }
+}
+
+impl<R: QuantizedRange, const BITS: u32> fmt::Debug for Quantized<R, BITS> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&self.get(), f)
+    }
+}
+
+impl<R: QuantizedRange, const BITS: u32> From<f32> for Quantized<R, BITS> {
+    fn from(value: f32) -> Self {
+        Self::new(value)
+    }
+}
+
+impl<R: QuantizedRange, const BITS: u32> From<Quantized<R, BITS>> for f32 {
+    fn from(value: Quantized<R, BITS>) -> Self {
+        value.get()
+    }
+}
+
+impl<R: QuantizedRange, const BITS: u32> Serialize for Quantized<R, BITS> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let name = NAMES[BITS as usize];
+        match BITS {
+            0..=8 => serializer.serialize_newtype_struct(name, &(self.step as u8)),
+            9..=16 => serializer.serialize_newtype_struct(name, &(self.step as u16)),
+            _ => serializer.serialize_newtype_struct(name, &self.step),
+        }
+    }
+}
+
+impl<'de, R: QuantizedRange, const BITS: u32> Deserialize<'de> for Quantized<R, BITS> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct StepVisitor<R: QuantizedRange, const BITS: u32>(PhantomData<R>);
+
+        impl<'de, R: QuantizedRange, const BITS: u32> de::Visitor<'de> for StepVisitor<R, BITS> {
+            type Value = Quantized<R, BITS>;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "a {BITS} bit quantized value")
+            }
+
+            fn visit_newtype_struct<D: Deserializer<'de>>(
+                self,
+                deserializer: D,
+            ) -> Result<Self::Value, D::Error> {
+                let step = match BITS {
+                    0..=8 => u8::deserialize(deserializer)? as u32,
+                    9..=16 => u16::deserialize(deserializer)? as u32,
+                    _ => u32::deserialize(deserializer)?,
+                };
+                self.visit_u32(step)
+            }
+
+            fn visit_u64<E: de::Error>(self, step: u64) -> Result<Self::Value, E> {
+                if step > Quantized::<R, BITS>::STEPS as u64 {
+                    return Err(E::invalid_value(de::Unexpected::Unsigned(step), &self));
+                }
+                Ok(Quantized {
+                    step: step as u32,
+                    range: PhantomData,
+                })
+            }
+        }
+
+        #[allow(clippy::let_unit_value)]
+        let _ = Self::VALID;
+        deserializer.deserialize_newtype_struct(NAMES[BITS as usize], StepVisitor(PhantomData))
+    }
+}
@@ -0,0 +1,164 @@
+use bevy::utils::HashMap;
+use bevy_matchbox::matchbox_socket::PeerId;
+use instant::Duration;
+
+/// How often the lazy `*_with` send methods of a protocol build a payload, set
+/// with [`ProtocolOptions::send_rate`](crate::protocol::ProtocolOptions::send_rate).
+///
+/// Calls made before the next slot is free are skipped without running their
+/// closure. Other send methods are never limited.
+///
+/// ```
+/// use bevy_rtc::SendRate;
+///
+/// // At most 20 payloads a second, to each peer
+/// let rate = SendRate::hz(20.0).per_peer();
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SendRate {
+    /// The minimum time between payloads.
+    pub interval: Duration,
+    /// Limit payloads sent to each peer with the `*_to_peer_with` methods
+    /// separately, rather than sharing one limit with every other call. This
+    /// has no effect on the client, which only sends to the host.
+    pub per_peer: bool,
+}
+
+impl SendRate {
+    /// At most `hz` payloads a second.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `hz` is not positive.
+    pub fn hz(hz: f64) -> Self {
+        assert!(hz > 0.0, "send rate must be positive, got {hz} Hz");
+        Self {
+            interval: Duration::from_secs_f64(1.0 / hz),
+            per_peer: false,
+        }
+    }
+
+    /// Limit payloads to each peer separately.
+    pub fn per_peer(self) -> Self {
+        Self {
+            per_peer: true,
+            ..self
+        }
+    }
+}
+
+/// The next free send slot of a protocol.
+#[derive(Debug, Default)]
+pub(crate) struct SendLimiter {
+    rate: Option<SendRate>,
+    /// When the next shared slot is free, as elapsed real time.
+    next: Option<Duration>,
+    /// When the next slot to each peer is free, if limited per peer.
+    next_by_peer: HashMap<PeerId, Duration>,
+}
+
+impl SendLimiter {
+    pub fn new(rate: Option<SendRate>) -> Self {
+        Self {
+            rate,
+            ..Default::default()
+        }
+    }
+
+    /// Take the next slot to send to a peer, or to several peers with `None`,
+    /// at `now` elapsed real time, returning false if it is not free yet.
+    pub fn try_acquire(&mut self, peer: Option<PeerId>, now: Duration) -> bool {
+        let Some(rate) = self.rate else {
+            return true;
+        };
+        let next = match peer {
+            Some(peer) if rate.per_peer => self.next_by_peer.entry(peer).or_insert(now),
+            _ => self.next.get_or_insert(now),
+        };
+        if now < *next {
+            return false;
+        }
+        // Keep the average rate when frames don't line up with slots, without
+        // bursting after a pause
+        *next += rate.interval;
+        if *next <= now {
+            *next = now + rate.interval;
+        }
+        true
+    }
+
+    /// Forget the slots of peers which are no longer connected.
+    #[cfg(feature = "server")]
+    pub fn retain_peers(&mut self, connected: impl Fn(&PeerId) -> bool) {
+        self.next_by_peer.retain(|peer, _| connected(peer));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::utils::Uuid;
+
+    const PEER: PeerId = PeerId(Uuid::from_u128(1));
+    const OTHER: PeerId = PeerId(Uuid::from_u128(2));
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn unlimited_without_a_rate() {
+        let mut limiter = SendLimiter::new(None);
+        assert!((0..10).all(|_| limiter.try_acquire(None, ms(0))));
+    }
+
+    #[test]
+    fn slots_are_an_interval_apart() {
+        let mut limiter = SendLimiter::new(Some(SendRate::hz(10.0)));
+        assert!(limiter.try_acquire(None, ms(0)));
+        assert!(!limiter.try_acquire(None, ms(0)));
+        assert!(!limiter.try_acquire(None, ms(99)));
+        assert!(limiter.try_acquire(None, ms(100)));
+        assert!(!limiter.try_acquire(None, ms(150)));
+    }
+
+    #[test]
+    fn average_rate_is_kept_when_frames_miss_slots() {
+        let mut limiter = SendLimiter::new(Some(SendRate::hz(10.0)));
+        // Frames every 60ms fall between slots, but 10 are still taken a second
+        let taken = (0..=1000)
+            .step_by(60)
+            .filter(|now| limiter.try_acquire(None, ms(*now)))
+            .count();
+        assert_eq!(taken, 10);
+    }
+
+    #[test]
+    fn no_burst_after_a_pause() {
+        let mut limiter = SendLimiter::new(Some(SendRate::hz(10.0)));
+        assert!(limiter.try_acquire(None, ms(0)));
+        assert!(limiter.try_acquire(None, ms(1000)));
+        assert!(!limiter.try_acquire(None, ms(1000)));
+        assert!(!limiter.try_acquire(None, ms(1050)));
+        assert!(limiter.try_acquire(None, ms(1100)));
+    }
+
+    #[test]
+    fn peers_have_their_own_slots() {
+        let mut limiter = SendLimiter::new(Some(SendRate::hz(10.0).per_peer()));
+        assert!(limiter.try_acquire(Some(PEER), ms(0)));
+        assert!(limiter.try_acquire(Some(OTHER), ms(0)));
+        assert!(!limiter.try_acquire(Some(PEER), ms(50)));
+        // Sends to several peers share a slot
+        assert!(limiter.try_acquire(None, ms(50)));
+        assert!(!limiter.try_acquire(None, ms(50)));
+    }
+
+    #[test]
+    fn peers_share_a_slot_unless_limited_per_peer() {
+        let mut limiter = SendLimiter::new(Some(SendRate::hz(10.0)));
+        assert!(limiter.try_acquire(Some(PEER), ms(0)));
+        assert!(!limiter.try_acquire(Some(OTHER), ms(0)));
+        assert!(!limiter.try_acquire(None, ms(0)));
+    }
+}
@@ -0,0 +1,103 @@
+use crate::{
+    events::RtcDeliveryTimeout,
+    protocol::{Protocol, RtcPacket},
+};
+use bevy::{prelude::*, utils::HashMap};
+use bevy_matchbox::matchbox_socket::{Packet, PeerId};
+use instant::Duration;
+use serde::{Deserialize, Serialize};
+
+/// How long to wait for the receipt of a tracked payload by default.
+pub(crate) const DEFAULT_DELIVERY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A tracked payload, returned by `RtcServer::reliable_to_peer_tracked` and
+/// `RtcClient::reliable_to_host_tracked`.
+///
+/// Once the peer reads the payload, an [`RtcDelivered`](crate::RtcDelivered)
+/// event is sent with its handle. If the peer does not read it in time, e.g.
+/// because it disconnected or dropped the payload from a full buffer, an
+/// [`RtcDeliveryTimeout`] event is sent instead. So is one for a payload which
+/// fails to encode, when it would have been sent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct MessageHandle {
+    protocol: u16,
+    id: u32,
+}
+
+impl MessageHandle {
+    pub(crate) fn new(protocol: u16, id: u32) -> Self {
+        Self { protocol, id }
+    }
+
+    /// The ID of the protocol the payload was sent with.
+    pub fn protocol(&self) -> u16 {
+        self.protocol
+    }
+}
+
+/// Acknowledges the tracked payloads of a protocol the receiving app read.
+#[derive(Protocol, Serialize, Deserialize, Debug, Clone)]
+#[protocol(crate = "crate")]
+pub struct DeliveryReceipt {
+    pub protocol: u16,
+    pub receipts: Vec<u32>,
+}
+
+/// Tracked payloads waiting for a receipt, by peer.
+#[derive(Resource, Debug)]
+pub(crate) struct Receipts {
+    timeout: Duration,
+    /// When each payload was sent, in real time.
+    pending: HashMap<(PeerId, MessageHandle), Duration>,
+}
+
+impl Receipts {
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            timeout,
+            pending: HashMap::new(),
+        }
+    }
+
+    /// Wait for the receipt of a payload sent to a peer.
+    pub fn track(&mut self, peer: PeerId, handle: MessageHandle, now: Duration) {
+        self.pending.insert((peer, handle), now);
+    }
+
+    /// Accept the receipt of a payload, returning false if it was not waited
+    /// for, e.g. because it timed out.
+    pub fn deliver(&mut self, peer: PeerId, handle: MessageHandle) -> bool {
+        self.pending.remove(&(peer, handle)).is_some()
+    }
+}
+
+/// Gives up on tracked payloads which were not acknowledged in time.
+pub(crate) fn expire_receipts(
+    time: Res<Time<Real>>,
+    mut receipts: ResMut<Receipts>,
+    mut timeouts: EventWriter<RtcDeliveryTimeout>,
+) {
+    let Some(deadline) = time.elapsed().checked_sub(receipts.timeout) else {
+        return;
+    };
+    receipts.pending.retain(|(peer, handle), sent| {
+        if *sent > deadline {
+            return true;
+        }
+        warn!("No receipt from {peer} for {handle:?}");
+        timeouts.send(RtcDeliveryTimeout(*handle));
+        false
+    });
+}
+
+/// Insert a receipt ID after the header of a packet.
+pub(crate) fn stamp(packet: Packet, receipt: u32) -> Packet {
+    RtcPacket::insert_extension(packet, RtcPacket::TRACKED, &receipt.to_le_bytes())
+}
+
+/// Remove the receipt ID of a tracked packet, returning `None` if it is too
+/// short to have one.
+pub(crate) fn unstamp(packet: &RtcPacket) -> Option<(u32, Packet)> {
+    let (receipt, packet) = packet.remove_extension(RtcPacket::TRACKED)?;
+    Some((u32::from_le_bytes(receipt), packet))
+}
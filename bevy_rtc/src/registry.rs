@@ -0,0 +1,399 @@
+use crate::{
+    channel::Channels,
+    handshake::{HandshakePayload, HangUpPayload},
+    protocol::{Protocol, ProtocolDirection, ProtocolOptions},
+    rate::SendRate,
+    transport_encoding::TransportEncoding,
+};
+use bevy::{prelude::*, utils::HashMap};
+use serde::{Deserialize, Serialize};
+use std::{
+    any::{type_name, TypeId},
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+/// A protocol in the manifest exchanged in the handshake.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProtocolEntry {
+    /// The protocol ID, see [`Protocol::id`]
+    pub id: u16,
+    /// The protocol name, see [`Protocol::reflect_name`]
+    pub name: String,
+    /// How the protocol was registered
+    pub direction: ProtocolDirection,
+    /// The encoding name, if it overrides the transport encoding
+    #[serde(default)]
+    pub encoding: Option<String>,
+}
+
+/// Counters of the traffic of a protocol, see [`RegisteredProtocol::stats`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ProtocolStats {
+    /// Payloads encoded and queued to be sent, counting each peer.
+    pub sent: u64,
+    /// Bytes of payloads queued to be sent, including headers.
+    pub sent_bytes: u64,
+    /// Payloads received and decoded.
+    pub received: u64,
+    /// Bytes of payloads received and decoded, including headers.
+    pub received_bytes: u64,
+    /// Received payloads which failed to decode.
+    pub decode_failures: u64,
+    /// Received payloads dropped because the read buffer was full.
+    pub dropped: u64,
+    /// Received payloads of a sequenced protocol dropped because a newer one
+    /// was already received.
+    pub stale: u64,
+}
+
+/// Counters updated by systems which only read the registry.
+#[derive(Debug, Default)]
+struct ProtocolCounters {
+    sent: AtomicU64,
+    sent_bytes: AtomicU64,
+    received: AtomicU64,
+    received_bytes: AtomicU64,
+    decode_failures: AtomicU64,
+    dropped: AtomicU64,
+    stale: AtomicU64,
+}
+
+/// A protocol registered with `AddServerProtocolExt` or `AddClientProtocolExt`.
+#[derive(Debug)]
+pub struct RegisteredProtocol {
+    id: u16,
+    name: &'static str,
+    direction: ProtocolDirection,
+    bound: Option<usize>,
+    encoding: Option<String>,
+    channel: Option<String>,
+    sequenced: bool,
+    send_rate: Option<SendRate>,
+    counters: ProtocolCounters,
+}
+
+impl RegisteredProtocol {
+    /// The protocol ID, see [`Protocol::id`].
+    pub fn id(&self) -> u16 {
+        self.id
+    }
+
+    /// The protocol name, see [`Protocol::reflect_name`].
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// How the protocol was registered.
+    pub fn direction(&self) -> ProtocolDirection {
+        self.direction
+    }
+
+    /// The number of payloads buffered for reading (per peer on the server),
+    /// or `None` if the protocol is never read. Unbounded protocols return
+    /// `usize::MAX`.
+    pub fn bound(&self) -> Option<usize> {
+        self.bound
+    }
+
+    /// The name of the encoding of this protocol, if it overrides the
+    /// transport encoding.
+    pub fn encoding(&self) -> Option<&str> {
+        self.encoding.as_deref()
+    }
+
+    /// The name of the channel this protocol is bound to, if any.
+    pub fn channel(&self) -> Option<&str> {
+        self.channel.as_deref()
+    }
+
+    /// Returns true if payloads sent of this protocol are sequenced.
+    pub fn is_sequenced(&self) -> bool {
+        self.sequenced
+    }
+
+    /// How often the lazy `*_with` send methods build a payload, if limited.
+    pub fn send_rate(&self) -> Option<SendRate> {
+        self.send_rate
+    }
+
+    /// The traffic of this protocol so far.
+    pub fn stats(&self) -> ProtocolStats {
+        let counters = &self.counters;
+        ProtocolStats {
+            sent: counters.sent.load(Ordering::Relaxed),
+            sent_bytes: counters.sent_bytes.load(Ordering::Relaxed),
+            received: counters.received.load(Ordering::Relaxed),
+            received_bytes: counters.received_bytes.load(Ordering::Relaxed),
+            decode_failures: counters.decode_failures.load(Ordering::Relaxed),
+            dropped: counters.dropped.load(Ordering::Relaxed),
+            stale: counters.stale.load(Ordering::Relaxed),
+        }
+    }
+
+    fn entry(&self) -> ProtocolEntry {
+        ProtocolEntry {
+            id: self.id,
+            name: self.name.to_string(),
+            direction: self.direction,
+            encoding: self.encoding.clone(),
+        }
+    }
+}
+
+/// A record of every protocol registered on this app, with counters of their
+/// traffic.
+///
+/// ```
+/// use bevy::prelude::*;
+/// use bevy_rtc::RtcProtocolRegistry;
+///
+/// fn log_traffic(registry: Res<RtcProtocolRegistry>) {
+///     for protocol in registry.iter() {
+///         let stats = protocol.stats();
+///         info!("{}: {} sent, {} received", protocol.name(), stats.sent, stats.received);
+///     }
+/// }
+/// ```
+#[derive(Resource, Debug)]
+pub struct RtcProtocolRegistry {
+    entries: Vec<RegisteredProtocol>,
+    /// The index of each registered protocol in `entries`, by ID, as they are
+    /// looked up for every packet.
+    index: HashMap<u16, usize>,
+    /// The type which claimed each protocol ID, including internal protocols.
+    claimed: HashMap<u16, (TypeId, &'static str)>,
+    /// Options set with `configure_server_protocol` or `configure_client_protocol`.
+    options: HashMap<u16, ProtocolOptions>,
+    /// Traces the format of each registered protocol, for exporting schemas.
+    #[cfg(feature = "schema")]
+    tracers: HashMap<u16, crate::schema::TraceFn>,
+}
+
+impl Default for RtcProtocolRegistry {
+    fn default() -> Self {
+        let mut registry = Self {
+            entries: vec![],
+            index: HashMap::new(),
+            claimed: HashMap::new(),
+            options: HashMap::new(),
+            #[cfg(feature = "schema")]
+            tracers: HashMap::new(),
+        };
+        // Internal protocols which are not registered through the extension
+        // traits still need their IDs reserved.
+        registry.claim::<HandshakePayload>();
+        registry.claim::<HangUpPayload>();
+        registry
+    }
+}
+
+impl RtcProtocolRegistry {
+    /// Iterate over the registered protocols, in the order they were
+    /// registered.
+    pub fn iter(&self) -> impl Iterator<Item = &RegisteredProtocol> {
+        self.entries.iter()
+    }
+
+    /// Returns the registered protocol with an ID.
+    pub fn get(&self, id: u16) -> Option<&RegisteredProtocol> {
+        self.index.get(&id).map(|&i| &self.entries[i])
+    }
+
+    /// Returns a registered protocol by its type.
+    pub fn get_for<M: Protocol>(&self) -> Option<&RegisteredProtocol> {
+        self.get(M::id())
+    }
+
+    /// Returns the number of registered protocols.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns true if no protocols are registered.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Record a protocol registration. Registering the same protocol twice,
+    /// e.g. read only and write only, merges the directions.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the protocol ID is already claimed by another type.
+    pub(crate) fn register<M: Protocol>(
+        &mut self,
+        direction: ProtocolDirection,
+        bound: Option<usize>,
+    ) {
+        self.claim::<M>();
+        #[cfg(feature = "schema")]
+        self.tracers.insert(M::id(), crate::schema::trace::<M>);
+        if let Some(&i) = self.index.get(&M::id()) {
+            let entry = &mut self.entries[i];
+            entry.direction = entry.direction.merge(direction);
+            entry.bound = entry.bound.or(bound);
+        } else {
+            self.index.insert(M::id(), self.entries.len());
+            self.entries.push(RegisteredProtocol {
+                id: M::id(),
+                name: M::reflect_name(),
+                direction,
+                bound,
+                encoding: self.encoding(M::id()).map(|e| e.to_string()),
+                channel: self.channel(M::id()).map(|c| c.to_string()),
+                sequenced: self.sequenced(M::id()),
+                send_rate: self.send_rate(M::id()),
+                counters: ProtocolCounters::default(),
+            });
+        }
+    }
+
+    /// The manifest of registered protocols sent in the handshake.
+    pub(crate) fn manifest(&self) -> Vec<ProtocolEntry> {
+        self.entries.iter().map(RegisteredProtocol::entry).collect()
+    }
+
+    /// Count a payload queued to be sent.
+    pub(crate) fn count_sent(&self, id: u16, bytes: usize) {
+        if let Some(entry) = self.get(id) {
+            entry.counters.sent.fetch_add(1, Ordering::Relaxed);
+            entry
+                .counters
+                .sent_bytes
+                .fetch_add(bytes as u64, Ordering::Relaxed);
+        }
+    }
+
+    /// Count a payload received and decoded.
+    pub(crate) fn count_received(&self, id: u16, bytes: usize) {
+        if let Some(entry) = self.get(id) {
+            entry.counters.received.fetch_add(1, Ordering::Relaxed);
+            entry
+                .counters
+                .received_bytes
+                .fetch_add(bytes as u64, Ordering::Relaxed);
+        }
+    }
+
+    /// Count a received payload which failed to decode.
+    pub(crate) fn count_decode_failure(&self, id: u16) {
+        if let Some(entry) = self.get(id) {
+            entry
+                .counters
+                .decode_failures
+                .fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Count a received payload dropped for being older than the newest.
+    pub(crate) fn count_stale(&self, id: u16) {
+        if let Some(entry) = self.get(id) {
+            entry.counters.stale.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Count received payloads dropped from a full read buffer.
+    pub(crate) fn count_dropped(&self, id: u16, dropped: usize) {
+        if let Some(entry) = self.get(id) {
+            entry
+                .counters
+                .dropped
+                .fetch_add(dropped as u64, Ordering::Relaxed);
+        }
+    }
+
+    /// Set the options of a protocol, before or after it is registered.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the protocol ID is already claimed by another type.
+    pub(crate) fn configure<M: Protocol>(&mut self, options: ProtocolOptions) {
+        self.claim::<M>();
+        if let Some(&i) = self.index.get(&M::id()) {
+            let entry = &mut self.entries[i];
+            entry.encoding = options.encoding.as_ref().map(|e| e.to_string());
+            entry.channel = options.channel.clone();
+            entry.sequenced = options.sequenced;
+            entry.send_rate = options.send_rate;
+        }
+        self.options.insert(M::id(), options);
+    }
+
+    /// Returns the encoding overriding the transport encoding for a protocol.
+    pub(crate) fn encoding(&self, id: u16) -> Option<&TransportEncoding> {
+        self.options.get(&id)?.encoding.as_ref()
+    }
+
+    /// Returns true if a protocol opted out of compression.
+    pub(crate) fn skip_compression(&self, id: u16) -> bool {
+        self.options.get(&id).is_some_and(|o| o.skip_compression)
+    }
+
+    /// Returns the name of the channel a protocol is bound to.
+    pub(crate) fn channel(&self, id: u16) -> Option<&str> {
+        self.options.get(&id)?.channel.as_deref()
+    }
+
+    /// Returns true if a protocol is sent sequenced.
+    pub(crate) fn sequenced(&self, id: u16) -> bool {
+        self.options.get(&id).is_some_and(|o| o.sequenced)
+    }
+
+    /// Returns the send rate of the lazy send methods of a protocol.
+    pub(crate) fn send_rate(&self, id: u16) -> Option<SendRate> {
+        self.options.get(&id)?.send_rate
+    }
+
+    /// Check that every protocol is bound to a declared channel.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a protocol is bound to an unknown channel.
+    pub(crate) fn check_channels(&self, channels: &Channels) {
+        for (id, options) in self.options.iter() {
+            let Some(channel) = options.channel.as_deref() else {
+                continue;
+            };
+            if channels.index(channel).is_none() {
+                let name = self.claimed.get(id).map_or("?", |(_, name)| name);
+                panic!("`{name}` is bound to channel `{channel}`, which is not declared");
+            }
+        }
+    }
+
+    /// Returns the name of a registered protocol.
+    pub(crate) fn name(&self, id: u16) -> Option<&str> {
+        self.get(id).map(|e| e.name)
+    }
+
+    /// Replace how a registered protocol is traced.
+    #[cfg(feature = "schema")]
+    pub(crate) fn set_tracer(&mut self, id: u16, trace: crate::schema::TraceFn) {
+        self.tracers.insert(id, trace);
+    }
+
+    /// Returns the tracer of a registered protocol.
+    #[cfg(feature = "schema")]
+    pub(crate) fn tracer(&self, id: u16) -> Option<crate::schema::TraceFn> {
+        self.tracers.get(&id).copied()
+    }
+
+    /// Returns true if a protocol, including internal protocols, has this ID.
+    pub(crate) fn is_claimed(&self, id: u16) -> bool {
+        self.claimed.contains_key(&id)
+    }
+
+    fn claim<M: Protocol>(&mut self) {
+        let (claimed_by, claimed_name) = *self
+            .claimed
+            .entry(M::id())
+            .or_insert((TypeId::of::<M>(), type_name::<M>()));
+        if claimed_by != TypeId::of::<M>() {
+            panic!(
+                "protocol id collision: `{}` and `{claimed_name}` both have id {}, pin one with `#[protocol(id = ...)]`",
+                type_name::<M>(),
+                M::id()
+            );
+        }
+    }
+}
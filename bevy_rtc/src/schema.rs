@@ -0,0 +1,609 @@
+//! Export registered protocols as JSON Schema or TypeScript, for clients which
+//! are not written in Rust.
+//!
+//! Payload shapes are traced from each protocol's `Deserialize` impl, so no
+//! extra derives are needed. They describe payloads as encoded with
+//! [`TransportEncoding::Json`](crate::TransportEncoding::Json).
+//!
+//! ```
+//! # #[cfg(feature = "server")]
+//! # fn main() {
+//! use bevy::prelude::*;
+//! use bevy_rtc::{prelude::*, schema::RtcSchemaExt};
+//! use serde::{Deserialize, Serialize};
+//!
+//! #[derive(Protocol, Serialize, Deserialize, Debug, Clone)]
+//! pub struct ChatPayload {
+//!     pub from: String,
+//!     pub message: String,
+//! }
+//!
+//! let mut app = App::new();
+//! app.add_server_rw_protocol::<ChatPayload>(2);
+//!
+//! let schema = app.protocol_schema().unwrap();
+//! let typescript = schema.to_typescript();
+//! assert!(typescript.contains("export type ChatPayload = { from: string; message: string };"));
+//! # }
+//! # #[cfg(not(feature = "server"))]
+//! # fn main() {}
+//! ```
+
+use crate::{
+    protocol::{Protocol, RtcPacket},
+    registry::{ProtocolEntry, RtcProtocolRegistry},
+    snapshot::{Snapshot, SnapshotBody, SnapshotPayload},
+    transport_encoding::TransportEncoding,
+};
+use bevy::prelude::*;
+use bevy::utils::{HashMap, HashSet};
+use serde_json::{json, Map, Value};
+use serde_reflection::{
+    ContainerFormat, Format, FormatHolder, Named, Registry, Tracer, TracerConfig, VariantFormat,
+};
+use std::{fmt, fmt::Write};
+
+/// Traces the format of a protocol.
+pub(crate) type TraceFn = fn(&mut Tracer) -> serde_reflection::Result<Format>;
+
+/// Trace the format of a protocol, see [`TraceFn`].
+pub(crate) fn trace<M: Protocol>(tracer: &mut Tracer) -> serde_reflection::Result<Format> {
+    tracer.trace_simple_type::<M>().map(|(format, _)| format)
+}
+
+/// Trace the format of a snapshot protocol. Only the variants of top-level
+/// enums are explored, so the body nested in the payload is traced first.
+pub(crate) fn trace_snapshot<S: Snapshot>(tracer: &mut Tracer) -> serde_reflection::Result<Format> {
+    tracer.trace_simple_type::<SnapshotBody<S>>()?;
+    trace::<SnapshotPayload<S>>(tracer)
+}
+
+/// An error raised while tracing the format of a protocol.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SchemaError {
+    /// The protocol name, see [`Protocol::reflect_name`]
+    pub protocol: String,
+    /// Why the format could not be traced
+    pub reason: String,
+}
+
+impl fmt::Display for SchemaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "failed to trace `{}`: {}", self.protocol, self.reason)
+    }
+}
+
+impl std::error::Error for SchemaError {}
+
+/// A protocol in a [`ProtocolSchema`].
+#[derive(Debug, Clone)]
+pub struct ProtocolFormat {
+    /// The protocol ID, see [`Protocol::id`]
+    pub msg_id: u16,
+    /// The protocol name, see [`Protocol::reflect_name`]
+    pub reflect_name: String,
+    /// How the protocol was registered, e.g. `rw`
+    pub direction: String,
+    /// The encoding of the protocol
+    pub encoding: String,
+    /// The format of the payload
+    pub format: Format,
+}
+
+/// The formats of every protocol registered on an app.
+#[derive(Debug, Clone)]
+pub struct ProtocolSchema {
+    /// The registered protocols
+    pub protocols: Vec<ProtocolFormat>,
+    /// The formats of every named type used by the protocols
+    pub types: Registry,
+}
+
+/// An extension trait to export the protocols registered on an app.
+pub trait RtcSchemaExt {
+    /// Trace the formats of every protocol registered with `AddServerProtocolExt`
+    /// or `AddClientProtocolExt`.
+    fn protocol_schema(&self) -> Result<ProtocolSchema, SchemaError>;
+}
+
+impl RtcSchemaExt for App {
+    fn protocol_schema(&self) -> Result<ProtocolSchema, SchemaError> {
+        let Some(registry) = self.world.get_resource::<RtcProtocolRegistry>() else {
+            return Ok(ProtocolSchema {
+                protocols: vec![],
+                types: Registry::new(),
+            });
+        };
+        let default_encoding = self
+            .world
+            .get_resource::<TransportEncoding>()
+            .map(|e| e.to_string());
+        ProtocolSchema::trace(registry, default_encoding.as_deref())
+    }
+}
+
+impl ProtocolSchema {
+    /// Trace each protocol with its own tracer, then merge the types they use.
+    ///
+    /// Serde names containers without their type arguments, so the
+    /// instantiations of a generic protocol (and the generic types within them)
+    /// would collide. The payload of an instantiation is named after the
+    /// protocol instead, e.g. `Spawn_Player` for `Spawn<Player>`, and other
+    /// types with a different format in another protocol are named after their
+    /// protocol's type arguments, e.g. `SnapshotBody_WorldSnapshot`.
+    fn trace(
+        registry: &RtcProtocolRegistry,
+        default_encoding: Option<&str>,
+    ) -> Result<Self, SchemaError> {
+        let mut traced = vec![];
+        for ProtocolEntry {
+            id,
+            name,
+            direction,
+            encoding,
+        } in registry.manifest().iter()
+        {
+            let error = |reason: String| SchemaError {
+                protocol: name.clone(),
+                reason,
+            };
+            let trace = registry
+                .tracer(*id)
+                .ok_or_else(|| error("protocol was not traced".to_string()))?;
+            let mut tracer = Tracer::new(TracerConfig::default().is_human_readable(true));
+            let format = trace(&mut tracer).map_err(|err| error(err.to_string()))?;
+            let types = tracer.registry().map_err(|err| error(err.to_string()))?;
+            let protocol = ProtocolFormat {
+                msg_id: *id,
+                reflect_name: name.clone(),
+                direction: direction.to_string(),
+                encoding: encoding
+                    .as_deref()
+                    .or(default_encoding)
+                    .unwrap_or_default()
+                    .to_string(),
+                format,
+            };
+            traced.push((protocol, types));
+        }
+
+        // Types which have a different format in another protocol
+        let mut formats: HashMap<&str, Vec<&ContainerFormat>> = HashMap::new();
+        for (_, types) in traced.iter() {
+            for (name, container) in types.iter() {
+                let formats = formats.entry(name.as_str()).or_default();
+                if !formats.contains(&container) {
+                    formats.push(container);
+                }
+            }
+        }
+        let ambiguous: HashSet<String> = formats
+            .into_iter()
+            .filter(|(_, formats)| formats.len() > 1)
+            .map(|(name, _)| name.to_string())
+            .collect();
+
+        let mut protocols = vec![];
+        let mut types = Registry::new();
+        for (mut protocol, protocol_types) in traced {
+            let error = |reason: String| SchemaError {
+                protocol: protocol.reflect_name.clone(),
+                reason,
+            };
+            let payload = match &protocol.format {
+                Format::TypeName(payload) => Some(payload.clone()),
+                _ => None,
+            };
+            let renames: HashMap<String, String> = protocol_types
+                .keys()
+                .filter(|name| {
+                    ambiguous.contains(*name)
+                        || (payload.as_ref() == Some(*name) && protocol.reflect_name.contains('<'))
+                })
+                .map(|name| {
+                    let renamed = instance_name(name, payload.as_deref(), &protocol.reflect_name);
+                    (name.clone(), renamed)
+                })
+                .collect();
+            let mut rename = |format: &mut Format| {
+                if let Format::TypeName(name) = format {
+                    if let Some(renamed) = renames.get(name) {
+                        *name = renamed.clone();
+                    }
+                }
+                Ok(())
+            };
+            protocol
+                .format
+                .visit_mut(&mut rename)
+                .map_err(|err| error(err.to_string()))?;
+            for (name, mut container) in protocol_types {
+                container
+                    .visit_mut(&mut rename)
+                    .map_err(|err| error(err.to_string()))?;
+                let name = renames.get(&name).cloned().unwrap_or(name);
+                match types.get(&name) {
+                    Some(existing) if *existing != container => {
+                        return Err(error(format!("`{name}` has another format")));
+                    }
+                    Some(_) => {}
+                    None => {
+                        types.insert(name, container);
+                    }
+                }
+            }
+            protocols.push(protocol);
+        }
+        Ok(Self { protocols, types })
+    }
+
+    /// Export a JSON Schema (draft 7) of the payloads.
+    ///
+    /// The schema accepts any protocol's payload. Each protocol is described
+    /// in `oneOf` with `x-msg-id`, `x-reflect-name`, `x-direction` and
+    /// `x-encoding`, and the packet header is described by `x-envelope`.
+    pub fn to_json_schema(&self) -> Value {
+        let definitions: Map<String, Value> = self
+            .types
+            .iter()
+            .map(|(name, container)| (name.clone(), container_schema(container)))
+            .collect();
+        let protocols: Vec<Value> = self
+            .protocols
+            .iter()
+            .map(|protocol| {
+                let mut schema = format_schema(&protocol.format);
+                if let Value::Object(ref mut schema) = schema {
+                    schema.insert("x-msg-id".into(), json!(protocol.msg_id));
+                    schema.insert("x-reflect-name".into(), json!(protocol.reflect_name));
+                    schema.insert("x-direction".into(), json!(protocol.direction));
+                    schema.insert("x-encoding".into(), json!(protocol.encoding));
+                }
+                schema
+            })
+            .collect();
+        json!({
+            "$schema": "http://json-schema.org/draft-07/schema#",
+            "title": "bevy_rtc protocols",
+            "x-envelope": {
+                "header_size": RtcPacket::HEADER_SIZE,
+                "msg_id": { "offset": 0, "type": "u16", "endianness": "little" },
+                "flags": {
+                    "offset": 2,
+                    "type": "u8",
+                    "bits": {
+                        "compressed": RtcPacket::COMPRESSED,
+                        "batched": RtcPacket::BATCHED,
+                        "fragment": RtcPacket::FRAGMENT,
+                        "sequenced": RtcPacket::SEQUENCED,
+                        "tracked": RtcPacket::TRACKED,
+                    },
+                },
+                "sequence": {
+                    "offset": RtcPacket::HEADER_SIZE,
+                    "type": "u16",
+                    "endianness": "little",
+                    "if": "sequenced",
+                },
+                "receipt": {
+                    "offset": RtcPacket::HEADER_SIZE,
+                    "type": "u32",
+                    "endianness": "little",
+                    "if": "tracked",
+                    "after": "sequence",
+                },
+                "compression": {
+                    "if": "compressed",
+                    "after": "receipt",
+                    "description": "The payload after the sequence and receipt extensions, which stay uncompressed, is an LZ4 block (not an LZ4 frame) prefixed with its uncompressed size as a little-endian u32.",
+                    "size": { "type": "u32", "endianness": "little" },
+                },
+                "batch": {
+                    "offset": RtcPacket::HEADER_SIZE,
+                    "if": "batched",
+                    "description": "The protocol ID is unused. The rest of the datagram is a sequence of packets, each prefixed with its length as a little-endian u16.",
+                    "length": { "type": "u16", "endianness": "little" },
+                },
+                "fragment": {
+                    "offset": RtcPacket::HEADER_SIZE,
+                    "if": "fragment",
+                    "description": "The header is followed by a 6-byte fragment header, then a chunk of the packet which was split. Packets are reassembled by concatenating the chunks of a message in order of their index.",
+                    "message": { "offset": 0, "type": "u16", "endianness": "little" },
+                    "index": { "offset": 2, "type": "u16", "endianness": "little" },
+                    "count": { "offset": 4, "type": "u16", "endianness": "little" },
+                },
+            },
+            "oneOf": protocols,
+            "definitions": definitions,
+        })
+    }
+
+    /// Export TypeScript declarations of the payloads, with the protocol IDs
+    /// and packet header layout.
+    pub fn to_typescript(&self) -> String {
+        let mut out = String::new();
+        writeln!(out, "// Generated by bevy_rtc, do not edit.").unwrap();
+        writeln!(out).unwrap();
+        writeln!(
+            out,
+            "/** Every packet starts with a header: the protocol ID as a little-endian u16, then a byte of flags. */"
+        )
+        .unwrap();
+        writeln!(
+            out,
+            "export const HEADER_SIZE = {};",
+            RtcPacket::HEADER_SIZE
+        )
+        .unwrap();
+        writeln!(
+            out,
+            "/** Set in the header flags when the payload is compressed: the bytes after the sequence and receipt extensions, which stay uncompressed, are an LZ4 block (not an LZ4 frame) prefixed with its uncompressed size as a little-endian u32. */"
+        )
+        .unwrap();
+        writeln!(
+            out,
+            "export const FLAG_COMPRESSED = {};",
+            RtcPacket::COMPRESSED
+        )
+        .unwrap();
+        writeln!(
+            out,
+            "/** Set in the header flags when the datagram is a batch of packets, each prefixed with its length as a little-endian u16. The protocol ID of a batch is unused. */"
+        )
+        .unwrap();
+        writeln!(out, "export const FLAG_BATCHED = {};", RtcPacket::BATCHED).unwrap();
+        writeln!(
+            out,
+            "/** Set in the header flags when the header is followed by a fragment header (the message number, the fragment index and the number of fragments, each a little-endian u16) and a chunk of a larger packet. */"
+        )
+        .unwrap();
+        writeln!(out, "export const FLAG_FRAGMENT = {};", RtcPacket::FRAGMENT).unwrap();
+        writeln!(
+            out,
+            "/** Set in the header flags when the header is followed by a little-endian u16 sequence number. */"
+        )
+        .unwrap();
+        writeln!(
+            out,
+            "export const FLAG_SEQUENCED = {};",
+            RtcPacket::SEQUENCED
+        )
+        .unwrap();
+        writeln!(
+            out,
+            "/** Set in the header flags when the header (and sequence number) is followed by a little-endian u32 receipt ID. */"
+        )
+        .unwrap();
+        writeln!(out, "export const FLAG_TRACKED = {};", RtcPacket::TRACKED).unwrap();
+        writeln!(out).unwrap();
+
+        // Names are quoted, since generic and snapshot protocols are not
+        // identifiers, e.g. `SnapshotPayload<World>`
+        writeln!(out, "/** The protocol ID of each payload. */").unwrap();
+        writeln!(out, "export const MSG_IDS = {{").unwrap();
+        for protocol in self.protocols.iter() {
+            writeln!(out, "  {:?}: {},", protocol.reflect_name, protocol.msg_id).unwrap();
+        }
+        writeln!(out, "}} as const;").unwrap();
+        writeln!(out).unwrap();
+
+        writeln!(out, "/** The encoding of each payload. */").unwrap();
+        writeln!(out, "export const ENCODINGS = {{").unwrap();
+        for protocol in self.protocols.iter() {
+            writeln!(
+                out,
+                "  {:?}: {:?},",
+                protocol.reflect_name, protocol.encoding
+            )
+            .unwrap();
+        }
+        writeln!(out, "}} as const;").unwrap();
+
+        for (name, container) in self.types.iter() {
+            writeln!(out).unwrap();
+            writeln!(out, "export type {name} = {};", container_ts(container)).unwrap();
+        }
+
+        writeln!(out).unwrap();
+        writeln!(out, "/** The payload type of each protocol. */").unwrap();
+        writeln!(out, "export type Protocols = {{").unwrap();
+        for protocol in self.protocols.iter() {
+            writeln!(
+                out,
+                "  {:?}: {};",
+                protocol.reflect_name,
+                format_ts(&protocol.format)
+            )
+            .unwrap();
+        }
+        writeln!(out, "}};").unwrap();
+        out
+    }
+}
+
+/// The name of a type used by an instantiation of a generic protocol, as an
+/// identifier. The payload is named after the protocol, and other types after
+/// the protocol's type arguments.
+fn instance_name(name: &str, payload: Option<&str>, reflect_name: &str) -> String {
+    let name = if payload == Some(name) {
+        reflect_name.to_string()
+    } else {
+        let arguments = payload
+            .and_then(|payload| reflect_name.strip_prefix(payload))
+            .filter(|arguments| arguments.starts_with('<'));
+        match arguments {
+            Some(arguments) => format!("{name}{arguments}"),
+            None => format!("{name}_{reflect_name}"),
+        }
+    };
+    let mut identifier = String::with_capacity(name.len());
+    for c in name.chars() {
+        if c.is_ascii_alphanumeric() {
+            identifier.push(c);
+        } else if !identifier.ends_with('_') {
+            identifier.push('_');
+        }
+    }
+    identifier.trim_end_matches('_').to_string()
+}
+
+fn format_schema(format: &Format) -> Value {
+    match format {
+        Format::Variable(_) => json!({}),
+        Format::TypeName(name) => json!({ "$ref": format!("#/definitions/{name}") }),
+        Format::Unit => json!({ "type": "null" }),
+        Format::Bool => json!({ "type": "boolean" }),
+        Format::I8 => integer_schema(i8::MIN as i64, i8::MAX as u64),
+        Format::I16 => integer_schema(i16::MIN as i64, i16::MAX as u64),
+        Format::I32 => integer_schema(i32::MIN as i64, i32::MAX as u64),
+        Format::U8 => integer_schema(0, u8::MAX as u64),
+        Format::U16 => integer_schema(0, u16::MAX as u64),
+        Format::U32 => integer_schema(0, u32::MAX as u64),
+        Format::U64 | Format::U128 => json!({ "type": "integer", "minimum": 0 }),
+        Format::I64 | Format::I128 => json!({ "type": "integer" }),
+        Format::F32 | Format::F64 => json!({ "type": "number" }),
+        Format::Char => json!({ "type": "string", "minLength": 1, "maxLength": 1 }),
+        Format::Str => json!({ "type": "string" }),
+        Format::Bytes => json!({ "type": "array", "items": integer_schema(0, u8::MAX as u64) }),
+        Format::Option(format) => json!({ "anyOf": [format_schema(format), { "type": "null" }] }),
+        Format::Seq(format) => json!({ "type": "array", "items": format_schema(format) }),
+        Format::Map { value, .. } => {
+            json!({ "type": "object", "additionalProperties": format_schema(value) })
+        }
+        Format::Tuple(formats) => tuple_schema(formats),
+        Format::TupleArray { content, size } => json!({
+            "type": "array",
+            "items": format_schema(content),
+            "minItems": size,
+            "maxItems": size,
+        }),
+    }
+}
+
+fn integer_schema(minimum: i64, maximum: u64) -> Value {
+    json!({ "type": "integer", "minimum": minimum, "maximum": maximum })
+}
+
+fn tuple_schema(formats: &[Format]) -> Value {
+    json!({
+        "type": "array",
+        "items": formats.iter().map(format_schema).collect::<Vec<_>>(),
+        "minItems": formats.len(),
+        "maxItems": formats.len(),
+    })
+}
+
+fn struct_schema(fields: &[Named<Format>]) -> Value {
+    let properties: Map<String, Value> = fields
+        .iter()
+        .map(|field| (field.name.clone(), format_schema(&field.value)))
+        .collect();
+    json!({
+        "type": "object",
+        "properties": properties,
+        "required": fields.iter().map(|field| &field.name).collect::<Vec<_>>(),
+        "additionalProperties": false,
+    })
+}
+
+fn container_schema(container: &ContainerFormat) -> Value {
+    match container {
+        ContainerFormat::UnitStruct => json!({ "type": "null" }),
+        ContainerFormat::NewTypeStruct(format) => format_schema(format),
+        ContainerFormat::TupleStruct(formats) => tuple_schema(formats),
+        ContainerFormat::Struct(fields) => struct_schema(fields),
+        // Enums are externally tagged, as serde does by default
+        ContainerFormat::Enum(variants) => {
+            let variants: Vec<Value> = variants
+                .values()
+                .map(|variant| {
+                    let value = match &variant.value {
+                        VariantFormat::Variable(_) => json!({}),
+                        VariantFormat::Unit => return json!({ "const": variant.name }),
+                        VariantFormat::NewType(format) => format_schema(format),
+                        VariantFormat::Tuple(formats) => tuple_schema(formats),
+                        VariantFormat::Struct(fields) => struct_schema(fields),
+                    };
+                    json!({
+                        "type": "object",
+                        "properties": { variant.name.clone(): value },
+                        "required": [variant.name],
+                        "additionalProperties": false,
+                    })
+                })
+                .collect();
+            json!({ "oneOf": variants })
+        }
+    }
+}
+
+fn format_ts(format: &Format) -> String {
+    match format {
+        Format::Variable(_) => "unknown".to_string(),
+        Format::TypeName(name) => name.clone(),
+        Format::Unit => "null".to_string(),
+        Format::Bool => "boolean".to_string(),
+        Format::I8
+        | Format::I16
+        | Format::I32
+        | Format::I64
+        | Format::I128
+        | Format::U8
+        | Format::U16
+        | Format::U32
+        | Format::U64
+        | Format::U128
+        | Format::F32
+        | Format::F64 => "number".to_string(),
+        Format::Char | Format::Str => "string".to_string(),
+        Format::Bytes => "number[]".to_string(),
+        Format::Option(format) => format!("{} | null", format_ts(format)),
+        Format::Seq(format)
+        | Format::TupleArray {
+            content: format, ..
+        } => {
+            format!("Array<{}>", format_ts(format))
+        }
+        Format::Map { value, .. } => format!("Record<string, {}>", format_ts(value)),
+        Format::Tuple(formats) => tuple_ts(formats),
+    }
+}
+
+fn tuple_ts(formats: &[Format]) -> String {
+    let formats: Vec<String> = formats.iter().map(format_ts).collect();
+    format!("[{}]", formats.join(", "))
+}
+
+fn struct_ts(fields: &[Named<Format>]) -> String {
+    let fields: Vec<String> = fields
+        .iter()
+        .map(|field| format!("{}: {}", field.name, format_ts(&field.value)))
+        .collect();
+    format!("{{ {} }}", fields.join("; "))
+}
+
+fn container_ts(container: &ContainerFormat) -> String {
+    match container {
+        ContainerFormat::UnitStruct => "null".to_string(),
+        ContainerFormat::NewTypeStruct(format) => format_ts(format),
+        ContainerFormat::TupleStruct(formats) => tuple_ts(formats),
+        ContainerFormat::Struct(fields) => struct_ts(fields),
+        // Enums are externally tagged, as serde does by default
+        ContainerFormat::Enum(variants) => {
+            let variants: Vec<String> = variants
+                .values()
+                .map(|variant| {
+                    let value = match &variant.value {
+                        VariantFormat::Variable(_) => "unknown".to_string(),
+                        VariantFormat::Unit => return format!("{:?}", variant.name),
+                        VariantFormat::NewType(format) => format_ts(format),
+                        VariantFormat::Tuple(formats) => tuple_ts(formats),
+                        VariantFormat::Struct(fields) => struct_ts(fields),
+                    };
+                    format!("{{ {}: {} }}", variant.name, value)
+                })
+                .collect();
+            variants.join(" | ")
+        }
+    }
+}
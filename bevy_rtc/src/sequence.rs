@@ -0,0 +1,159 @@
+use crate::protocol::RtcPacket;
+use bevy::{prelude::*, utils::HashMap};
+use bevy_matchbox::matchbox_socket::{Packet, PeerId};
+
+/// The sequence numbers of sequenced protocols, by protocol ID and peer.
+#[derive(Resource, Default, Debug)]
+pub(crate) struct Sequences {
+    /// The next sequence number to send.
+    sent: HashMap<(u16, PeerId), u16>,
+    /// The newest sequence number accepted.
+    received: HashMap<(u16, PeerId), u16>,
+}
+
+impl Sequences {
+    /// Returns the next sequence number of a protocol to a peer.
+    pub fn next(&mut self, id: u16, peer: PeerId) -> u16 {
+        let next = self.sent.entry((id, peer)).or_default();
+        let seq = *next;
+        *next = next.wrapping_add(1);
+        seq
+    }
+
+    /// Accept a sequence number received from a peer, returning false if it is
+    /// not newer than the newest one accepted before.
+    pub fn accept(&mut self, id: u16, peer: PeerId, seq: u16) -> bool {
+        match self.received.get_mut(&(id, peer)) {
+            Some(newest) if !is_newer(seq, *newest) => false,
+            Some(newest) => {
+                *newest = seq;
+                true
+            }
+            None => {
+                self.received.insert((id, peer), seq);
+                true
+            }
+        }
+    }
+
+    /// Forget the sequence numbers of a disconnected peer.
+    #[cfg(feature = "server")]
+    pub fn forget(&mut self, peer: PeerId) {
+        self.sent.retain(|(_, p), _| *p != peer);
+        self.received.retain(|(_, p), _| *p != peer);
+    }
+
+    /// Forget the sequence numbers of every peer.
+    #[cfg(feature = "client")]
+    pub fn clear(&mut self) {
+        self.sent.clear();
+        self.received.clear();
+    }
+}
+
+/// Returns true if `seq` was sent after `newest`, allowing for wrapping as long
+/// as they are less than half the sequence space apart.
+fn is_newer(seq: u16, newest: u16) -> bool {
+    (seq.wrapping_sub(newest) as i16) > 0
+}
+
+/// Insert a sequence number after the header of a packet.
+pub(crate) fn stamp(packet: Packet, seq: u16) -> Packet {
+    RtcPacket::insert_extension(packet, RtcPacket::SEQUENCED, &seq.to_le_bytes())
+}
+
+/// Remove the sequence number of a sequenced packet, returning `None` if it is
+/// too short to have one.
+pub(crate) fn unstamp(packet: &RtcPacket) -> Option<(u16, Packet)> {
+    let (seq, packet) = packet.remove_extension(RtcPacket::SEQUENCED)?;
+    Some((u16::from_le_bytes(seq), packet))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::utils::Uuid;
+
+    const PEER: PeerId = PeerId(Uuid::from_u128(1));
+    const OTHER: PeerId = PeerId(Uuid::from_u128(2));
+
+    #[test]
+    fn newer_allows_for_wrapping() {
+        assert!(is_newer(1, 0));
+        assert!(is_newer(0, 65535));
+        assert!(is_newer(10, 65530));
+        assert!(!is_newer(0, 0));
+        assert!(!is_newer(0, 1));
+        assert!(!is_newer(65535, 0));
+        // Half the sequence space apart is too far to tell
+        assert!(!is_newer(32768, 0));
+        assert!(is_newer(32767, 0));
+    }
+
+    #[test]
+    fn first_sequence_number_is_accepted() {
+        let mut sequences = Sequences::default();
+        assert!(sequences.accept(1, PEER, 500));
+    }
+
+    #[test]
+    fn stale_sequence_numbers_are_dropped() {
+        let mut sequences = Sequences::default();
+        assert!(sequences.accept(1, PEER, 5));
+        assert!(!sequences.accept(1, PEER, 4));
+        assert!(sequences.accept(1, PEER, 7));
+        assert!(!sequences.accept(1, PEER, 6));
+    }
+
+    #[test]
+    fn duplicate_sequence_numbers_are_dropped() {
+        let mut sequences = Sequences::default();
+        assert!(sequences.accept(1, PEER, 5));
+        assert!(!sequences.accept(1, PEER, 5));
+    }
+
+    #[test]
+    fn sequence_numbers_wrap_around() {
+        let mut sequences = Sequences::default();
+        assert!(sequences.accept(1, PEER, 65534));
+        assert!(sequences.accept(1, PEER, 65535));
+        assert!(sequences.accept(1, PEER, 0));
+        assert!(!sequences.accept(1, PEER, 65535));
+        assert!(sequences.accept(1, PEER, 1));
+
+        let mut sequences = Sequences::default();
+        for _ in 0..65535 {
+            sequences.next(1, PEER);
+        }
+        assert_eq!(sequences.next(1, PEER), 65535);
+        assert_eq!(sequences.next(1, PEER), 0);
+    }
+
+    #[test]
+    fn peers_and_protocols_are_sequenced_separately() {
+        let mut sequences = Sequences::default();
+        assert!(sequences.accept(1, PEER, 100));
+        assert!(sequences.accept(1, OTHER, 5));
+        assert!(sequences.accept(2, PEER, 5));
+        assert!(!sequences.accept(1, PEER, 5));
+
+        assert_eq!(sequences.next(1, PEER), 0);
+        assert_eq!(sequences.next(1, PEER), 1);
+        assert_eq!(sequences.next(1, OTHER), 0);
+        assert_eq!(sequences.next(2, PEER), 0);
+    }
+
+    #[cfg(feature = "server")]
+    #[test]
+    fn forgotten_peers_start_over() {
+        let mut sequences = Sequences::default();
+        assert!(sequences.accept(1, PEER, 100));
+        assert!(sequences.accept(1, OTHER, 100));
+        sequences.next(1, PEER);
+
+        sequences.forget(PEER);
+        assert!(sequences.accept(1, PEER, 5));
+        assert!(!sequences.accept(1, OTHER, 5));
+        assert_eq!(sequences.next(1, PEER), 0);
+    }
+}
@@ -1,5 +1,5 @@
 use super::router::{IncomingMessages, OutgoingMessages};
-use crate::protocol::Protocol;
+use crate::{protocol::Protocol, receipt::MessageHandle};
 use bevy::{ecs::system::SystemParam, prelude::*};
 use bevy_matchbox::prelude::PeerId;
 
@@ -9,6 +9,8 @@ pub struct RtcServer<'w, M: Protocol> {
     // Option is none if it's send-only or read-only.
     pub(crate) incoming: Option<ResMut<'w, IncomingMessages<M>>>,
     pub(crate) outgoing: Option<ResMut<'w, OutgoingMessages<M>>>,
+    /// The clock of the send rate limiter.
+    pub(crate) time: Res<'w, Time<Real>>,
 }
 
 impl<'w, M: Protocol> RtcServer<'w, M> {
@@ -40,16 +42,22 @@ impl<'w, M: Protocol> RtcServer<'w, M> {
         }
     }
 
-    /// Consumes all messages in the buffer and iterate on them.
+    /// Consumes all messages in the buffer and iterate on them. Tracked
+    /// messages are acknowledged to their sender.
     pub fn read(&mut self) -> Vec<(PeerId, M)> {
         if let Some(ref mut incoming) = self.incoming {
-            incoming
-                .messages
-                .drain()
-                .fold(vec![], |mut v, (peer, payloads)| {
-                    v.extend(payloads.into_iter().map(|p| (peer, p)));
-                    v
-                })
+            let IncomingMessages {
+                messages, receipts, ..
+            } = &mut **incoming;
+            messages.drain().fold(vec![], |mut v, (peer, payloads)| {
+                for (payload, receipt) in payloads {
+                    if let Some(receipt) = receipt {
+                        receipts.push((peer, receipt));
+                    }
+                    v.push((peer, payload));
+                }
+                v
+            })
         } else {
             panic!(
                 "Attempting to read from `{}` is not allowed, it is registered write only.",
@@ -59,6 +67,10 @@ impl<'w, M: Protocol> RtcServer<'w, M> {
     }
 
     /// Send a payload to all connected peers with reliability.
+    ///
+    /// If the protocol is bound to a reliable channel with
+    /// [`ProtocolOptions::channel`](crate::protocol::ProtocolOptions::channel),
+    /// the payload is sent on that channel instead.
     pub fn reliable_to_all(&mut self, message: M) {
         if let Some(ref mut outgoing) = self.outgoing {
             outgoing.reliable_to_all.push(message);
@@ -73,7 +85,7 @@ impl<'w, M: Protocol> RtcServer<'w, M> {
     /// Send a payload to all connected peers with no expectation of delivery.
     pub fn unreliable_to_all(&mut self, message: M) {
         if let Some(ref mut outgoing) = self.outgoing {
-            outgoing.reliable_to_all.push(message);
+            outgoing.unreliable_to_all.push(message);
         } else {
             panic!(
                 "Attempting to write `{}` is not allowed, it is registered read only.",
@@ -83,6 +95,10 @@ impl<'w, M: Protocol> RtcServer<'w, M> {
     }
 
     /// Send a payload to a peer with reliability.
+    ///
+    /// If the protocol is bound to a reliable channel with
+    /// [`ProtocolOptions::channel`](crate::protocol::ProtocolOptions::channel),
+    /// the payload is sent on that channel instead.
     pub fn reliable_to_peer(&mut self, peer_id: PeerId, message: M) {
         if let Some(ref mut outgoing) = self.outgoing {
             outgoing.reliable_to_peer.push((peer_id, message));
@@ -94,6 +110,27 @@ impl<'w, M: Protocol> RtcServer<'w, M> {
         }
     }
 
+    /// Send a payload to a peer with reliability, and track when the peer
+    /// reads it. An [`RtcDelivered`](crate::RtcDelivered) event with the
+    /// returned handle is sent once it does, or an
+    /// [`RtcDeliveryTimeout`](crate::RtcDeliveryTimeout) event if it does not
+    /// within the plugin's `delivery_timeout`, or if the payload fails to
+    /// encode.
+    ///
+    /// If the protocol is bound to a reliable channel with
+    /// [`ProtocolOptions::channel`](crate::protocol::ProtocolOptions::channel),
+    /// the payload is sent on that channel instead.
+    pub fn reliable_to_peer_tracked(&mut self, peer_id: PeerId, message: M) -> MessageHandle {
+        if let Some(ref mut outgoing) = self.outgoing {
+            outgoing.track(peer_id, message)
+        } else {
+            panic!(
+                "Attempting to write `{}` is not allowed, it is registered read only.",
+                M::reflect_name()
+            );
+        }
+    }
+
     /// Send a payload to a peer with no expectation of delivery.
     pub fn unreliable_to_peer(&mut self, peer_id: PeerId, message: M) {
         if let Some(ref mut outgoing) = self.outgoing {
@@ -107,6 +144,10 @@ impl<'w, M: Protocol> RtcServer<'w, M> {
     }
 
     /// Send a payload to all connected peers except one with reliability.
+    ///
+    /// If the protocol is bound to a reliable channel with
+    /// [`ProtocolOptions::channel`](crate::protocol::ProtocolOptions::channel),
+    /// the payload is sent on that channel instead.
     pub fn reliable_to_all_except(&mut self, peer_id: PeerId, message: M) {
         if let Some(ref mut outgoing) = self.outgoing {
             outgoing.reliable_to_all_except.push((peer_id, message));
@@ -131,11 +172,93 @@ impl<'w, M: Protocol> RtcServer<'w, M> {
         }
     }
 
+    /// Send a payload to all connected peers with reliability, in any order.
+    pub fn reliable_unordered_to_all(&mut self, message: M) {
+        if let Some(ref mut outgoing) = self.outgoing {
+            outgoing.reliable_unordered_to_all.push(message);
+        } else {
+            panic!(
+                "Attempting to write `{}` is not allowed, it is registered read only.",
+                M::reflect_name()
+            );
+        }
+    }
+
+    /// Send a payload to a peer with reliability, in any order.
+    pub fn reliable_unordered_to_peer(&mut self, peer_id: PeerId, message: M) {
+        if let Some(ref mut outgoing) = self.outgoing {
+            outgoing.reliable_unordered_to_peer.push((peer_id, message));
+        } else {
+            panic!(
+                "Attempting to write `{}` is not allowed, it is registered read only.",
+                M::reflect_name()
+            );
+        }
+    }
+
+    /// Send a payload to all connected peers except one with reliability, in any order.
+    pub fn reliable_unordered_to_all_except(&mut self, peer_id: PeerId, message: M) {
+        if let Some(ref mut outgoing) = self.outgoing {
+            outgoing
+                .reliable_unordered_to_all_except
+                .push((peer_id, message));
+        } else {
+            panic!(
+                "Attempting to write `{}` is not allowed, it is registered read only.",
+                M::reflect_name()
+            );
+        }
+    }
+
+    /// Send a payload to all connected peers with no expectation of delivery,
+    /// in any order.
+    pub fn unordered_unreliable_to_all(&mut self, message: M) {
+        if let Some(ref mut outgoing) = self.outgoing {
+            outgoing.unordered_unreliable_to_all.push(message);
+        } else {
+            panic!(
+                "Attempting to write `{}` is not allowed, it is registered read only.",
+                M::reflect_name()
+            );
+        }
+    }
+
+    /// Send a payload to a peer with no expectation of delivery, in any order.
+    pub fn unordered_unreliable_to_peer(&mut self, peer_id: PeerId, message: M) {
+        if let Some(ref mut outgoing) = self.outgoing {
+            outgoing
+                .unordered_unreliable_to_peer
+                .push((peer_id, message));
+        } else {
+            panic!(
+                "Attempting to write `{}` is not allowed, it is registered read only.",
+                M::reflect_name()
+            );
+        }
+    }
+
+    /// Send a payload to all connected peers except one with no expectation of
+    /// delivery, in any order.
+    pub fn unordered_unreliable_to_all_except(&mut self, peer_id: PeerId, message: M) {
+        if let Some(ref mut outgoing) = self.outgoing {
+            outgoing
+                .unordered_unreliable_to_all_except
+                .push((peer_id, message));
+        } else {
+            panic!(
+                "Attempting to write `{}` is not allowed, it is registered read only.",
+                M::reflect_name()
+            );
+        }
+    }
+
     /// Send a payload to all connected peers with reliability. The payload is
     /// created with lazy behavior, only when the send rate allows.
     pub fn reliable_to_all_with(&mut self, message_fn: impl Fn() -> M) {
         if let Some(ref mut outgoing) = self.outgoing {
-            outgoing.reliable_to_all.push(message_fn());
+            if outgoing.limiter.try_acquire(None, self.time.elapsed()) {
+                outgoing.reliable_to_all.push(message_fn());
+            }
         } else {
             panic!(
                 "Attempting to write `{}` is not allowed, it is registered read only.",
@@ -149,7 +272,9 @@ impl<'w, M: Protocol> RtcServer<'w, M> {
     /// allows.
     pub fn unreliable_to_all_with(&mut self, message_fn: impl Fn() -> M) {
         if let Some(ref mut outgoing) = self.outgoing {
-            outgoing.unreliable_to_all.push(message_fn());
+            if outgoing.limiter.try_acquire(None, self.time.elapsed()) {
+                outgoing.unreliable_to_all.push(message_fn());
+            }
         } else {
             panic!(
                 "Attempting to write `{}` is not allowed, it is registered read only.",
@@ -162,7 +287,12 @@ impl<'w, M: Protocol> RtcServer<'w, M> {
     /// created with lazy behavior, only when the send rate allows.
     pub fn reliable_to_peer_with(&mut self, peer_id: PeerId, message_fn: impl Fn() -> M) {
         if let Some(ref mut outgoing) = self.outgoing {
-            outgoing.reliable_to_peer.push((peer_id, message_fn()));
+            if outgoing
+                .limiter
+                .try_acquire(Some(peer_id), self.time.elapsed())
+            {
+                outgoing.reliable_to_peer.push((peer_id, message_fn()));
+            }
         } else {
             panic!(
                 "Attempting to write `{}` is not allowed, it is registered read only.",
@@ -176,7 +306,12 @@ impl<'w, M: Protocol> RtcServer<'w, M> {
     /// allows.
     pub fn unreliable_to_peer_with(&mut self, peer_id: PeerId, message_fn: impl Fn() -> M) {
         if let Some(ref mut outgoing) = self.outgoing {
-            outgoing.unreliable_to_peer.push((peer_id, message_fn()));
+            if outgoing
+                .limiter
+                .try_acquire(Some(peer_id), self.time.elapsed())
+            {
+                outgoing.unreliable_to_peer.push((peer_id, message_fn()));
+            }
         } else {
             panic!(
                 "Attempting to write `{}` is not allowed, it is registered read only.",
@@ -190,9 +325,11 @@ impl<'w, M: Protocol> RtcServer<'w, M> {
     /// allows.
     pub fn reliable_to_all_except_with(&mut self, peer_id: PeerId, message_fn: impl Fn() -> M) {
         if let Some(ref mut outgoing) = self.outgoing {
-            outgoing
-                .reliable_to_all_except
-                .push((peer_id, message_fn()));
+            if outgoing.limiter.try_acquire(None, self.time.elapsed()) {
+                outgoing
+                    .reliable_to_all_except
+                    .push((peer_id, message_fn()));
+            }
         } else {
             panic!(
                 "Attempting to write `{}` is not allowed, it is registered read only.",
@@ -206,9 +343,11 @@ impl<'w, M: Protocol> RtcServer<'w, M> {
     /// send rate allows.
     pub fn unreliable_to_all_except_with(&mut self, peer_id: PeerId, message_fn: impl Fn() -> M) {
         if let Some(ref mut outgoing) = self.outgoing {
-            outgoing
-                .unreliable_to_all_except
-                .push((peer_id, message_fn()));
+            if outgoing.limiter.try_acquire(None, self.time.elapsed()) {
+                outgoing
+                    .unreliable_to_all_except
+                    .push((peer_id, message_fn()));
+            }
         } else {
             panic!(
                 "Attempting to write `{}` is not allowed, it is registered read only.",
@@ -216,4 +355,154 @@ impl<'w, M: Protocol> RtcServer<'w, M> {
             );
         }
     }
+
+    /// Send a payload to all connected peers with reliability, in any order.
+    /// The payload is created with lazy behavior, only when the send rate
+    /// allows.
+    pub fn reliable_unordered_to_all_with(&mut self, message_fn: impl Fn() -> M) {
+        if let Some(ref mut outgoing) = self.outgoing {
+            if outgoing.limiter.try_acquire(None, self.time.elapsed()) {
+                outgoing.reliable_unordered_to_all.push(message_fn());
+            }
+        } else {
+            panic!(
+                "Attempting to write `{}` is not allowed, it is registered read only.",
+                M::reflect_name()
+            );
+        }
+    }
+
+    /// Send a payload to a peer with reliability, in any order. The payload is
+    /// created with lazy behavior, only when the send rate allows.
+    pub fn reliable_unordered_to_peer_with(&mut self, peer_id: PeerId, message_fn: impl Fn() -> M) {
+        if let Some(ref mut outgoing) = self.outgoing {
+            if outgoing
+                .limiter
+                .try_acquire(Some(peer_id), self.time.elapsed())
+            {
+                outgoing
+                    .reliable_unordered_to_peer
+                    .push((peer_id, message_fn()));
+            }
+        } else {
+            panic!(
+                "Attempting to write `{}` is not allowed, it is registered read only.",
+                M::reflect_name()
+            );
+        }
+    }
+
+    /// Send a payload to all connected peers except one with reliability, in
+    /// any order. The payload is created with lazy behavior, only when the send
+    /// rate allows.
+    pub fn reliable_unordered_to_all_except_with(
+        &mut self,
+        peer_id: PeerId,
+        message_fn: impl Fn() -> M,
+    ) {
+        if let Some(ref mut outgoing) = self.outgoing {
+            if outgoing.limiter.try_acquire(None, self.time.elapsed()) {
+                outgoing
+                    .reliable_unordered_to_all_except
+                    .push((peer_id, message_fn()));
+            }
+        } else {
+            panic!(
+                "Attempting to write `{}` is not allowed, it is registered read only.",
+                M::reflect_name()
+            );
+        }
+    }
+
+    /// Send a payload to all connected peers with no expectation of delivery,
+    /// in any order. The payload is created with lazy behavior, only when the
+    /// send rate allows.
+    pub fn unordered_unreliable_to_all_with(&mut self, message_fn: impl Fn() -> M) {
+        if let Some(ref mut outgoing) = self.outgoing {
+            if outgoing.limiter.try_acquire(None, self.time.elapsed()) {
+                outgoing.unordered_unreliable_to_all.push(message_fn());
+            }
+        } else {
+            panic!(
+                "Attempting to write `{}` is not allowed, it is registered read only.",
+                M::reflect_name()
+            );
+        }
+    }
+
+    /// Send a payload to a peer with no expectation of delivery, in any order.
+    /// The payload is created with lazy behavior, only when the send rate
+    /// allows.
+    pub fn unordered_unreliable_to_peer_with(
+        &mut self,
+        peer_id: PeerId,
+        message_fn: impl Fn() -> M,
+    ) {
+        if let Some(ref mut outgoing) = self.outgoing {
+            if outgoing
+                .limiter
+                .try_acquire(Some(peer_id), self.time.elapsed())
+            {
+                outgoing
+                    .unordered_unreliable_to_peer
+                    .push((peer_id, message_fn()));
+            }
+        } else {
+            panic!(
+                "Attempting to write `{}` is not allowed, it is registered read only.",
+                M::reflect_name()
+            );
+        }
+    }
+
+    /// Send a payload to all connected peers except one with no expectation of
+    /// delivery, in any order. The payload is created with lazy behavior, only
+    /// when the send rate allows.
+    pub fn unordered_unreliable_to_all_except_with(
+        &mut self,
+        peer_id: PeerId,
+        message_fn: impl Fn() -> M,
+    ) {
+        if let Some(ref mut outgoing) = self.outgoing {
+            if outgoing.limiter.try_acquire(None, self.time.elapsed()) {
+                outgoing
+                    .unordered_unreliable_to_all_except
+                    .push((peer_id, message_fn()));
+            }
+        } else {
+            panic!(
+                "Attempting to write `{}` is not allowed, it is registered read only.",
+                M::reflect_name()
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::AddServerProtocolExt;
+    use bevy::ecs::system::RunSystemOnce;
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Protocol, Serialize, Deserialize, Debug, Clone)]
+    #[protocol(crate = "crate", id = 1)]
+    struct Position {
+        x: f32,
+    }
+
+    #[test]
+    fn unreliable_to_all_is_queued_unreliably() {
+        let mut app = App::new();
+        app.add_server_wo_protocol::<Position>();
+
+        app.world
+            .run_system_once(|mut server: RtcServer<Position>| {
+                server.unreliable_to_all(Position { x: 1.0 });
+            });
+
+        let outgoing = app.world.resource::<OutgoingMessages<Position>>();
+        assert_eq!(outgoing.unreliable_to_all.len(), 1);
+        assert!(outgoing.reliable_to_all.is_empty());
+    }
 }
@@ -1,6 +1,8 @@
 use bevy::ecs::event::Event;
 use bevy_matchbox::matchbox_socket::PeerId;
 
+use crate::ProtocolMismatch;
+
 /// Socket events that are possible to subscribe to in Bevy
 #[derive(Debug, Clone, Event)]
 pub enum RtcServerEvent {
@@ -10,4 +12,16 @@ pub enum RtcServerEvent {
     ClientJoined(PeerId),
     /// A peer has left this server
     ClientLeft(PeerId),
+    /// A peer registered protocols or an encoding incompatible with this
+    /// server. The server stops reading from and sending to the peer, and asks
+    /// it to disconnect.
+    ProtocolMismatch(PeerId, ProtocolMismatch),
+    /// A peer did not send its protocol manifest within the
+    /// `handshake_timeout` of the plugin, and is refused like a mismatch.
+    HandshakeTimeout(PeerId),
+    /// A peer fell too far behind with [`BackpressurePolicy::Disconnect`].
+    /// Nothing more is sent to it, and it is asked to disconnect itself.
+    ///
+    /// [`BackpressurePolicy::Disconnect`]: crate::BackpressurePolicy::Disconnect
+    ClientTooSlow(PeerId),
 }
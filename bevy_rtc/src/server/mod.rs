@@ -2,6 +2,7 @@ mod client;
 mod events;
 mod plugin;
 mod router;
+mod snapshot;
 mod state;
 mod systems;
 
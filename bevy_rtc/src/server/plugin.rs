@@ -1,6 +1,15 @@
 use crate::{
-    events::SocketRecvEvent,
+    batching::Batching,
+    channel::{ChannelConfig, Channels},
+    compression::Compression,
+    dispatch::{dispatch_packets, ReceivedPackets},
+    events::{RtcDecodeError, RtcDelivered, RtcDeliveryTimeout, RtcSendError, SocketRecvEvent},
+    fragmentation::{expire_fragments, Fragmentation, FragmentationStats, Reassembly},
     latency::LatencyTracerPayload,
+    outbox::{flush_outbox, Backpressure, Outbox, RtcSendQueues},
+    receipt::{expire_receipts, DeliveryReceipt, Receipts, DEFAULT_DELIVERY_TIMEOUT},
+    registry::RtcProtocolRegistry,
+    sequence::Sequences,
     socket::{common_socket_reader, RtcSocket},
     transport_encoding::TransportEncoding,
 };
@@ -8,7 +17,10 @@ use bevy::{prelude::*, time::common_conditions::on_timer};
 use instant::Duration;
 use std::net::Ipv4Addr;
 
-use super::{systems, AddServerProtocolExt, RtcServerEvent, RtcServerState, RtcServerStatus};
+use super::{
+    router::IncomingMessages, state::DEFAULT_HANDSHAKE_TIMEOUT, systems, AddServerProtocolExt,
+    RtcServerEvent, RtcServerState, RtcServerStatus,
+};
 
 /// A plugin to serve a WebRTC server.
 pub struct RtcServerPlugin {
@@ -19,18 +31,91 @@ pub struct RtcServerPlugin {
     /// # Available encodings:
     /// - JSON: with the `json` cargo feature
     /// - Binary: with the `binary` cargo feature
+    /// - MessagePack: with the `msgpack` cargo feature
+    /// - Postcard: with the `postcard` cargo feature
+    /// - BitPacked: bit-level packing which honors [`Quantized`](crate::quantize::Quantized) precision
+    /// - Custom: any user-defined [`Codec`](crate::codec::Codec)
     pub encoding: TransportEncoding,
+    /// Compress large payloads before sending them, or `None` to never compress.
+    /// Compressed payloads are always decompressed when received.
+    pub compression: Option<Compression>,
+    /// Batch small payloads into fewer datagrams, or `None` to send every
+    /// payload in its own datagram. Batches are always unpacked when received.
+    pub batching: Option<Batching>,
+    /// Split large unreliable payloads into fragments, or `None` to send them
+    /// whole. Fragments are always reassembled when received.
+    pub fragmentation: Option<Fragmentation>,
+    /// Data channels to open in addition to the built-in channels of the
+    /// `reliable_*`, `unreliable_*`, `reliable_unordered_*` and
+    /// `unordered_unreliable_*` methods. Protocols are bound to them by name with
+    /// [`ProtocolOptions::channel`](crate::protocol::ProtocolOptions::channel).
+    /// Peers must declare the same channels.
+    pub channels: Vec<ChannelConfig>,
+    /// How long to wait for a peer to read a tracked payload before sending
+    /// [`RtcDeliveryTimeout`](crate::RtcDeliveryTimeout).
+    pub delivery_timeout: Duration,
+    /// How long to wait for a joined peer's protocol manifest before refusing
+    /// it with [`RtcServerEvent::HandshakeTimeout`].
+    pub handshake_timeout: Duration,
+    /// Limit the bytes sent to each peer on each channel, queueing or dropping
+    /// packets over the limit, or `None` to send every packet immediately.
+    /// Queues can be watched in [`RtcSendQueues`](crate::RtcSendQueues).
+    pub backpressure: Option<Backpressure>,
+}
+
+impl Default for RtcServerPlugin {
+    fn default() -> Self {
+        Self {
+            port: 3536,
+            encoding: TransportEncoding::Json,
+            compression: None,
+            batching: None,
+            fragmentation: None,
+            channels: vec![],
+            delivery_timeout: DEFAULT_DELIVERY_TIMEOUT,
+            handshake_timeout: DEFAULT_HANDSHAKE_TIMEOUT,
+            backpressure: None,
+        }
+    }
 }
 
 impl Plugin for RtcServerPlugin {
     fn build(&self, app: &mut App) {
-        app.insert_resource(self.encoding)
+        if let Some(compression) = self.compression {
+            app.insert_resource(compression);
+        }
+        if let Some(batching) = self.batching {
+            app.insert_resource(batching);
+        }
+        if let Some(fragmentation) = self.fragmentation {
+            app.insert_resource(fragmentation);
+        }
+        if let Some(backpressure) = self.backpressure {
+            app.insert_resource(backpressure);
+        }
+        let channels = Channels::new(&self.channels);
+        app.insert_resource(self.encoding.clone())
+            .insert_resource(RtcSendQueues::new(&channels))
+            .insert_resource(channels)
+            .init_resource::<RtcProtocolRegistry>()
+            .init_resource::<ReceivedPackets>()
+            .init_resource::<Outbox>()
+            .init_resource::<Sequences>()
+            .insert_resource(Receipts::new(self.delivery_timeout))
+            .init_resource::<Reassembly>()
+            .init_resource::<FragmentationStats>()
             .add_event::<SocketRecvEvent>()
             .add_event::<RtcServerEvent>()
+            .add_event::<RtcSendError>()
+            .add_event::<RtcDecodeError>()
+            .add_event::<RtcDelivered>()
+            .add_event::<RtcDeliveryTimeout>()
             .add_server_rw_protocol::<LatencyTracerPayload>(2)
+            .add_server_rw_unbounded_protocol::<DeliveryReceipt>()
             .init_state::<RtcServerStatus>()
             .insert_resource(RtcServerState::new(
                 (Ipv4Addr::UNSPECIFIED, self.port).into(),
+                self.handshake_timeout,
             ))
             .add_systems(
                 Startup,
@@ -42,7 +127,12 @@ impl Plugin for RtcServerPlugin {
                 First,
                 (
                     common_socket_reader,
+                    expire_fragments,
+                    dispatch_packets,
                     systems::server_event_writer,
+                    systems::send_handshakes,
+                    systems::read_handshakes,
+                    systems::expire_handshakes,
                     systems::calculate_latency,
                 )
                     .chain()
@@ -55,6 +145,27 @@ impl Plugin for RtcServerPlugin {
                     systems::send_latency_tracers.run_if(on_timer(Duration::from_millis(100))),
                 )
                     .run_if(in_state(RtcServerStatus::Ready)),
-            );
+            )
+            .add_systems(
+                First,
+                (systems::read_delivery_receipts, expire_receipts)
+                    .chain()
+                    .after(IncomingMessages::<DeliveryReceipt>::receive_payloads)
+                    .run_if(resource_exists::<RtcSocket>),
+            )
+            .add_systems(
+                Last,
+                (flush_outbox, systems::hang_up_slow_clients)
+                    .chain()
+                    .run_if(resource_exists::<RtcSocket>),
+            )
+            .add_systems(Last, systems::count_decode_failures);
+    }
+
+    fn finish(&self, app: &mut App) {
+        let channels = app.world.resource::<Channels>();
+        app.world
+            .resource::<RtcProtocolRegistry>()
+            .check_channels(channels);
     }
 }
@@ -1,9 +1,16 @@
 mod receive;
 mod send;
 
+use super::snapshot::{collect_snapshot_acks, ServerSnapshots, SnapshotAcks};
 use crate::{
-    protocol::Protocol,
-    socket::{common_socket_reader, RtcSocket},
+    dispatch::dispatch_packets,
+    outbox::flush_outbox,
+    protocol::{Protocol, ProtocolDirection, ProtocolOptions},
+    rate::SendLimiter,
+    receipt::DeliveryReceipt,
+    registry::RtcProtocolRegistry,
+    snapshot::{Snapshot, SnapshotAck, SnapshotPayload, SNAPSHOT_HISTORY},
+    socket::RtcSocket,
 };
 use bevy::{prelude::*, utils::hashbrown::HashMap};
 
@@ -25,6 +32,13 @@ pub trait AddServerProtocolExt {
     /// Register a protocol for sending and receiving. Use a growable buffer
     /// for reading.
     fn add_server_rw_unbounded_protocol<M: Protocol>(&mut self) -> &mut Self;
+    /// Register a snapshot that is sent to clients as a delta against the
+    /// latest snapshot each client acknowledged. Send snapshots with
+    /// `RtcServer<SnapshotPayload<S>>`.
+    fn add_server_snapshot_protocol<S: Snapshot>(&mut self) -> &mut Self;
+    /// Set the options of a protocol, e.g. to encode it differently than the
+    /// plugin's transport encoding. The client must use the same options.
+    fn configure_server_protocol<M: Protocol>(&mut self, options: ProtocolOptions) -> &mut Self;
 }
 
 impl AddServerProtocolExt for App {
@@ -32,17 +46,35 @@ impl AddServerProtocolExt for App {
         if self.world.contains_resource::<OutgoingMessages<M>>() {
             panic!("server already contains resource: {}", M::reflect_name());
         }
+        // Read by the send rate limiter of `RtcServer`
+        self.init_resource::<Time<Real>>();
+        let mut registry = self
+            .world
+            .get_resource_or_insert_with(RtcProtocolRegistry::default);
+        registry.register::<M>(ProtocolDirection::WriteOnly, None);
+        let send_rate = registry.send_rate(M::id());
         self.insert_resource(OutgoingMessages::<M> {
             reliable_to_all: vec![],
             unreliable_to_all: vec![],
+            reliable_unordered_to_all: vec![],
+            unordered_unreliable_to_all: vec![],
             reliable_to_all_except: vec![],
             unreliable_to_all_except: vec![],
+            reliable_unordered_to_all_except: vec![],
+            unordered_unreliable_to_all_except: vec![],
             reliable_to_peer: vec![],
             unreliable_to_peer: vec![],
+            reliable_unordered_to_peer: vec![],
+            unordered_unreliable_to_peer: vec![],
+            reliable_to_peer_tracked: vec![],
+            next_receipt: 0,
+            limiter: SendLimiter::new(send_rate),
         })
         .add_systems(
             Last,
-            OutgoingMessages::<M>::send_payloads.run_if(resource_exists::<RtcSocket>),
+            OutgoingMessages::<M>::send_payloads
+                .before(flush_outbox)
+                .run_if(resource_exists::<RtcSocket>),
         );
 
         self
@@ -52,14 +84,25 @@ impl AddServerProtocolExt for App {
         if self.world.contains_resource::<IncomingMessages<M>>() {
             panic!("server already contains resource: {}", M::reflect_name());
         }
+        self.init_resource::<Time<Real>>();
+        self.world
+            .get_resource_or_insert_with(RtcProtocolRegistry::default)
+            .register::<M>(ProtocolDirection::ReadOnly, Some(bound));
         self.insert_resource(IncomingMessages::<M> {
             messages: HashMap::new(),
+            receipts: vec![],
             bound,
         })
         .add_systems(
             First,
             IncomingMessages::<M>::receive_payloads
-                .after(common_socket_reader)
+                .after(dispatch_packets)
+                .run_if(resource_exists::<RtcSocket>),
+        )
+        .add_systems(
+            Last,
+            IncomingMessages::<M>::send_receipts
+                .before(OutgoingMessages::<DeliveryReceipt>::send_payloads)
                 .run_if(resource_exists::<RtcSocket>),
         );
 
@@ -70,36 +113,57 @@ impl AddServerProtocolExt for App {
         self.add_server_ro_protocol::<M>(usize::MAX)
     }
 
-    fn add_server_rw_protocol<M: Protocol>(&mut self, bound: usize) -> &mut Self
-    where
-        M: Protocol,
-    {
+    fn add_server_rw_protocol<M: Protocol>(&mut self, bound: usize) -> &mut Self {
         if self.world.contains_resource::<IncomingMessages<M>>()
             || self.world.contains_resource::<OutgoingMessages<M>>()
         {
             panic!("server already contains resource: {}", M::reflect_name());
         }
+        self.init_resource::<Time<Real>>();
+        let mut registry = self
+            .world
+            .get_resource_or_insert_with(RtcProtocolRegistry::default);
+        registry.register::<M>(ProtocolDirection::ReadWrite, Some(bound));
+        let send_rate = registry.send_rate(M::id());
         self.insert_resource(IncomingMessages::<M> {
             messages: HashMap::new(),
+            receipts: vec![],
             bound,
         })
         .insert_resource(OutgoingMessages::<M> {
             reliable_to_all: vec![],
             unreliable_to_all: vec![],
+            reliable_unordered_to_all: vec![],
+            unordered_unreliable_to_all: vec![],
             reliable_to_all_except: vec![],
             unreliable_to_all_except: vec![],
+            reliable_unordered_to_all_except: vec![],
+            unordered_unreliable_to_all_except: vec![],
             reliable_to_peer: vec![],
             unreliable_to_peer: vec![],
+            reliable_unordered_to_peer: vec![],
+            unordered_unreliable_to_peer: vec![],
+            reliable_to_peer_tracked: vec![],
+            next_receipt: 0,
+            limiter: SendLimiter::new(send_rate),
         })
         .add_systems(
             First,
             IncomingMessages::<M>::receive_payloads
-                .after(common_socket_reader)
+                .after(dispatch_packets)
                 .run_if(resource_exists::<RtcSocket>),
         )
         .add_systems(
             Last,
-            OutgoingMessages::<M>::send_payloads.run_if(resource_exists::<RtcSocket>),
+            IncomingMessages::<M>::send_receipts
+                .before(OutgoingMessages::<DeliveryReceipt>::send_payloads)
+                .run_if(resource_exists::<RtcSocket>),
+        )
+        .add_systems(
+            Last,
+            OutgoingMessages::<M>::send_payloads
+                .before(flush_outbox)
+                .run_if(resource_exists::<RtcSocket>),
         );
 
         self
@@ -108,4 +172,43 @@ impl AddServerProtocolExt for App {
     fn add_server_rw_unbounded_protocol<M: Protocol>(&mut self) -> &mut Self {
         self.add_server_rw_protocol::<M>(usize::MAX)
     }
+
+    fn add_server_snapshot_protocol<S: Snapshot>(&mut self) -> &mut Self {
+        if !self.world.contains_resource::<SnapshotAcks>() {
+            // Acknowledgements of every snapshot share one protocol
+            self.add_server_ro_protocol::<SnapshotAck>(SNAPSHOT_HISTORY)
+                .init_resource::<SnapshotAcks>()
+                .add_systems(
+                    First,
+                    collect_snapshot_acks
+                        .after(IncomingMessages::<SnapshotAck>::receive_payloads)
+                        .run_if(resource_exists::<RtcSocket>),
+                );
+        }
+        self.add_server_wo_protocol::<SnapshotPayload<S>>()
+            .init_resource::<ServerSnapshots<S>>()
+            .add_systems(
+                Last,
+                ServerSnapshots::<S>::encode_snapshots
+                    .before(OutgoingMessages::<SnapshotPayload<S>>::send_payloads)
+                    .run_if(resource_exists::<RtcSocket>),
+            );
+        // The generic tracer cannot explore the body nested in the payload
+        #[cfg(feature = "schema")]
+        self.world.resource_mut::<RtcProtocolRegistry>().set_tracer(
+            SnapshotPayload::<S>::id(),
+            crate::schema::trace_snapshot::<S>,
+        );
+        self
+    }
+
+    fn configure_server_protocol<M: Protocol>(&mut self, options: ProtocolOptions) -> &mut Self {
+        self.world
+            .get_resource_or_insert_with(RtcProtocolRegistry::default)
+            .configure::<M>(options.clone());
+        if let Some(mut outgoing) = self.world.get_resource_mut::<OutgoingMessages<M>>() {
+            outgoing.limiter = SendLimiter::new(options.send_rate);
+        }
+        self
+    }
 }
@@ -1,4 +1,13 @@
-use crate::{events::SocketRecvEvent, protocol::Protocol, transport_encoding::TransportEncoding};
+use super::OutgoingMessages;
+use crate::{
+    dispatch::{ReceivedPacket, ReceivedPackets},
+    events::RtcDecodeError,
+    protocol::Protocol,
+    receipt::DeliveryReceipt,
+    registry::RtcProtocolRegistry,
+    sequence::Sequences,
+    transport_encoding::TransportEncoding,
+};
 use bevy::{prelude::*, utils::hashbrown::HashMap};
 use bevy_matchbox::prelude::PeerId;
 use std::collections::VecDeque;
@@ -6,29 +15,64 @@ use std::collections::VecDeque;
 #[derive(Default, Debug, Resource)]
 pub struct IncomingMessages<M: Protocol> {
     pub bound: usize,
-    pub messages: HashMap<PeerId, VecDeque<M>>,
+    /// Payloads waiting to be read, with their receipt ID if tracked.
+    pub messages: HashMap<PeerId, VecDeque<(M, Option<u32>)>>,
+    /// Receipt IDs of tracked payloads read since the last receipts were sent.
+    pub receipts: Vec<(PeerId, u32)>,
 }
 
 impl<M: Protocol> IncomingMessages<M> {
-    pub fn receive_payloads(
+    pub(crate) fn receive_payloads(
         mut incoming: ResMut<Self>,
-        mut events: EventReader<SocketRecvEvent>,
+        mut received: ResMut<ReceivedPackets>,
         encoding: Res<TransportEncoding>,
+        registry: Res<RtcProtocolRegistry>,
+        mut sequences: ResMut<Sequences>,
+        mut decode_errors: EventWriter<RtcDecodeError>,
     ) {
+        let encoding = registry.encoding(M::id()).unwrap_or(&encoding);
         let bound = incoming.bound;
-        let packets: HashMap<PeerId, Vec<M>> = events.read().fold(
-            HashMap::new(),
-            |mut acc, &SocketRecvEvent((peer_id, ref packet))| {
+        let packets: HashMap<PeerId, Vec<(M, Option<u32>)>> = received
+            .take(M::id())
+            .into_iter()
+            .fold(HashMap::new(), |mut acc, received| {
+                let ReceivedPacket {
+                    peer: peer_id,
+                    seq,
+                    receipt,
+                    packet,
+                } = received;
+                if seq.is_some_and(|seq| !sequences.accept(M::id(), peer_id, seq)) {
+                    registry.count_stale(M::id());
+                    return acc;
+                }
                 let buf = acc.entry(peer_id).or_insert(vec![]);
                 if buf.len() >= bound {
+                    registry.count_dropped(M::id(), 1);
                     return acc;
                 }
-                if let Some(packet) = M::from_packet(packet, &encoding) {
-                    buf.push(packet);
+                match M::from_packet(&packet, encoding) {
+                    Ok(payload) => {
+                        registry.count_received(M::id(), packet.len());
+                        buf.push((payload, receipt));
+                    }
+                    Err(err) => {
+                        registry.count_decode_failure(M::id());
+                        warn!(
+                            "Failed to decode `{}` from {peer_id}: {err}",
+                            M::reflect_name()
+                        );
+                        decode_errors.send(RtcDecodeError {
+                            peer: peer_id,
+                            msg_id: Some(M::id()),
+                            protocol: Some(M::reflect_name().to_string()),
+                            len: packet.len(),
+                            reason: err.to_string(),
+                        });
+                    }
                 }
                 acc
-            },
-        );
+            });
         for (peer_id, payloads) in packets {
             // Get or insert the VecDeque for the peer_id
             let messages_for_peer = incoming
@@ -43,10 +87,32 @@ impl<M: Protocol> IncomingMessages<M> {
                     "The `{}` protocol is overflowing its bounded buffer ({bound}) and dropping packets! Is it being read?",
                     M::reflect_name()
                 );
-                while messages_for_peer.len() > bound {
-                    messages_for_peer.pop_front();
-                }
+                // Never acknowledged, so tracked payloads time out
+                let dropped = messages_for_peer.len() - bound;
+                messages_for_peer.drain(..dropped);
+                registry.count_dropped(M::id(), dropped);
             }
         }
     }
+
+    /// Acknowledges the tracked payloads read since the last receipts were
+    /// sent.
+    pub(crate) fn send_receipts(
+        mut incoming: ResMut<Self>,
+        mut outgoing: ResMut<OutgoingMessages<DeliveryReceipt>>,
+    ) {
+        let mut by_peer: HashMap<PeerId, Vec<u32>> = HashMap::new();
+        for (peer, receipt) in incoming.receipts.drain(..) {
+            by_peer.entry(peer).or_default().push(receipt);
+        }
+        for (peer, receipts) in by_peer {
+            outgoing.reliable_to_peer.push((
+                peer,
+                DeliveryReceipt {
+                    protocol: M::id(),
+                    receipts,
+                },
+            ));
+        }
+    }
 }
@@ -1,6 +1,17 @@
 use crate::{
+    channel::Channels,
+    compression::Compression,
+    events::{RtcDeliveryTimeout, RtcSendError},
+    outbox::Outbox,
     protocol::Protocol,
-    socket::{RtcSocket, RELIABLE_CHANNEL_INDEX, UNRELIABLE_CHANNEL_INDEX},
+    rate::SendLimiter,
+    receipt::{self, MessageHandle, Receipts},
+    registry::RtcProtocolRegistry,
+    sequence::{self, Sequences},
+    socket::{
+        RtcSocket, RELIABLE_CHANNEL_INDEX, RELIABLE_UNORDERED_CHANNEL_INDEX,
+        UNORDERED_UNRELIABLE_CHANNEL_INDEX, UNRELIABLE_CHANNEL_INDEX,
+    },
     transport_encoding::TransportEncoding,
 };
 use bevy::prelude::*;
@@ -10,10 +21,22 @@ use bevy_matchbox::prelude::PeerId;
 pub struct OutgoingMessages<M: Protocol> {
     pub reliable_to_all: Vec<M>,
     pub unreliable_to_all: Vec<M>,
+    pub reliable_unordered_to_all: Vec<M>,
+    pub unordered_unreliable_to_all: Vec<M>,
     pub reliable_to_all_except: Vec<(PeerId, M)>,
     pub unreliable_to_all_except: Vec<(PeerId, M)>,
+    pub reliable_unordered_to_all_except: Vec<(PeerId, M)>,
+    pub unordered_unreliable_to_all_except: Vec<(PeerId, M)>,
     pub reliable_to_peer: Vec<(PeerId, M)>,
     pub unreliable_to_peer: Vec<(PeerId, M)>,
+    pub reliable_unordered_to_peer: Vec<(PeerId, M)>,
+    pub unordered_unreliable_to_peer: Vec<(PeerId, M)>,
+    /// Payloads sent with reliability and tracked, with their receipt ID.
+    pub reliable_to_peer_tracked: Vec<(PeerId, M, u32)>,
+    /// The receipt ID of the next tracked payload.
+    pub next_receipt: u32,
+    /// Limits the payloads built by the lazy `*_with` methods.
+    pub(crate) limiter: SendLimiter,
 }
 
 impl<M: Protocol> OutgoingMessages<M> {
@@ -22,29 +45,96 @@ impl<M: Protocol> OutgoingMessages<M> {
     pub fn flush(&mut self) {
         self.reliable_to_all.clear();
         self.unreliable_to_all.clear();
+        self.reliable_unordered_to_all.clear();
+        self.unordered_unreliable_to_all.clear();
         self.reliable_to_all_except.clear();
         self.unreliable_to_all_except.clear();
+        self.reliable_unordered_to_all_except.clear();
+        self.unordered_unreliable_to_all_except.clear();
         self.reliable_to_peer.clear();
         self.unreliable_to_peer.clear();
+        self.reliable_unordered_to_peer.clear();
+        self.unordered_unreliable_to_peer.clear();
+        self.reliable_to_peer_tracked.clear();
     }
 
+    /// Queue a tracked payload to a peer with reliability.
+    pub(crate) fn track(&mut self, peer: PeerId, message: M) -> MessageHandle {
+        let id = self.next_receipt;
+        self.next_receipt = self.next_receipt.wrapping_add(1);
+        self.reliable_to_peer_tracked.push((peer, message, id));
+        MessageHandle::new(M::id(), id)
+    }
+
+    #[allow(clippy::too_many_arguments)]
     pub(crate) fn send_payloads(
         mut queue: ResMut<Self>,
-        mut socket: ResMut<RtcSocket>,
+        socket: Res<RtcSocket>,
         encoding: Res<TransportEncoding>,
+        registry: Res<RtcProtocolRegistry>,
+        channels: Res<Channels>,
+        compression: Option<Res<Compression>>,
+        mut send_errors: EventWriter<RtcSendError>,
+        mut timeouts: EventWriter<RtcDeliveryTimeout>,
+        mut outbox: ResMut<Outbox>,
+        mut sequences: ResMut<Sequences>,
+        mut receipts: ResMut<Receipts>,
+        time: Res<Time<Real>>,
     ) {
+        queue
+            .limiter
+            .retain_peers(|peer| socket.connected_peers().any(|p| p == *peer));
+        let encoding = registry.encoding(M::id()).unwrap_or(&encoding);
+        let compression = compression.filter(|_| !registry.skip_compression(M::id()));
+        let sequenced = registry.sequenced(M::id());
+        // Checked when the plugin is finished
+        let bound_channel = registry
+            .channel(M::id())
+            .and_then(|name| channels.index(name));
+        let mut send = |channel: usize, peer: PeerId, message: &M, receipt: Option<u32>| {
+            let channel = channels.resolve(bound_channel, channel);
+            let packet = match message.to_packet(encoding) {
+                Ok(packet) => packet,
+                Err(err) => {
+                    error!("failed to encode packet for {peer}: {err}");
+                    send_errors.send(RtcSendError {
+                        peer,
+                        protocol: M::reflect_name(),
+                        reason: format!("failed to encode: {err}"),
+                    });
+                    // The handle was already returned, so it must resolve
+                    if let Some(receipt) = receipt {
+                        timeouts.send(RtcDeliveryTimeout(MessageHandle::new(M::id(), receipt)));
+                    }
+                    return;
+                }
+            };
+            let packet = match compression {
+                Some(ref compression) => compression.compress(packet),
+                None => packet,
+            };
+            let packet = match receipt {
+                Some(receipt) => receipt::stamp(packet, receipt),
+                None => packet,
+            };
+            let packet = if sequenced {
+                sequence::stamp(packet, sequences.next(M::id(), peer))
+            } else {
+                packet
+            };
+            registry.count_sent(M::id(), packet.len());
+            outbox.push(peer, channel, M::reflect_name(), packet);
+            if let Some(receipt) = receipt {
+                receipts.track(peer, MessageHandle::new(M::id(), receipt), time.elapsed());
+            }
+        };
+
         // Server is sending
         for message in queue.reliable_to_all.iter() {
             let peers: Vec<PeerId> = socket.connected_peers().collect();
-            peers.into_iter().for_each(|peer| {
-                if socket
-                    .channel_mut(RELIABLE_CHANNEL_INDEX)
-                    .try_send(message.to_packet(&encoding), peer)
-                    .is_err()
-                {
-                    error!("failed to send reliable packet to {peer}: {message:?}");
-                }
-            })
+            for peer in peers {
+                send(RELIABLE_CHANNEL_INDEX, peer, message, None);
+            }
         }
         if !queue.reliable_to_all.is_empty() {
             trace!(
@@ -55,15 +145,9 @@ impl<M: Protocol> OutgoingMessages<M> {
         }
         for message in queue.unreliable_to_all.iter() {
             let peers: Vec<PeerId> = socket.connected_peers().collect();
-            peers.into_iter().for_each(|peer| {
-                if socket
-                    .channel_mut(UNRELIABLE_CHANNEL_INDEX)
-                    .try_send(message.to_packet(&encoding), peer)
-                    .is_err()
-                {
-                    error!("failed to send unreliable packet to {peer}: {message:?}");
-                }
-            })
+            for peer in peers {
+                send(UNRELIABLE_CHANNEL_INDEX, peer, message, None);
+            }
         }
         if !queue.unreliable_to_all.is_empty() {
             trace!(
@@ -72,17 +156,37 @@ impl<M: Protocol> OutgoingMessages<M> {
                 M::reflect_name()
             );
         }
-        for (peer, message) in queue.reliable_to_all_except.iter() {
-            let peers: Vec<PeerId> = socket.connected_peers().filter(|p| p != peer).collect();
-            peers.into_iter().for_each(|peer| {
-                if socket
-                    .channel_mut(RELIABLE_CHANNEL_INDEX)
-                    .try_send(message.to_packet(&encoding), peer)
-                    .is_err()
-                {
-                    error!("failed to send reliable packet to {peer}: {message:?}");
-                }
-            });
+        for message in queue.reliable_unordered_to_all.iter() {
+            let peers: Vec<PeerId> = socket.connected_peers().collect();
+            for peer in peers {
+                send(RELIABLE_UNORDERED_CHANNEL_INDEX, peer, message, None);
+            }
+        }
+        if !queue.reliable_unordered_to_all.is_empty() {
+            trace!(
+                "sent {} [RU;N] {} packets",
+                queue.reliable_unordered_to_all.len(),
+                M::reflect_name()
+            );
+        }
+        for message in queue.unordered_unreliable_to_all.iter() {
+            let peers: Vec<PeerId> = socket.connected_peers().collect();
+            for peer in peers {
+                send(UNORDERED_UNRELIABLE_CHANNEL_INDEX, peer, message, None);
+            }
+        }
+        if !queue.unordered_unreliable_to_all.is_empty() {
+            trace!(
+                "sent {} [UU;N] {} packets",
+                queue.unordered_unreliable_to_all.len(),
+                M::reflect_name()
+            );
+        }
+        for (except, message) in queue.reliable_to_all_except.iter() {
+            let peers: Vec<PeerId> = socket.connected_peers().filter(|p| p != except).collect();
+            for peer in peers {
+                send(RELIABLE_CHANNEL_INDEX, peer, message, None);
+            }
         }
         if !queue.reliable_to_all_except.is_empty() {
             trace!(
@@ -91,17 +195,11 @@ impl<M: Protocol> OutgoingMessages<M> {
                 M::reflect_name()
             );
         }
-        for (peer, message) in queue.unreliable_to_all_except.iter() {
-            let peers: Vec<PeerId> = socket.connected_peers().filter(|p| p != peer).collect();
-            peers.into_iter().for_each(|peer| {
-                if socket
-                    .channel_mut(UNRELIABLE_CHANNEL_INDEX)
-                    .try_send(message.to_packet(&encoding), peer)
-                    .is_err()
-                {
-                    error!("failed to send unreliable packet to {peer}: {message:?}");
-                }
-            });
+        for (except, message) in queue.unreliable_to_all_except.iter() {
+            let peers: Vec<PeerId> = socket.connected_peers().filter(|p| p != except).collect();
+            for peer in peers {
+                send(UNRELIABLE_CHANNEL_INDEX, peer, message, None);
+            }
         }
         if !queue.unreliable_to_all_except.is_empty() {
             trace!(
@@ -110,15 +208,35 @@ impl<M: Protocol> OutgoingMessages<M> {
                 M::reflect_name()
             );
         }
-        for (peer, message) in queue.reliable_to_peer.iter() {
-            if socket
-                .channel_mut(RELIABLE_CHANNEL_INDEX)
-                .try_send(message.to_packet(&encoding), *peer)
-                .is_err()
-            {
-                error!("failed to send reliable packet to {peer}: {message:?}");
+        for (except, message) in queue.reliable_unordered_to_all_except.iter() {
+            let peers: Vec<PeerId> = socket.connected_peers().filter(|p| p != except).collect();
+            for peer in peers {
+                send(RELIABLE_UNORDERED_CHANNEL_INDEX, peer, message, None);
             }
         }
+        if !queue.reliable_unordered_to_all_except.is_empty() {
+            trace!(
+                "sent {} [RU;N-1] {} packets",
+                queue.reliable_unordered_to_all_except.len(),
+                M::reflect_name()
+            );
+        }
+        for (except, message) in queue.unordered_unreliable_to_all_except.iter() {
+            let peers: Vec<PeerId> = socket.connected_peers().filter(|p| p != except).collect();
+            for peer in peers {
+                send(UNORDERED_UNRELIABLE_CHANNEL_INDEX, peer, message, None);
+            }
+        }
+        if !queue.unordered_unreliable_to_all_except.is_empty() {
+            trace!(
+                "sent {} [UU;N-1] {} packets",
+                queue.unordered_unreliable_to_all_except.len(),
+                M::reflect_name()
+            );
+        }
+        for (peer, message) in queue.reliable_to_peer.iter() {
+            send(RELIABLE_CHANNEL_INDEX, *peer, message, None);
+        }
         if !queue.reliable_to_peer.is_empty() {
             trace!(
                 "sent {} [R] {} packets",
@@ -127,13 +245,7 @@ impl<M: Protocol> OutgoingMessages<M> {
             );
         }
         for (peer, message) in queue.unreliable_to_peer.iter() {
-            if socket
-                .channel_mut(UNRELIABLE_CHANNEL_INDEX)
-                .try_send(message.to_packet(&encoding), *peer)
-                .is_err()
-            {
-                error!("failed to send unreliable packet to {peer}: {message:?}");
-            }
+            send(UNRELIABLE_CHANNEL_INDEX, *peer, message, None);
         }
         if !queue.unreliable_to_peer.is_empty() {
             trace!(
@@ -142,6 +254,36 @@ impl<M: Protocol> OutgoingMessages<M> {
                 M::reflect_name()
             );
         }
+        for (peer, message) in queue.reliable_unordered_to_peer.iter() {
+            send(RELIABLE_UNORDERED_CHANNEL_INDEX, *peer, message, None);
+        }
+        if !queue.reliable_unordered_to_peer.is_empty() {
+            trace!(
+                "sent {} [RU] {} packets",
+                queue.reliable_unordered_to_peer.len(),
+                M::reflect_name()
+            );
+        }
+        for (peer, message) in queue.unordered_unreliable_to_peer.iter() {
+            send(UNORDERED_UNRELIABLE_CHANNEL_INDEX, *peer, message, None);
+        }
+        if !queue.unordered_unreliable_to_peer.is_empty() {
+            trace!(
+                "sent {} [UU] {} packets",
+                queue.unordered_unreliable_to_peer.len(),
+                M::reflect_name()
+            );
+        }
+        for (peer, message, receipt) in queue.reliable_to_peer_tracked.iter() {
+            send(RELIABLE_CHANNEL_INDEX, *peer, message, Some(*receipt));
+        }
+        if !queue.reliable_to_peer_tracked.is_empty() {
+            trace!(
+                "sent {} [R;T] {} packets",
+                queue.reliable_to_peer_tracked.len(),
+                M::reflect_name()
+            );
+        }
 
         queue.flush();
     }
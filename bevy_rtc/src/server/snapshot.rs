@@ -0,0 +1,275 @@
+use super::{router::OutgoingMessages, RtcServer};
+use crate::{
+    snapshot::{Snapshot, SnapshotAck, SnapshotBody, SnapshotPayload, SNAPSHOT_HISTORY},
+    socket::RtcSocket,
+};
+use bevy::{prelude::*, utils::HashMap};
+use bevy_matchbox::prelude::PeerId;
+use std::collections::VecDeque;
+
+/// Snapshot acknowledgements received this frame, by snapshot protocol ID.
+#[derive(Resource, Default, Debug)]
+pub(crate) struct SnapshotAcks {
+    by_id: HashMap<u16, Vec<(PeerId, u32)>>,
+}
+
+/// Sorts the snapshot acknowledgements received this frame by snapshot.
+pub(crate) fn collect_snapshot_acks(
+    mut server: RtcServer<SnapshotAck>,
+    mut acks: ResMut<SnapshotAcks>,
+) {
+    acks.by_id.clear();
+    for (peer, ack) in server.read() {
+        acks.by_id.entry(ack.id).or_default().push((peer, ack.seq));
+    }
+}
+
+/// The snapshots sent to a peer, and the latest one it acknowledged.
+#[derive(Debug)]
+struct PeerSnapshots<S: Snapshot> {
+    next_seq: u32,
+    /// Snapshots which were sent but not acknowledged, oldest first.
+    sent: VecDeque<(u32, S)>,
+    baseline: Option<(u32, S)>,
+}
+
+impl<S: Snapshot> Default for PeerSnapshots<S> {
+    fn default() -> Self {
+        Self {
+            next_seq: 0,
+            sent: VecDeque::new(),
+            baseline: None,
+        }
+    }
+}
+
+impl<S: Snapshot> PeerSnapshots<S> {
+    /// Make an acknowledged snapshot the baseline, if it is newer than the
+    /// current one.
+    fn ack(&mut self, seq: u32) {
+        let Some(index) = self.sent.iter().position(|(sent, _)| *sent == seq) else {
+            // Already a baseline, or too old to remember
+            return;
+        };
+        self.baseline = self.sent.drain(..=index).last();
+    }
+
+    /// Number a snapshot and encode it against the baseline.
+    fn encode(&mut self, state: &S) -> SnapshotPayload<S> {
+        let seq = self.next_seq;
+        self.next_seq = self.next_seq.wrapping_add(1);
+        let body = match self.baseline {
+            // The client only remembers its latest snapshots
+            Some((baseline, ref base))
+                if seq.wrapping_sub(baseline) as usize <= SNAPSHOT_HISTORY =>
+            {
+                SnapshotBody::Delta {
+                    baseline,
+                    delta: state.diff(base),
+                }
+            }
+            _ => SnapshotBody::Full(state.clone()),
+        };
+        self.sent.push_back((seq, state.clone()));
+        if self.sent.len() > SNAPSHOT_HISTORY {
+            self.sent.pop_front();
+        }
+        SnapshotPayload { seq, body }
+    }
+}
+
+/// The snapshots sent to each peer, to encode the next snapshot against.
+#[derive(Resource, Debug)]
+pub(crate) struct ServerSnapshots<S: Snapshot> {
+    peers: HashMap<PeerId, PeerSnapshots<S>>,
+}
+
+impl<S: Snapshot> Default for ServerSnapshots<S> {
+    fn default() -> Self {
+        Self {
+            peers: HashMap::new(),
+        }
+    }
+}
+
+impl<S: Snapshot> ServerSnapshots<S> {
+    /// Replaces the snapshots queued this frame with a delta for each peer,
+    /// against the latest snapshot the peer acknowledged.
+    pub(crate) fn encode_snapshots(
+        mut snapshots: ResMut<Self>,
+        mut queue: ResMut<OutgoingMessages<SnapshotPayload<S>>>,
+        mut acks: ResMut<SnapshotAcks>,
+        socket: Res<RtcSocket>,
+    ) {
+        let connected: Vec<PeerId> = socket.connected_peers().collect();
+        snapshots.peers.retain(|peer, _| connected.contains(peer));
+        for (peer, seq) in acks.by_id.remove(&S::PROTOCOL_ID).unwrap_or_default() {
+            if let Some(peer) = snapshots.peers.get_mut(&peer) {
+                peer.ack(seq);
+            }
+        }
+
+        let mut encode = |payloads: Vec<(PeerId, SnapshotPayload<S>)>| {
+            payloads
+                .into_iter()
+                .map(|(peer, payload)| {
+                    let encoded = snapshots
+                        .peers
+                        .entry(peer)
+                        .or_default()
+                        .encode(payload.state());
+                    (peer, encoded)
+                })
+                .collect()
+        };
+        let queue = &mut *queue;
+        queue.reliable_to_peer = encode(fan_out(
+            &connected,
+            &mut queue.reliable_to_all,
+            &mut queue.reliable_to_all_except,
+            &mut queue.reliable_to_peer,
+        ));
+        queue.unreliable_to_peer = encode(fan_out(
+            &connected,
+            &mut queue.unreliable_to_all,
+            &mut queue.unreliable_to_all_except,
+            &mut queue.unreliable_to_peer,
+        ));
+        queue.reliable_unordered_to_peer = encode(fan_out(
+            &connected,
+            &mut queue.reliable_unordered_to_all,
+            &mut queue.reliable_unordered_to_all_except,
+            &mut queue.reliable_unordered_to_peer,
+        ));
+        queue.unordered_unreliable_to_peer = encode(fan_out(
+            &connected,
+            &mut queue.unordered_unreliable_to_all,
+            &mut queue.unordered_unreliable_to_all_except,
+            &mut queue.unordered_unreliable_to_peer,
+        ));
+        let (tracked, receipts): (Vec<_>, Vec<_>) = queue
+            .reliable_to_peer_tracked
+            .drain(..)
+            .map(|(peer, payload, receipt)| ((peer, payload), receipt))
+            .unzip();
+        queue.reliable_to_peer_tracked = encode(tracked)
+            .into_iter()
+            .zip(receipts)
+            .map(|((peer, payload), receipt)| (peer, payload, receipt))
+            .collect();
+    }
+}
+
+/// Takes the payloads queued for all peers, all peers but one, and single
+/// peers, as a payload for each connected peer.
+fn fan_out<M: Clone>(
+    connected: &[PeerId],
+    to_all: &mut Vec<M>,
+    to_all_except: &mut Vec<(PeerId, M)>,
+    to_peer: &mut Vec<(PeerId, M)>,
+) -> Vec<(PeerId, M)> {
+    let mut payloads = vec![];
+    for payload in to_all.drain(..) {
+        payloads.extend(connected.iter().map(|peer| (*peer, payload.clone())));
+    }
+    for (except, payload) in to_all_except.drain(..) {
+        payloads.extend(
+            connected
+                .iter()
+                .filter(|peer| **peer != except)
+                .map(|peer| (*peer, payload.clone())),
+        );
+    }
+    payloads.append(to_peer);
+    payloads
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::snapshot::Snapshot;
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Snapshot, Serialize, Deserialize, Debug, Clone, PartialEq)]
+    #[snapshot(crate = "crate")]
+    struct Score {
+        #[snapshot(diff)]
+        points: u32,
+    }
+
+    fn score(points: u32) -> Score {
+        Score { points }
+    }
+
+    fn baseline(payload: &SnapshotPayload<Score>) -> Option<u32> {
+        match payload.body {
+            SnapshotBody::Full(_) => None,
+            SnapshotBody::Delta { baseline, .. } => Some(baseline),
+        }
+    }
+
+    #[test]
+    fn snapshots_are_full_until_acknowledged() {
+        let mut peer = PeerSnapshots::default();
+
+        let first = peer.encode(&score(1));
+        let second = peer.encode(&score(2));
+        assert_eq!((first.seq, baseline(&first)), (0, None));
+        assert_eq!((second.seq, baseline(&second)), (1, None));
+        assert_eq!(second.state(), &score(2));
+    }
+
+    #[test]
+    fn snapshots_are_encoded_against_the_acknowledged_baseline() {
+        let mut peer = PeerSnapshots::default();
+        for points in 0..3 {
+            peer.encode(&score(points));
+        }
+
+        peer.ack(1);
+        assert_eq!(peer.baseline, Some((1, score(1))));
+        // Snapshots up to the baseline are forgotten
+        assert_eq!(
+            peer.sent.iter().map(|(seq, _)| *seq).collect::<Vec<_>>(),
+            [2]
+        );
+
+        let payload = peer.encode(&score(5));
+        assert_eq!(payload.seq, 3);
+        assert_eq!(baseline(&payload), Some(1));
+        let SnapshotBody::Delta { delta, .. } = &payload.body else {
+            unreachable!()
+        };
+        assert_eq!(score(1).apply(delta), score(5));
+    }
+
+    #[test]
+    fn stale_acks_keep_the_newer_baseline() {
+        let mut peer = PeerSnapshots::default();
+        for points in 0..3 {
+            peer.encode(&score(points));
+        }
+
+        peer.ack(2);
+        peer.ack(1);
+        peer.ack(7);
+        assert_eq!(peer.baseline, Some((2, score(2))));
+        assert!(peer.sent.is_empty());
+    }
+
+    #[test]
+    fn baselines_older_than_the_history_are_not_used() {
+        let mut peer = PeerSnapshots::default();
+        peer.encode(&score(0));
+        peer.ack(0);
+
+        for seq in 1..=SNAPSHOT_HISTORY as u32 {
+            let payload = peer.encode(&score(seq));
+            assert_eq!(baseline(&payload), Some(0));
+        }
+        // The client no longer remembers snapshot 0
+        let payload = peer.encode(&score(0));
+        assert_eq!(payload.seq, SNAPSHOT_HISTORY as u32 + 1);
+        assert_eq!(baseline(&payload), None);
+    }
+}
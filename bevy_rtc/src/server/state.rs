@@ -7,6 +7,9 @@ use bevy_matchbox::prelude::PeerId;
 use instant::Duration;
 use std::net::SocketAddr;
 
+/// How long to wait for a joined peer's protocol manifest by default.
+pub(crate) const DEFAULT_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
 /// State of the server
 #[derive(Debug, Default, Clone, Eq, PartialEq, Hash, States)]
 pub enum RtcServerStatus {
@@ -33,16 +36,28 @@ pub struct RtcServerState {
 
     /// A map of smoothed user latencies
     pub(crate) smoothed_latencies: HashMap<PeerId, Option<Duration>>,
+
+    /// A map of packets from each peer which failed to decode
+    pub(crate) decode_failures: HashMap<PeerId, u64>,
+
+    /// When each peer joined, while waiting for its protocol manifest
+    pub(crate) pending_handshakes: HashMap<PeerId, Duration>,
+
+    /// How long to wait for the protocol manifest of a joined peer
+    pub(crate) handshake_timeout: Duration,
 }
 
 impl RtcServerState {
-    pub(crate) fn new(addr: SocketAddr) -> Self {
+    pub(crate) fn new(addr: SocketAddr, handshake_timeout: Duration) -> Self {
         Self {
             addr,
             peer_id: None,
             peers: HashSet::new(),
             latencies: HashMap::new(),
             smoothed_latencies: HashMap::new(),
+            decode_failures: HashMap::new(),
+            pending_handshakes: HashMap::new(),
+            handshake_timeout,
         }
     }
 
@@ -86,4 +101,14 @@ impl RtcServerState {
     pub fn get_smoothed_latency_for(&self, peer_id: PeerId) -> Option<Duration> {
         *self.smoothed_latencies.get(&peer_id)?
     }
+
+    /// Return the number of packets from a peer which failed to decode
+    pub fn get_decode_failures_for(&self, peer_id: PeerId) -> u64 {
+        self.decode_failures.get(&peer_id).copied().unwrap_or(0)
+    }
+
+    /// Return the number of packets which failed to decode for all peers
+    pub fn iter_decode_failures(&self) -> impl Iterator<Item = (PeerId, u64)> + '_ {
+        self.decode_failures.iter().map(|(p, n)| (*p, *n))
+    }
 }
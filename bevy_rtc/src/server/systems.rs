@@ -1,7 +1,17 @@
 use super::{events::RtcServerEvent, RtcServer, RtcServerState, RtcServerStatus};
 use crate::{
+    channel::Channels,
+    dispatch::{ReceivedPacket, ReceivedPackets},
+    events::{RtcDecodeError, RtcDelivered},
+    handshake::{HandshakePayload, HangUpPayload, HANDSHAKE_ENCODING},
     latency::{LatencyTracer, LatencyTracerPayload},
-    socket::RtcSocket,
+    outbox::Outbox,
+    protocol::Protocol,
+    receipt::{DeliveryReceipt, MessageHandle, Receipts},
+    registry::RtcProtocolRegistry,
+    sequence::Sequences,
+    socket::{RtcSocket, RELIABLE_CHANNEL_INDEX},
+    transport_encoding::TransportEncoding,
 };
 use bevy::prelude::*;
 use bevy_matchbox::{
@@ -9,8 +19,7 @@ use bevy_matchbox::{
         topologies::client_server::{ClientServer, ClientServerState},
         SignalingServerBuilder,
     },
-    matchbox_socket::{PeerState, WebRtcSocket},
-    prelude::ChannelConfig,
+    matchbox_socket::{PeerId, PeerState},
     OpenSocketExt, StartServerExt,
 };
 use instant::Duration;
@@ -22,6 +31,9 @@ use std::sync::{
 /// Initialize the signaling server
 pub fn init_signaling_server(mut commands: Commands, rtc_state: Res<RtcServerState>) {
     let host_ready: Arc<AtomicBool> = Arc::new(AtomicBool::new(false));
+    // Matchbox requires connection requests to be refused with its large
+    // `Response` error type
+    #[allow(clippy::result_large_err)]
     let builder =
         SignalingServerBuilder::new(rtc_state.addr, ClientServer, ClientServerState::default())
             .on_id_assignment(|(socket, id)| info!("{socket} assigned {id}"))
@@ -74,26 +86,26 @@ pub fn init_signaling_server(mut commands: Commands, rtc_state: Res<RtcServerSta
 }
 
 /// Initialize the server socket
-pub fn init_server_socket(mut commands: Commands, state: Res<RtcServerState>) {
+pub fn init_server_socket(
+    mut commands: Commands,
+    state: Res<RtcServerState>,
+    channels: Res<Channels>,
+) {
     // Create matchbox socket
     let room_url = format!("ws://{}", state.addr);
-    let socker_builder = WebRtcSocket::builder(room_url)
-        // Match UNRELIABLE_CHANNEL_INDEX
-        .add_channel(ChannelConfig {
-            ordered: true,
-            max_retransmits: Some(0),
-        })
-        // Match RELIABLE_CHANNEL_INDEX
-        .add_channel(ChannelConfig::reliable());
-    commands.open_socket(socker_builder);
+    commands.open_socket(channels.socket_builder(room_url));
 }
 
 /// Translates socket events into Bevy events
+#[allow(clippy::too_many_arguments)]
 pub fn server_event_writer(
     mut commands: Commands,
     tracer_query: Query<(Entity, &LatencyTracer)>,
     mut state: ResMut<RtcServerState>,
     mut socket: ResMut<RtcSocket>,
+    mut sequences: ResMut<Sequences>,
+    mut received: ResMut<ReceivedPackets>,
+    mut outbox: ResMut<Outbox>,
     mut event_wtr: EventWriter<RtcServerEvent>,
     mut next_server_status: ResMut<NextState<RtcServerStatus>>,
 ) {
@@ -118,6 +130,11 @@ pub fn server_event_writer(
                 state.peers.remove(&peer);
                 state.latencies.remove(&peer);
                 state.smoothed_latencies.remove(&peer);
+                state.decode_failures.remove(&peer);
+                state.pending_handshakes.remove(&peer);
+                sequences.forget(peer);
+                received.forget(peer);
+                outbox.forget(peer);
                 if let Some(entity) = tracer_query
                     .iter()
                     .find(|(_, tracer)| tracer.peer_id == peer)
@@ -133,6 +150,149 @@ pub fn server_event_writer(
     }
 }
 
+/// Sends our protocol manifest to newly joined peers
+pub fn send_handshakes(
+    time: Res<Time<Real>>,
+    mut state: ResMut<RtcServerState>,
+    mut socket: ResMut<RtcSocket>,
+    mut events: EventReader<RtcServerEvent>,
+    encoding: Res<TransportEncoding>,
+    registry: Res<RtcProtocolRegistry>,
+    channels: Res<Channels>,
+) {
+    for ev in events.read() {
+        if let RtcServerEvent::ClientJoined(peer) = ev {
+            state.pending_handshakes.insert(*peer, time.elapsed());
+            let manifest = HandshakePayload::new(&encoding, &registry, &channels);
+            if socket
+                .channel_mut(RELIABLE_CHANNEL_INDEX)
+                .try_send(
+                    manifest
+                        .to_packet(&HANDSHAKE_ENCODING)
+                        .expect("protocol manifests always encode"),
+                    *peer,
+                )
+                .is_err()
+            {
+                error!("failed to send protocol manifest to {peer}");
+            }
+        }
+    }
+}
+
+/// Compares protocol manifests sent by peers against our own, and hangs up on
+/// incompatible peers
+#[allow(clippy::too_many_arguments)]
+pub fn read_handshakes(
+    mut state: ResMut<RtcServerState>,
+    mut socket: ResMut<RtcSocket>,
+    mut received: ResMut<ReceivedPackets>,
+    mut outbox: ResMut<Outbox>,
+    encoding: Res<TransportEncoding>,
+    registry: Res<RtcProtocolRegistry>,
+    channels: Res<Channels>,
+    mut event_wtr: EventWriter<RtcServerEvent>,
+    mut decode_errors: EventWriter<RtcDecodeError>,
+) {
+    let local = HandshakePayload::new(&encoding, &registry, &channels);
+    for ReceivedPacket { peer, packet, .. } in received.take(HandshakePayload::id()) {
+        let remote = match HandshakePayload::from_packet(&packet, &HANDSHAKE_ENCODING) {
+            Ok(remote) => remote,
+            Err(err) => {
+                warn!("Failed to decode protocol manifest from {peer}: {err}");
+                decode_errors.send(RtcDecodeError {
+                    peer,
+                    msg_id: Some(HandshakePayload::id()),
+                    protocol: Some(HandshakePayload::reflect_name().to_string()),
+                    len: packet.len(),
+                    reason: err.to_string(),
+                });
+                continue;
+            }
+        };
+        if state.pending_handshakes.remove(&peer).is_none() {
+            warn!("Ignoring a repeated protocol manifest from {peer}");
+            continue;
+        }
+        match local.check(&remote) {
+            Some(mismatch) => {
+                warn!("Protocol mismatch with {peer}: {mismatch}");
+                let reason = format!("protocol mismatch: {mismatch}");
+                hang_up(&mut socket, &mut received, &mut outbox, peer, reason);
+                event_wtr.send(RtcServerEvent::ProtocolMismatch(peer, mismatch));
+            }
+            None => debug!("Protocol manifest accepted from {peer}"),
+        }
+    }
+}
+
+/// Refuses peers which did not send their protocol manifest in time
+pub fn expire_handshakes(
+    time: Res<Time<Real>>,
+    mut state: ResMut<RtcServerState>,
+    mut socket: ResMut<RtcSocket>,
+    mut received: ResMut<ReceivedPackets>,
+    mut outbox: ResMut<Outbox>,
+    mut event_wtr: EventWriter<RtcServerEvent>,
+) {
+    let Some(deadline) = time.elapsed().checked_sub(state.handshake_timeout) else {
+        return;
+    };
+    let expired: Vec<PeerId> = state
+        .pending_handshakes
+        .iter()
+        .filter(|(_, joined)| **joined <= deadline)
+        .map(|(peer, _)| *peer)
+        .collect();
+    for peer in expired {
+        warn!("No protocol manifest from {peer}");
+        state.pending_handshakes.remove(&peer);
+        let reason = "no protocol manifest".to_string();
+        hang_up(&mut socket, &mut received, &mut outbox, peer, reason);
+        event_wtr.send(RtcServerEvent::HandshakeTimeout(peer));
+    }
+}
+
+/// Asks clients which fell too far behind to disconnect
+pub fn hang_up_slow_clients(
+    mut socket: ResMut<RtcSocket>,
+    mut received: ResMut<ReceivedPackets>,
+    mut outbox: ResMut<Outbox>,
+    mut event_wtr: EventWriter<RtcServerEvent>,
+) {
+    for peer in outbox.take_too_slow() {
+        let reason = "send queue overflowed".to_string();
+        hang_up(&mut socket, &mut received, &mut outbox, peer, reason);
+        event_wtr.send(RtcServerEvent::ClientTooSlow(peer));
+    }
+}
+
+/// Asks a client to disconnect, and stops reading from and sending to it.
+/// Matchbox cannot drop a single peer from the host.
+fn hang_up(
+    socket: &mut RtcSocket,
+    received: &mut ReceivedPackets,
+    outbox: &mut Outbox,
+    peer: PeerId,
+    reason: String,
+) {
+    received.refuse(peer);
+    outbox.hang_up(peer);
+    let hang_up = HangUpPayload { reason };
+    if socket
+        .channel_mut(RELIABLE_CHANNEL_INDEX)
+        .try_send(
+            hang_up
+                .to_packet(&HANDSHAKE_ENCODING)
+                .expect("hang ups always encode"),
+            peer,
+        )
+        .is_err()
+    {
+        error!("failed to hang up on {peer}");
+    }
+}
+
 pub fn send_latency_tracers(
     state: Res<RtcServerState>,
     mut server: RtcServer<LatencyTracerPayload>,
@@ -167,6 +327,22 @@ pub fn read_latency_tracers(
     }
 }
 
+/// Sends an event for each tracked payload acknowledged by its peer
+pub fn read_delivery_receipts(
+    mut server: RtcServer<DeliveryReceipt>,
+    mut receipts: ResMut<Receipts>,
+    mut delivered: EventWriter<RtcDelivered>,
+) {
+    for (peer, ack) in server.read() {
+        for id in ack.receipts {
+            let handle = MessageHandle::new(ack.protocol, id);
+            if receipts.deliver(peer, handle) {
+                delivered.send(RtcDelivered(handle));
+            }
+        }
+    }
+}
+
 pub fn calculate_latency(
     time: Res<Time>,
     mut state: ResMut<RtcServerState>,
@@ -204,3 +380,55 @@ pub fn calculate_latency(
         }
     }
 }
+
+/// Counts packets from each peer which failed to decode
+pub fn count_decode_failures(
+    mut decode_errors: EventReader<RtcDecodeError>,
+    mut state: ResMut<RtcServerState>,
+) {
+    for error in decode_errors.read() {
+        if state.peers.contains(&error.peer) {
+            *state.decode_failures.entry(error.peer).or_default() += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        outbox::tests::{flush, world, PEER},
+        Backpressure, BackpressurePolicy, RtcSendQueues,
+    };
+    use bevy::ecs::system::RunSystemOnce;
+
+    #[test]
+    fn slow_clients_are_hung_up_on() {
+        let mut world = world(Some(Backpressure {
+            max_queued_bytes: 100,
+            policy: BackpressurePolicy::Disconnect,
+            ..default()
+        }));
+        world.init_resource::<ReceivedPackets>();
+        world.init_resource::<Events<RtcServerEvent>>();
+
+        flush(&mut world, 2, 100);
+        world.run_system_once(hang_up_slow_clients);
+        let events = world
+            .resource_mut::<Events<RtcServerEvent>>()
+            .drain()
+            .collect::<Vec<_>>();
+        assert!(matches!(events[..], [RtcServerEvent::ClientTooSlow(PEER)]));
+
+        // Nothing more is sent to the client
+        assert!(flush(&mut world, 1, 100).is_empty());
+        let stats = world
+            .resource::<RtcSendQueues>()
+            .get(PEER, "unreliable")
+            .unwrap();
+        assert_eq!(stats.queued_packets, 0);
+        assert_eq!(stats.dropped, 3);
+        world.run_system_once(hang_up_slow_clients);
+        assert!(world.resource::<Events<RtcServerEvent>>().is_empty());
+    }
+}
@@ -0,0 +1,162 @@
+//! Delta-compressed state snapshots.
+//!
+//! A [`Snapshot`] is state the server sends to clients every tick, e.g. the
+//! positions of every player. Rather than sending the whole state each time,
+//! the server keeps the last snapshot each client acknowledged, and only sends
+//! the fields which changed since then. Clients rebuild the full state and
+//! acknowledge it, so a lost packet only costs a slightly larger delta.
+//!
+//! Snapshots are registered with `add_server_snapshot_protocol` and
+//! `add_client_snapshot_protocol`, and sent and read as
+//! [`SnapshotPayload`]s with the usual `RtcServer` and `RtcClient` params:
+//!
+//! ```
+//! use bevy_rtc::snapshot::Snapshot;
+//! use serde::{Deserialize, Serialize};
+//!
+//! #[derive(Snapshot, Serialize, Deserialize, Debug, Clone, PartialEq)]
+//! pub struct WorldState {
+//!     /// Sent every time
+//!     pub tick: u32,
+//!     /// Only sent when it changed since the client's baseline
+//!     #[snapshot(diff)]
+//!     pub positions: Vec<(f32, f32)>,
+//! }
+//!
+//! let baseline = WorldState { tick: 1, positions: vec![(0.0, 0.0)] };
+//! let next = WorldState { tick: 2, ..baseline.clone() };
+//! let delta = next.diff(&baseline);
+//! assert_eq!(delta.positions, None);
+//! assert_eq!(baseline.apply(&delta), next);
+//! ```
+
+use crate::protocol::Protocol;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::fmt::Debug;
+
+// Note: Intentional name collision with the trait Snapshot!
+pub use proc_macro_protocol::Snapshot;
+
+/// How many snapshots each side remembers as possible baselines. A client
+/// which has not acknowledged any of the last this many snapshots is sent the
+/// full state.
+pub(crate) const SNAPSHOT_HISTORY: usize = 32;
+
+/// State which can be sent as a delta against an earlier copy of itself.
+///
+/// The derive macro sends fields marked `#[snapshot(diff)]` only when they
+/// differ from the baseline, and every other field every time. Marked fields
+/// must implement `PartialEq`.
+pub trait Snapshot: Debug + Clone + Send + Sync + Serialize + DeserializeOwned + 'static {
+    /// The changes between a snapshot and its baseline.
+    type Delta: Debug + Clone + Send + Sync + Serialize + DeserializeOwned + 'static;
+
+    /// The ID of this snapshot's [`SnapshotPayload`] on the wire. The derive
+    /// macro hashes the fully qualified type path with
+    /// [`protocol_id`](crate::protocol::protocol_id).
+    const PROTOCOL_ID: u16;
+
+    /// The name of this snapshot's [`SnapshotPayload`].
+    const PROTOCOL_NAME: &'static str;
+
+    /// Returns the changes from `baseline` to `self`.
+    fn diff(&self, baseline: &Self) -> Self::Delta;
+
+    /// Returns `self` with the changes of a delta applied.
+    fn apply(&self, delta: &Self::Delta) -> Self;
+}
+
+/// A snapshot sent from the server, and its sequence number.
+///
+/// Send the full state with `RtcServer<SnapshotPayload<S>>`, and it is replaced
+/// with a delta against each client's acknowledged baseline before it is sent.
+/// Payloads read with `RtcClient<SnapshotPayload<S>>` always hold the full
+/// state.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(bound = "")]
+pub struct SnapshotPayload<S: Snapshot> {
+    pub(crate) seq: u32,
+    pub(crate) body: SnapshotBody<S>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(bound = "")]
+pub(crate) enum SnapshotBody<S: Snapshot> {
+    /// The whole state.
+    Full(S),
+    /// The changes since the snapshot with the given sequence number.
+    Delta { baseline: u32, delta: S::Delta },
+}
+
+impl<S: Snapshot> SnapshotPayload<S> {
+    /// A snapshot of the full state, to be sent to clients.
+    pub fn new(state: S) -> Self {
+        Self {
+            seq: 0,
+            body: SnapshotBody::Full(state),
+        }
+    }
+
+    /// The sequence number of this snapshot, which increases with every
+    /// snapshot sent to a client.
+    pub fn seq(&self) -> u32 {
+        self.seq
+    }
+
+    /// The full state of this snapshot.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the payload is a delta which has not been applied yet, which
+    /// never happens for payloads read with `RtcClient`.
+    pub fn state(&self) -> &S {
+        match &self.body {
+            SnapshotBody::Full(state) => state,
+            SnapshotBody::Delta { .. } => panic!(
+                "`{}` has not been applied to its baseline",
+                S::PROTOCOL_NAME
+            ),
+        }
+    }
+
+    /// Take the full state of this snapshot.
+    ///
+    /// # Panics
+    ///
+    /// See [`SnapshotPayload::state`].
+    pub fn into_state(self) -> S {
+        match self.body {
+            SnapshotBody::Full(state) => state,
+            SnapshotBody::Delta { .. } => panic!(
+                "`{}` has not been applied to its baseline",
+                S::PROTOCOL_NAME
+            ),
+        }
+    }
+}
+
+impl<S: Snapshot> From<S> for SnapshotPayload<S> {
+    fn from(state: S) -> Self {
+        Self::new(state)
+    }
+}
+
+impl<S: Snapshot> Protocol for SnapshotPayload<S> {
+    fn id() -> u16 {
+        S::PROTOCOL_ID
+    }
+
+    fn reflect_name() -> &'static str {
+        S::PROTOCOL_NAME
+    }
+}
+
+/// Acknowledges the latest snapshot of a kind a client has received, so the
+/// server can use it as the baseline of the next delta.
+#[derive(Protocol, Serialize, Deserialize, Debug, Clone)]
+#[protocol(crate = "crate")]
+pub(crate) struct SnapshotAck {
+    /// The protocol ID of the snapshot.
+    pub id: u16,
+    pub seq: u32,
+}
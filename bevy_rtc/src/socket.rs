@@ -1,4 +1,4 @@
-use crate::events::SocketRecvEvent;
+use crate::{channel::Channels, events::SocketRecvEvent};
 use bevy::prelude::*;
 use bevy_matchbox::{prelude::MultipleChannels, MatchboxSocket};
 
@@ -11,19 +11,35 @@ pub type RtcSocketPlurality = MultipleChannels;
 pub const UNRELIABLE_CHANNEL_INDEX: usize = 0;
 /// The index of the reliable channel in the [`WebRtcSocket`].
 pub const RELIABLE_CHANNEL_INDEX: usize = 1;
+/// The index of the unordered unreliable channel in the [`WebRtcSocket`].
+pub const UNORDERED_UNRELIABLE_CHANNEL_INDEX: usize = 2;
+/// The index of the unordered reliable channel in the [`WebRtcSocket`].
+pub const RELIABLE_UNORDERED_CHANNEL_INDEX: usize = 3;
 
 pub fn common_socket_reader(
     mut socket: ResMut<RtcSocket>,
+    channels: Res<Channels>,
     mut event_wtr: EventWriter<SocketRecvEvent>,
 ) {
-    let messages = socket
-        .channel_mut(RELIABLE_CHANNEL_INDEX)
-        .receive()
-        .into_iter()
-        .chain(socket.channel_mut(UNRELIABLE_CHANNEL_INDEX).receive())
+    let messages = (0..channels.len())
+        .flat_map(|channel| socket.channel_mut(channel).receive())
         .map(SocketRecvEvent)
         .collect::<Vec<_>>();
     trace!("Received {} total messages", messages.len());
 
     event_wtr.send_batch(messages);
 }
+
+/// A socket whose message loop has stopped, so every channel is closed and
+/// fails to send.
+#[cfg(test)]
+pub(crate) fn closed_socket(channels: &Channels) -> RtcSocket {
+    use bevy::tasks::{IoTaskPool, TaskPool};
+    use bevy_matchbox::matchbox_socket::MessageLoopFuture;
+
+    IoTaskPool::get_or_init(TaskPool::new);
+    let (socket, message_loop) = channels.socket_builder("ws://localhost:0").build();
+    drop(message_loop);
+    let stopped: MessageLoopFuture = Box::pin(std::future::ready(Ok(())));
+    RtcSocket::from((socket, stopped))
+}
@@ -1,43 +1,96 @@
 use bevy::{ecs::system::Resource, reflect::erased_serde::Serialize};
 use bevy_matchbox::matchbox_socket::Packet;
 use serde::Deserialize;
+use std::{fmt, sync::Arc};
 
-use crate::{prelude::Protocol, protocol::RtcPacket};
+use crate::{
+    codec::{self, Codec, CodecError},
+    prelude::Protocol,
+    protocol::RtcPacket,
+};
 
-#[derive(Resource, Clone, Copy, PartialEq, Eq)]
+#[derive(Resource, Clone)]
 pub enum TransportEncoding {
     Json,
     #[cfg(feature = "binary")]
     #[cfg_attr(docsrs, doc(cfg(feature = "binary")))]
     Binary,
+    #[cfg(feature = "msgpack")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "msgpack")))]
+    MessagePack,
+    #[cfg(feature = "postcard")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "postcard")))]
+    Postcard,
+    /// A bit-packed binary format, which writes
+    /// [`Quantized`](crate::quantize::Quantized) floats with exactly their
+    /// number of bits, and booleans with one bit.
+    BitPacked,
+    /// A user-defined codec, see [`Codec`].
+    Custom(Arc<dyn Codec>),
 }
 
 impl TransportEncoding {
-    pub(crate) fn decode_packet<T>(&self, packet: &Packet) -> Option<T>
+    /// Use a user-defined codec.
+    pub fn custom(codec: impl Codec) -> Self {
+        Self::Custom(Arc::new(codec))
+    }
+
+    /// Returns the codec used by this encoding.
+    pub fn codec(&self) -> &dyn Codec {
+        match self {
+            TransportEncoding::Json => &codec::JsonCodec,
+            #[cfg(feature = "binary")]
+            TransportEncoding::Binary => &codec::BinaryCodec,
+            #[cfg(feature = "msgpack")]
+            TransportEncoding::MessagePack => &codec::MessagePackCodec,
+            #[cfg(feature = "postcard")]
+            TransportEncoding::Postcard => &codec::PostcardCodec,
+            TransportEncoding::BitPacked => &codec::BitPackedCodec,
+            TransportEncoding::Custom(codec) => codec.as_ref(),
+        }
+    }
+
+    pub(crate) fn decode_packet<T>(&self, packet: &Packet) -> Result<T, CodecError>
     where
         T: for<'a> Deserialize<'a> + Protocol,
     {
-        match self {
-            TransportEncoding::Json => serde_json::from_slice::<RtcPacket<T>>(packet).ok(),
-            #[cfg(feature = "binary")]
-            TransportEncoding::Binary => bincode::deserialize::<RtcPacket<T>>(packet).ok(),
+        let packet = RtcPacket::parse(packet).ok_or("packet is shorter than its header")?;
+        if packet.msg_id != T::id() {
+            return Err(
+                format!("expected protocol id {}, found {}", T::id(), packet.msg_id).into(),
+            );
         }
-        .filter(|packet| packet.msg_id == T::id())
-        .map(|packet| packet.data)
+        codec::decode_with(self.codec(), packet.data)
     }
 
-    pub(crate) fn encode_packet<T>(&self, v: &T) -> Packet
+    pub(crate) fn encode_packet<T>(&self, v: &T) -> Result<Packet, CodecError>
     where
         T: Serialize + Protocol,
     {
-        let packet = RtcPacket {
-            msg_id: T::id(),
-            data: v.clone(),
-        };
+        let mut buf = vec![];
+        RtcPacket::write_header(T::id(), 0, &mut buf);
+        self.codec().encode(v, &mut buf)?;
+        Ok(buf.into_boxed_slice())
+    }
+}
+
+impl PartialEq for TransportEncoding {
+    fn eq(&self, other: &Self) -> bool {
+        self.codec().name() == other.codec().name()
+    }
+}
+
+impl fmt::Debug for TransportEncoding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            TransportEncoding::Json => serde_json::to_string(&packet).unwrap().as_bytes().into(),
-            #[cfg(feature = "binary")]
-            TransportEncoding::Binary => bincode::serialize(&packet).unwrap().into_boxed_slice(),
+            TransportEncoding::Custom(codec) => write!(f, "Custom({})", codec.name()),
+            _ => f.write_str(self.codec().name()),
         }
     }
 }
+
+impl fmt::Display for TransportEncoding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.codec().name())
+    }
+}
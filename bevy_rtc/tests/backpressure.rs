@@ -0,0 +1,42 @@
+#![cfg(feature = "server")]
+
+use bevy::prelude::*;
+use bevy_rtc::prelude::*;
+
+fn app(backpressure: Option<Backpressure>) -> App {
+    let mut app = App::new();
+    app.add_plugins(MinimalPlugins)
+        .add_plugins(RtcServerPlugin {
+            channels: vec![ChannelConfig::unreliable("voice")],
+            backpressure,
+            ..default()
+        });
+    app
+}
+
+#[test]
+fn backpressure_is_off_by_default() {
+    let app = app(None);
+
+    assert!(app.world.get_resource::<Backpressure>().is_none());
+    assert_eq!(app.world.resource::<RtcSendQueues>().iter().count(), 0);
+}
+
+#[test]
+fn backpressure_retries_by_default() {
+    let app = app(Some(Backpressure::default()));
+
+    let backpressure = app.world.resource::<Backpressure>();
+    assert_eq!(backpressure.policy, BackpressurePolicy::Retry);
+}
+
+#[test]
+fn send_queues_are_looked_up_by_channel_name() {
+    let app = app(Some(Backpressure::default()));
+
+    let queues = app.world.resource::<RtcSendQueues>();
+    let peer = bevy_rtc::bevy_matchbox::prelude::PeerId(Default::default());
+    assert_eq!(queues.get(peer, "voice"), None);
+    assert_eq!(queues.get(peer, "undeclared"), None);
+    assert_eq!(queues.queued_bytes(peer), 0);
+}
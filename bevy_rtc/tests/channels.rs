@@ -0,0 +1,59 @@
+#![cfg(feature = "server")]
+
+use bevy::prelude::*;
+use bevy_rtc::prelude::*;
+use serde::{Deserialize, Serialize};
+
+#[derive(Protocol, Serialize, Deserialize, Debug, Clone)]
+struct VoicePayload {
+    samples: Vec<u8>,
+}
+
+fn app(channels: Vec<ChannelConfig>, channel: &str) -> App {
+    let mut app = App::new();
+    app.add_plugins(MinimalPlugins)
+        .add_plugins(RtcServerPlugin {
+            channels,
+            ..default()
+        })
+        .configure_server_protocol::<VoicePayload>(ProtocolOptions {
+            channel: Some(channel.to_string()),
+            ..default()
+        })
+        .add_server_wo_protocol::<VoicePayload>();
+    app
+}
+
+#[test]
+fn protocols_are_bound_to_declared_channels() {
+    let mut app = app(vec![ChannelConfig::unreliable("voice")], "voice");
+    app.finish();
+
+    let registry = app.world.resource::<RtcProtocolRegistry>();
+    let voice = registry.get_for::<VoicePayload>().unwrap();
+    assert_eq!(voice.channel(), Some("voice"));
+}
+
+#[test]
+fn protocols_can_be_bound_to_built_in_channels() {
+    for channel in [
+        bevy_rtc::UNRELIABLE_CHANNEL,
+        bevy_rtc::RELIABLE_CHANNEL,
+        bevy_rtc::UNORDERED_UNRELIABLE_CHANNEL,
+        bevy_rtc::RELIABLE_UNORDERED_CHANNEL,
+    ] {
+        app(vec![], channel).finish();
+    }
+}
+
+#[test]
+#[should_panic(expected = "bound to channel `voice`, which is not declared")]
+fn binding_an_undeclared_channel_panics() {
+    app(vec![ChannelConfig::reliable("bulk")], "voice").finish();
+}
+
+#[test]
+#[should_panic(expected = "channel `reliable` is declared more than once")]
+fn redeclaring_a_channel_panics() {
+    app(vec![ChannelConfig::unreliable("reliable")], "voice");
+}
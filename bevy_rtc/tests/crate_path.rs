@@ -0,0 +1,46 @@
+//! Derives through a re-export of `bevy_rtc`, as a wrapper crate would.
+
+mod my_net {
+    pub use ::bevy_rtc as rtc;
+}
+
+mod payloads {
+    use serde::{Deserialize, Serialize};
+
+    // Shadows the real crate, so only the configured path can resolve
+    #[allow(dead_code)]
+    mod bevy_rtc {}
+
+    #[derive(crate::my_net::rtc::protocol::Protocol, Serialize, Deserialize, Debug, Clone)]
+    #[protocol(crate = "crate::my_net::rtc")]
+    pub struct Ping {
+        pub tick: u32,
+    }
+
+    #[derive(crate::my_net::rtc::snapshot::Snapshot, Serialize, Deserialize, Debug, Clone)]
+    #[snapshot(crate = "crate::my_net::rtc")]
+    pub struct World {
+        #[snapshot(diff)]
+        pub tick: u32,
+    }
+}
+
+#[test]
+fn derives_use_the_configured_crate_path() {
+    use bevy_rtc::{
+        protocol::{protocol_id, Protocol},
+        snapshot::{Snapshot, SnapshotPayload},
+    };
+
+    assert_eq!(payloads::Ping::reflect_name(), "Ping");
+    assert_eq!(
+        payloads::Ping::id(),
+        protocol_id("crate_path::payloads::Ping")
+    );
+    assert_eq!(
+        SnapshotPayload::<payloads::World>::reflect_name(),
+        "SnapshotPayload<World>"
+    );
+    let world = payloads::World { tick: 1 };
+    assert_eq!(world.diff(&world).tick, None);
+}
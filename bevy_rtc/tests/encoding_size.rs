@@ -0,0 +1,81 @@
+#![cfg(all(feature = "binary", feature = "postcard"))]
+
+use bevy_rtc::{protocol::Protocol, TransportEncoding};
+use serde::{Deserialize, Serialize};
+
+#[derive(Protocol, Serialize, Deserialize, Debug, Clone)]
+struct DrawLinePayload {
+    x1: f32,
+    y1: f32,
+    x2: f32,
+    y2: f32,
+}
+
+#[derive(Protocol, Serialize, Deserialize, Debug, Clone)]
+struct InputPayload {
+    tick: u32,
+    player: u16,
+    buttons: u8,
+    cursor: (i32, i32),
+    name: String,
+}
+
+fn input() -> InputPayload {
+    InputPayload {
+        tick: 120,
+        player: 3,
+        buttons: 0b101,
+        cursor: (-12, 40),
+        name: "ferris".to_string(),
+    }
+}
+
+fn draw_line() -> DrawLinePayload {
+    DrawLinePayload {
+        x1: 0.25,
+        y1: 0.5,
+        x2: 0.3,
+        y2: 0.55,
+    }
+}
+
+fn size<M: Protocol>(message: &M, encoding: TransportEncoding) -> usize {
+    message.to_packet(&encoding).unwrap().len()
+}
+
+#[test]
+fn postcard_shrinks_small_integers() {
+    let json = size(&input(), TransportEncoding::Json);
+    let binary = size(&input(), TransportEncoding::Binary);
+    let postcard = size(&input(), TransportEncoding::Postcard);
+
+    // 3 byte header + 1 (tick) + 1 (player) + 1 (buttons) + 2 (cursor) + 7 (name)
+    assert_eq!(postcard, 15);
+    // 3 byte header + 4 (tick) + 2 (player) + 1 (buttons) + 8 (cursor) + 14 (name)
+    assert_eq!(binary, 32);
+    assert!(
+        postcard < binary && binary < json,
+        "{postcard} < {binary} < {json}"
+    );
+}
+
+#[test]
+fn postcard_matches_binary_for_floats() {
+    let json = size(&draw_line(), TransportEncoding::Json);
+    let binary = size(&draw_line(), TransportEncoding::Binary);
+    let postcard = size(&draw_line(), TransportEncoding::Postcard);
+
+    // Floats are not variable length
+    assert_eq!(postcard, 3 + 16);
+    assert_eq!(binary, 3 + 16);
+    assert!(postcard < json, "{postcard} < {json}");
+}
+
+#[test]
+fn postcard_round_trips() {
+    let packet = input().to_packet(&TransportEncoding::Postcard).unwrap();
+    let decoded = InputPayload::from_packet(&packet, &TransportEncoding::Postcard).unwrap();
+    assert_eq!(decoded.tick, 120);
+    assert_eq!(decoded.cursor, (-12, 40));
+    assert_eq!(decoded.name, "ferris");
+}
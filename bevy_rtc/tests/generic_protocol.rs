@@ -0,0 +1,87 @@
+use bevy::reflect::TypePath;
+use bevy_rtc::{
+    protocol::{protocol_id, Protocol},
+    TransportEncoding,
+};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::fmt::Debug;
+
+#[derive(TypePath, Serialize, Deserialize, Debug, Clone, PartialEq)]
+struct Player {
+    name: String,
+}
+
+#[derive(TypePath, Serialize, Deserialize, Debug, Clone, PartialEq)]
+struct Npc {
+    kind: u8,
+}
+
+#[derive(Protocol, Serialize, Deserialize, Debug, Clone, PartialEq)]
+struct Spawn<T> {
+    tick: u32,
+    entity: T,
+}
+
+#[derive(Protocol, Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(bound = "T: Serialize + DeserializeOwned")]
+struct Batch<T, const N: usize>
+where
+    T: Debug + Default,
+{
+    items: Vec<T>,
+}
+
+#[test]
+fn instantiations_have_different_ids() {
+    assert_ne!(Spawn::<Player>::id(), Spawn::<Npc>::id());
+    assert_ne!(Batch::<u8, 1>::id(), Batch::<u8, 2>::id());
+    assert_eq!(Spawn::<Player>::id(), Spawn::<Player>::id());
+}
+
+#[test]
+fn instantiations_hash_the_type_path_of_their_arguments() {
+    assert_eq!(
+        Spawn::<Player>::id(),
+        protocol_id("generic_protocol::Spawn<generic_protocol::Player>")
+    );
+    assert_eq!(
+        Batch::<u8, 2>::id(),
+        protocol_id("generic_protocol::Batch<u8, 2>")
+    );
+}
+
+#[test]
+fn instantiations_are_named_after_their_arguments() {
+    assert_eq!(Spawn::<Player>::reflect_name(), "Spawn<Player>");
+    assert_eq!(Spawn::<Npc>::reflect_name(), "Spawn<Npc>");
+    assert_eq!(Batch::<u8, 1>::reflect_name(), "Batch<u8, 1>");
+}
+
+#[test]
+fn generic_protocols_round_trip() {
+    let encoding = TransportEncoding::Json;
+    let spawn = Spawn {
+        tick: 7,
+        entity: Player {
+            name: "ferris".to_string(),
+        },
+    };
+    let packet = spawn.to_packet(&encoding).unwrap();
+    assert_eq!(
+        Spawn::<Player>::from_packet(&packet, &encoding).unwrap(),
+        spawn
+    );
+    // Another instantiation does not accept it
+    assert!(Spawn::<Npc>::from_packet(&packet, &encoding).is_err());
+}
+
+#[cfg(feature = "server")]
+#[test]
+fn instantiations_can_be_registered_together() {
+    use bevy::prelude::*;
+    use bevy_rtc::server::AddServerProtocolExt;
+
+    App::new()
+        .add_server_wo_protocol::<Spawn<Player>>()
+        .add_server_wo_protocol::<Spawn<Npc>>();
+}
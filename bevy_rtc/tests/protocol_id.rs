@@ -0,0 +1,19 @@
+use bevy_rtc::protocol::{protocol_id, Protocol};
+use serde::{Deserialize, Serialize};
+
+#[derive(Protocol, Serialize, Deserialize, Debug, Clone)]
+struct PingPayload;
+
+#[test]
+fn protocol_ids_match_known_vectors() {
+    // Protocol IDs are part of the wire format, these must never change
+    assert_eq!(protocol_id(""), 7385);
+    assert_eq!(protocol_id("a"), 52512);
+    assert_eq!(protocol_id("foobar"), 18164);
+    assert_eq!(protocol_id("my_crate::net::ChatPayload"), 32652);
+}
+
+#[test]
+fn derived_ids_hash_the_module_path() {
+    assert_eq!(PingPayload::id(), 24927);
+}
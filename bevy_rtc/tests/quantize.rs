@@ -0,0 +1,166 @@
+use bevy_rtc::{
+    protocol::Protocol,
+    quantize::{Quantized, SignedUnit, Unit},
+    quantized_range, TransportEncoding,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+quantized_range!(Angle, 0.0, std::f32::consts::TAU);
+
+/// Checks every quantized value is within half a step of the original, give or
+/// take float rounding.
+fn assert_error_bound<R: bevy_rtc::quantize::QuantizedRange, const BITS: u32>() {
+    let bound = Quantized::<R, BITS>::MAX_ERROR + (R::MAX - R::MIN) * f32::EPSILON;
+    for i in 0..=10_000 {
+        let value = R::MIN + (R::MAX - R::MIN) * i as f32 / 10_000.0;
+        let quantized = Quantized::<R, BITS>::new(value);
+        let error = (quantized.get() - value).abs();
+        assert!(
+            error <= bound,
+            "{value} quantized to {BITS} bits was {}, off by {error} > {bound}",
+            quantized.get()
+        );
+    }
+}
+
+#[test]
+fn round_trip_error_is_within_half_a_step() {
+    assert_error_bound::<Unit, 4>();
+    assert_error_bound::<Unit, 8>();
+    assert_error_bound::<Unit, 12>();
+    assert_error_bound::<Unit, 16>();
+    assert_error_bound::<Unit, 24>();
+    assert_error_bound::<SignedUnit, 12>();
+    assert_error_bound::<Angle, 10>();
+
+    assert_eq!(Quantized::<Unit, 8>::MAX_ERROR, 1.0 / 255.0 / 2.0);
+    assert_eq!(Quantized::<SignedUnit, 16>::MAX_ERROR, 2.0 / 65535.0 / 2.0);
+}
+
+#[test]
+fn range_ends_are_exact() {
+    assert_eq!(Quantized::<Unit, 12>::new(0.0).get(), 0.0);
+    assert_eq!(Quantized::<Unit, 12>::new(1.0).get(), 1.0);
+    assert_eq!(Quantized::<SignedUnit, 12>::new(-1.0).get(), -1.0);
+    assert_eq!(Quantized::<SignedUnit, 12>::new(1.0).get(), 1.0);
+}
+
+#[test]
+fn out_of_range_values_are_clamped() {
+    assert_eq!(Quantized::<Unit, 12>::new(-3.0).get(), 0.0);
+    assert_eq!(Quantized::<Unit, 12>::new(7.5).get(), 1.0);
+    assert_eq!(Quantized::<Unit, 12>::new(f32::NAN).get(), 0.0);
+    assert_eq!(Quantized::<Unit, 12>::new(f32::INFINITY).get(), 1.0);
+}
+
+#[derive(Protocol, Serialize, Deserialize, Debug, Clone, PartialEq)]
+struct DrawLinePayload {
+    x1: Quantized<Unit, 12>,
+    y1: Quantized<Unit, 12>,
+    x2: Quantized<Unit, 12>,
+    y2: Quantized<Unit, 12>,
+}
+
+fn draw_line() -> DrawLinePayload {
+    DrawLinePayload {
+        x1: 0.25.into(),
+        y1: 0.5.into(),
+        x2: 0.3.into(),
+        y2: 0.55.into(),
+    }
+}
+
+fn encodings() -> Vec<TransportEncoding> {
+    vec![
+        TransportEncoding::Json,
+        TransportEncoding::BitPacked,
+        #[cfg(feature = "binary")]
+        TransportEncoding::Binary,
+        #[cfg(feature = "msgpack")]
+        TransportEncoding::MessagePack,
+        #[cfg(feature = "postcard")]
+        TransportEncoding::Postcard,
+    ]
+}
+
+#[test]
+fn quantized_values_round_trip_in_every_encoding() {
+    for encoding in encodings() {
+        let packet = draw_line().to_packet(&encoding).unwrap();
+        let decoded = DrawLinePayload::from_packet(&packet, &encoding)
+            .unwrap_or_else(|e| panic!("{encoding}: {e}"));
+        assert_eq!(decoded, draw_line(), "{encoding}");
+    }
+}
+
+#[test]
+fn bit_packed_quantized_values_use_their_bits() {
+    let packet = draw_line()
+        .to_packet(&TransportEncoding::BitPacked)
+        .unwrap();
+    // 4 * 12 bits
+    assert_eq!(packet.len(), 3 + 6);
+}
+
+#[cfg(feature = "binary")]
+#[test]
+fn binary_quantized_values_use_the_smallest_integer() {
+    let packet = draw_line().to_packet(&TransportEncoding::Binary).unwrap();
+    // 4 * u16
+    assert_eq!(packet.len(), 3 + 8);
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+enum Action {
+    Idle,
+    Move(Quantized<SignedUnit, 8>, Quantized<SignedUnit, 8>),
+    Say { text: String, loud: bool },
+}
+
+#[derive(Protocol, Serialize, Deserialize, Debug, Clone, PartialEq)]
+struct MixedPayload {
+    tick: u32,
+    health: i16,
+    alive: bool,
+    name: Option<String>,
+    actions: Vec<Action>,
+    scores: BTreeMap<u8, i64>,
+    heading: Quantized<Angle, 10>,
+    ratio: f64,
+    glyph: char,
+}
+
+#[test]
+fn bit_packed_round_trips_any_payload() {
+    let payload = MixedPayload {
+        tick: 123_456,
+        health: -40,
+        alive: true,
+        name: Some("ferris 🦀".to_string()),
+        actions: vec![
+            Action::Idle,
+            Action::Move((-0.5).into(), 0.75.into()),
+            Action::Say {
+                text: "hi".to_string(),
+                loud: false,
+            },
+        ],
+        scores: BTreeMap::from([(1, -7), (200, i64::MAX)]),
+        heading: 2.0.into(),
+        ratio: 0.1,
+        glyph: 'λ',
+    };
+    let encoding = TransportEncoding::BitPacked;
+    let packet = payload.to_packet(&encoding).unwrap();
+    let decoded = MixedPayload::from_packet(&packet, &encoding).unwrap();
+    assert_eq!(decoded, payload);
+}
+
+#[test]
+fn bit_packed_rejects_truncated_payloads() {
+    let encoding = TransportEncoding::BitPacked;
+    let packet = draw_line().to_packet(&encoding).unwrap();
+    let truncated = &packet[..packet.len() - 1];
+    assert!(DrawLinePayload::from_packet(&truncated.into(), &encoding).is_err());
+}
@@ -0,0 +1,41 @@
+#![cfg(feature = "client")]
+
+use bevy::{ecs::system::RunSystemOnce, prelude::*};
+use bevy_rtc::prelude::*;
+use serde::{Deserialize, Serialize};
+
+#[derive(Protocol, Serialize, Deserialize, Debug, Clone)]
+#[protocol(id = 7)]
+struct TradePayload {
+    item: u32,
+}
+
+#[test]
+fn tracked_payloads_get_distinct_handles() {
+    let mut app = App::new();
+    app.add_client_wo_protocol::<TradePayload>();
+
+    let (first, second) = app
+        .world
+        .run_system_once(|mut client: RtcClient<TradePayload>| {
+            (
+                client.reliable_to_host_tracked(TradePayload { item: 1 }),
+                client.reliable_to_host_tracked(TradePayload { item: 2 }),
+            )
+        });
+    assert_ne!(first, second);
+    assert_eq!(first.protocol(), 7);
+    assert_eq!(second.protocol(), 7);
+}
+
+#[test]
+#[should_panic(expected = "registered read only")]
+fn tracking_a_read_only_protocol_panics() {
+    let mut app = App::new();
+    app.add_client_ro_protocol::<TradePayload>(1);
+
+    app.world
+        .run_system_once(|mut client: RtcClient<TradePayload>| {
+            client.reliable_to_host_tracked(TradePayload { item: 1 });
+        });
+}
@@ -0,0 +1,86 @@
+#![cfg(feature = "server")]
+
+use bevy::prelude::*;
+use bevy_rtc::{prelude::*, protocol::ProtocolDirection, ProtocolStats};
+use serde::{Deserialize, Serialize};
+
+#[derive(Protocol, Serialize, Deserialize, Debug, Clone)]
+#[protocol(id = 1)]
+struct ChatPayload {
+    message: String,
+}
+
+#[derive(Protocol, Serialize, Deserialize, Debug, Clone)]
+#[protocol(id = 2)]
+struct InputPayload {
+    x: f32,
+}
+
+#[derive(Protocol, Serialize, Deserialize, Debug, Clone)]
+#[protocol(id = 3)]
+struct StatePayload {
+    tick: u32,
+}
+
+fn app() -> App {
+    let mut app = App::new();
+    app.configure_server_protocol::<InputPayload>(ProtocolOptions {
+        encoding: Some(TransportEncoding::Json),
+        ..default()
+    })
+    .configure_server_protocol::<StatePayload>(ProtocolOptions {
+        sequenced: true,
+        ..default()
+    })
+    .add_server_rw_protocol::<ChatPayload>(10)
+    .add_server_ro_protocol::<InputPayload>(2)
+    .add_server_wo_protocol::<StatePayload>();
+    app
+}
+
+#[test]
+fn registry_lists_protocols_in_registration_order() {
+    let app = app();
+    let registry = app.world.resource::<RtcProtocolRegistry>();
+
+    let ids: Vec<u16> = registry.iter().map(|p| p.id()).collect();
+    assert_eq!(ids, [1, 2, 3]);
+    assert_eq!(registry.len(), 3);
+    assert!(registry.get(4).is_none());
+}
+
+#[test]
+fn registry_records_registration_options() {
+    let app = app();
+    let registry = app.world.resource::<RtcProtocolRegistry>();
+
+    let chat = registry.get_for::<ChatPayload>().unwrap();
+    assert_eq!(chat.name(), "ChatPayload");
+    assert_eq!(chat.direction(), ProtocolDirection::ReadWrite);
+    assert_eq!(chat.bound(), Some(10));
+    assert_eq!(chat.encoding(), None);
+    assert!(!chat.is_sequenced());
+
+    let input = registry.get_for::<InputPayload>().unwrap();
+    assert_eq!(input.direction(), ProtocolDirection::ReadOnly);
+    assert_eq!(input.bound(), Some(2));
+    assert_eq!(input.encoding(), Some("Json"));
+
+    let state = registry.get_for::<StatePayload>().unwrap();
+    assert_eq!(state.direction(), ProtocolDirection::WriteOnly);
+    assert_eq!(state.bound(), None);
+    assert!(state.is_sequenced());
+    assert_eq!(state.stats(), ProtocolStats::default());
+}
+
+#[derive(Protocol, Serialize, Deserialize, Debug, Clone)]
+#[protocol(id = 1)]
+struct OtherChatPayload;
+
+#[test]
+#[should_panic(expected = "protocol id collision")]
+fn registering_two_protocols_with_the_same_id_panics() {
+    let mut app = App::new();
+    app.add_server_rw_protocol::<ChatPayload>(10)
+        .add_server_rw_protocol::<OtherChatPayload>(10);
+}
@@ -0,0 +1,171 @@
+#![cfg(all(feature = "schema", feature = "server"))]
+
+use bevy::{prelude::*, reflect::TypePath};
+use bevy_rtc::{prelude::*, schema::RtcSchemaExt, snapshot::SnapshotPayload};
+use serde::{Deserialize, Serialize};
+
+#[derive(Protocol, Serialize, Deserialize, Debug, Clone)]
+#[protocol(id = 1)]
+struct ChatPayload {
+    from: Option<String>,
+    message: String,
+}
+
+#[derive(Protocol, Serialize, Deserialize, Debug, Clone)]
+#[protocol(id = 2)]
+enum InputPayload {
+    Jump,
+    Move(f32, f32),
+    Aim { yaw: f32 },
+}
+
+#[derive(Snapshot, Serialize, Deserialize, Debug, Clone, PartialEq)]
+struct WorldSnapshot {
+    tick: u32,
+}
+
+#[derive(Snapshot, Serialize, Deserialize, Debug, Clone, PartialEq)]
+struct ScoreSnapshot {
+    #[snapshot(diff)]
+    points: u32,
+}
+
+#[derive(TypePath, Serialize, Deserialize, Debug, Clone)]
+struct Player {
+    name: String,
+}
+
+#[derive(TypePath, Serialize, Deserialize, Debug, Clone)]
+struct Npc {
+    kind: u8,
+}
+
+#[derive(Protocol, Serialize, Deserialize, Debug, Clone)]
+struct Spawn<T> {
+    tick: u32,
+    entity: T,
+}
+
+fn app() -> App {
+    let mut app = App::new();
+    app.insert_resource(TransportEncoding::Json)
+        .configure_server_protocol::<InputPayload>(ProtocolOptions {
+            encoding: Some(TransportEncoding::Binary),
+            ..default()
+        })
+        .add_server_rw_protocol::<ChatPayload>(2)
+        .add_server_ro_protocol::<InputPayload>(2);
+    app
+}
+
+#[test]
+fn typescript_declares_payloads_and_ids() {
+    let typescript = app().protocol_schema().unwrap().to_typescript();
+
+    assert!(typescript.contains("export const HEADER_SIZE = 3;"));
+    assert!(
+        typescript.contains("an LZ4 block (not an LZ4 frame) prefixed with its uncompressed size")
+    );
+    assert!(typescript.contains("export const FLAG_BATCHED = 2;"));
+    assert!(typescript.contains("export const FLAG_FRAGMENT = 4;"));
+    assert!(typescript.contains("export const FLAG_SEQUENCED = 8;"));
+    assert!(typescript.contains("export const FLAG_TRACKED = 16;"));
+    assert!(typescript.contains("  \"ChatPayload\": 1,\n  \"InputPayload\": 2,"));
+    assert!(typescript.contains("  \"ChatPayload\": \"Json\",\n  \"InputPayload\": \"Binary\","));
+    assert!(
+        typescript.contains("export type ChatPayload = { from: string | null; message: string };")
+    );
+    assert!(typescript.contains(
+        "export type InputPayload = \"Jump\" | { Move: [number, number] } | { Aim: { yaw: number } };"
+    ));
+}
+
+#[test]
+fn json_schema_describes_protocols() {
+    let schema = app().protocol_schema().unwrap().to_json_schema();
+
+    assert_eq!(schema["x-envelope"]["header_size"], 3);
+    assert_eq!(schema["x-envelope"]["compression"]["size"]["type"], "u32");
+    assert_eq!(schema["x-envelope"]["compression"]["after"], "receipt");
+    assert_eq!(schema["x-envelope"]["flags"]["bits"]["batched"], 2);
+    assert_eq!(schema["x-envelope"]["batch"]["length"]["type"], "u16");
+    assert_eq!(schema["x-envelope"]["flags"]["bits"]["fragment"], 4);
+    assert_eq!(schema["x-envelope"]["fragment"]["count"]["offset"], 4);
+    assert_eq!(schema["x-envelope"]["flags"]["bits"]["sequenced"], 8);
+    assert_eq!(schema["x-envelope"]["sequence"]["offset"], 3);
+    assert_eq!(schema["x-envelope"]["flags"]["bits"]["tracked"], 16);
+    assert_eq!(schema["x-envelope"]["receipt"]["after"], "sequence");
+    let protocols = schema["oneOf"].as_array().unwrap();
+    assert_eq!(protocols.len(), 2);
+    assert_eq!(protocols[0]["$ref"], "#/definitions/ChatPayload");
+    assert_eq!(protocols[0]["x-msg-id"], 1);
+    assert_eq!(protocols[0]["x-reflect-name"], "ChatPayload");
+    assert_eq!(protocols[0]["x-direction"], "rw");
+    assert_eq!(protocols[1]["x-direction"], "ro");
+    assert_eq!(protocols[1]["x-encoding"], "Binary");
+    assert_eq!(
+        schema["definitions"]["ChatPayload"]["required"],
+        serde_json::json!(["from", "message"])
+    );
+}
+
+#[test]
+fn typescript_quotes_protocol_names() {
+    let mut app = app();
+    app.add_server_snapshot_protocol::<WorldSnapshot>();
+    let typescript = app.protocol_schema().unwrap().to_typescript();
+
+    let id = SnapshotPayload::<WorldSnapshot>::id();
+    assert!(typescript.contains(&format!("  \"SnapshotPayload<WorldSnapshot>\": {id},")));
+    assert!(typescript.contains("  \"SnapshotPayload<WorldSnapshot>\": \"Json\","));
+    assert!(
+        typescript.contains("  \"SnapshotPayload<WorldSnapshot>\": SnapshotPayload_WorldSnapshot;")
+    );
+}
+
+#[test]
+fn generic_instantiations_have_their_own_types() {
+    let mut app = app();
+    app.add_server_wo_protocol::<Spawn<Player>>()
+        .add_server_wo_protocol::<Spawn<Npc>>();
+    let schema = app.protocol_schema().unwrap();
+    let typescript = schema.to_typescript();
+
+    assert!(typescript.contains("export type Spawn_Player = { tick: number; entity: Player };"));
+    assert!(typescript.contains("export type Spawn_Npc = { tick: number; entity: Npc };"));
+    assert!(typescript.contains("  \"Spawn<Player>\": Spawn_Player;"));
+    assert!(typescript.contains("  \"Spawn<Npc>\": Spawn_Npc;"));
+    // Types which are the same in every protocol keep their name
+    assert!(typescript.contains("export type Player = { name: string };"));
+    assert!(typescript.contains("export type ChatPayload = "));
+
+    let json = schema.to_json_schema();
+    let spawn = json["oneOf"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|protocol| protocol["x-reflect-name"] == "Spawn<Npc>")
+        .unwrap();
+    assert_eq!(spawn["$ref"], "#/definitions/Spawn_Npc");
+    assert_eq!(
+        json["definitions"]["Spawn_Npc"]["properties"]["entity"]["$ref"],
+        "#/definitions/Npc"
+    );
+}
+
+#[test]
+fn snapshot_protocols_have_their_own_types() {
+    let mut app = app();
+    app.add_server_snapshot_protocol::<WorldSnapshot>()
+        .add_server_snapshot_protocol::<ScoreSnapshot>();
+    let typescript = app.protocol_schema().unwrap().to_typescript();
+
+    assert!(typescript.contains("export type SnapshotPayload_WorldSnapshot = "));
+    assert!(typescript.contains("export type SnapshotPayload_ScoreSnapshot = "));
+    assert!(typescript.contains("export type SnapshotBody_WorldSnapshot = "));
+    assert!(typescript.contains("export type SnapshotBody_ScoreSnapshot = "));
+    assert!(!typescript.contains("export type SnapshotPayload = "));
+    assert!(
+        typescript.contains("  \"SnapshotPayload<ScoreSnapshot>\": SnapshotPayload_ScoreSnapshot;")
+    );
+}
@@ -0,0 +1,104 @@
+#![cfg(feature = "client")]
+
+use bevy::{ecs::system::RunSystemOnce, prelude::*};
+use bevy_rtc::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::{cell::Cell, time::Duration};
+
+#[derive(Protocol, Serialize, Deserialize, Debug, Clone)]
+struct PositionPayload {
+    x: f32,
+}
+
+/// Calls a lazy send method three times, returning how many payloads were built.
+fn build_three(app: &mut App) -> u32 {
+    app.world
+        .run_system_once(|mut client: RtcClient<PositionPayload>| {
+            let built = Cell::new(0);
+            for _ in 0..3 {
+                client.unreliable_to_host_with(|| {
+                    built.set(built.get() + 1);
+                    PositionPayload { x: 0.0 }
+                });
+            }
+            built.get()
+        })
+}
+
+/// Advances the clock of the send rate limiter.
+fn advance(app: &mut App, millis: u64) {
+    app.world
+        .resource_mut::<Time<Real>>()
+        .advance_by(Duration::from_millis(millis));
+}
+
+#[test]
+fn lazy_sends_are_unlimited_by_default() {
+    let mut app = App::new();
+    app.add_client_wo_protocol::<PositionPayload>();
+
+    assert_eq!(build_three(&mut app), 3);
+}
+
+#[test]
+fn lazy_sends_wait_for_a_free_slot() {
+    let mut app = App::new();
+    app.configure_client_protocol::<PositionPayload>(ProtocolOptions {
+        send_rate: Some(SendRate::hz(1.0)),
+        ..default()
+    })
+    .add_client_wo_protocol::<PositionPayload>();
+
+    assert_eq!(build_three(&mut app), 1);
+    assert_eq!(build_three(&mut app), 0);
+}
+
+#[test]
+fn lazy_sends_resume_when_the_clock_advances() {
+    let mut app = App::new();
+    app.configure_client_protocol::<PositionPayload>(ProtocolOptions {
+        send_rate: Some(SendRate::hz(10.0)),
+        ..default()
+    })
+    .add_client_wo_protocol::<PositionPayload>();
+
+    assert_eq!(build_three(&mut app), 1);
+    advance(&mut app, 50);
+    assert_eq!(build_three(&mut app), 0);
+    advance(&mut app, 50);
+    assert_eq!(build_three(&mut app), 1);
+    // Slots missed while idle are not sent in a burst
+    advance(&mut app, 1000);
+    assert_eq!(build_three(&mut app), 1);
+}
+
+#[test]
+fn send_rate_can_be_configured_after_registration() {
+    let mut app = App::new();
+    app.add_client_wo_protocol::<PositionPayload>()
+        .configure_client_protocol::<PositionPayload>(ProtocolOptions {
+            send_rate: Some(SendRate::hz(1.0).per_peer()),
+            ..default()
+        });
+
+    assert_eq!(build_three(&mut app), 1);
+    let registry = app.world.resource::<RtcProtocolRegistry>();
+    let position = registry.get_for::<PositionPayload>().unwrap();
+    assert_eq!(position.send_rate(), Some(SendRate::hz(1.0).per_peer()));
+}
+
+#[test]
+fn lazy_sends_do_not_limit_eager_sends() {
+    let mut app = App::new();
+    app.configure_client_protocol::<PositionPayload>(ProtocolOptions {
+        send_rate: Some(SendRate::hz(1.0)),
+        ..default()
+    })
+    .add_client_wo_protocol::<PositionPayload>();
+
+    app.world
+        .run_system_once(|mut client: RtcClient<PositionPayload>| {
+            client.unreliable_to_host(PositionPayload { x: 0.0 });
+        });
+    assert_eq!(build_three(&mut app), 1);
+}
@@ -0,0 +1,75 @@
+#![cfg(feature = "binary")]
+
+use bevy_rtc::{
+    protocol::Protocol,
+    snapshot::{Snapshot, SnapshotPayload},
+    TransportEncoding,
+};
+use serde::{Deserialize, Serialize};
+
+#[derive(Snapshot, Serialize, Deserialize, Debug, Clone, PartialEq)]
+struct WorldSnapshot {
+    tick: u32,
+    #[snapshot(diff)]
+    positions: Vec<(f32, f32)>,
+    #[snapshot(diff)]
+    scores: Vec<u32>,
+}
+
+fn world() -> WorldSnapshot {
+    WorldSnapshot {
+        tick: 1,
+        positions: vec![(1.0, 2.0); 16],
+        scores: vec![0; 16],
+    }
+}
+
+#[test]
+fn unchanged_fields_are_omitted() {
+    let baseline = world();
+    let mut next = world();
+    next.tick = 2;
+    next.scores[3] = 10;
+
+    let delta = next.diff(&baseline);
+    assert_eq!(delta.tick, 2);
+    assert_eq!(delta.positions, None);
+    assert_eq!(delta.scores.as_ref(), Some(&next.scores));
+    assert_eq!(baseline.apply(&delta), next);
+}
+
+#[test]
+fn deltas_are_smaller_than_full_state() {
+    let baseline = world();
+    let mut next = world();
+    next.tick = 2;
+
+    let encoding = TransportEncoding::Binary;
+    let full = bincode::serialize(&next).unwrap();
+    let delta = bincode::serialize(&next.diff(&baseline)).unwrap();
+    assert!(
+        delta.len() < full.len() / 10,
+        "{} >= {}",
+        delta.len(),
+        full.len()
+    );
+
+    // Full snapshots round trip as protocols
+    let packet = SnapshotPayload::new(next.clone())
+        .to_packet(&encoding)
+        .unwrap();
+    let decoded = SnapshotPayload::<WorldSnapshot>::from_packet(&packet, &encoding).unwrap();
+    assert_eq!(decoded.state(), &next);
+}
+
+#[test]
+fn snapshot_ids_differ_from_their_state() {
+    assert_eq!(
+        SnapshotPayload::<WorldSnapshot>::reflect_name(),
+        "SnapshotPayload<WorldSnapshot>"
+    );
+    assert_eq!(
+        SnapshotPayload::<WorldSnapshot>::id(),
+        bevy_rtc::protocol::protocol_id("snapshot::SnapshotPayload<WorldSnapshot>")
+    );
+}
@@ -1,22 +1,133 @@
 extern crate proc_macro;
 
 use proc_macro::TokenStream;
-use quote::quote;
-use std::{
-    collections::hash_map::DefaultHasher,
-    hash::{Hash, Hasher},
+use quote::{format_ident, quote};
+use syn::{
+    parse_macro_input, parse_quote, Data, DataStruct, DeriveInput, Field, Fields, GenericParam,
+    LitInt, LitStr, Path,
 };
-use syn::{parse_macro_input, DeriveInput};
 
-#[proc_macro_derive(Protocol)]
+#[proc_macro_derive(Protocol, attributes(protocol))]
 pub fn derive_payload_fn(item: TokenStream) -> TokenStream {
-    let DeriveInput { ident, .. } = parse_macro_input!(item);
-    let mut s = DefaultHasher::new();
-    ident.to_string().hash(&mut s);
-    let id = s.finish() as u16;
-    let reflect_name = ident.to_string();
+    let DeriveInput {
+        ident,
+        attrs,
+        generics,
+        ..
+    } = parse_macro_input!(item);
+
+    // Parse `#[protocol(id = 42, crate = "bevy_rtc")]`
+    let mut pinned_id = None;
+    let mut krate: Path = parse_quote!(bevy_rtc);
+    for attr in attrs.iter().filter(|attr| attr.path().is_ident("protocol")) {
+        let parsed = attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("id") {
+                let lit: LitInt = meta.value()?.parse()?;
+                pinned_id.replace(lit.base10_parse::<u16>()?);
+                Ok(())
+            } else if meta.path.is_ident("crate") {
+                let lit: LitStr = meta.value()?.parse()?;
+                krate = lit.parse()?;
+                Ok(())
+            } else {
+                Err(meta.error("unsupported protocol attribute, expected `id` or `crate`"))
+            }
+        });
+        if let Err(e) = parsed {
+            return e.to_compile_error().into();
+        }
+    }
+
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+    let generic = !generics.params.is_empty();
+    if generic && pinned_id.is_some() {
+        return syn::Error::new_spanned(
+            generics,
+            "generic protocols cannot pin an id, every instantiation would share it",
+        )
+        .to_compile_error()
+        .into();
+    }
+
+    // The module path is only known where the derive expands, so the stable
+    // hash is evaluated there in a const context. Generic protocols hash their
+    // path with the `TypePath` of each type argument and the value of each
+    // const argument, so each instantiation has its own id and name.
+    let args = generics.params.iter().filter_map(|param| match param {
+        GenericParam::Type(param) => {
+            let ident = &param.ident;
+            Some(quote! {
+                <#ident as #krate::protocol::TypePath>::type_path().to_string()
+            })
+        }
+        GenericParam::Const(param) => {
+            let ident = &param.ident;
+            Some(quote! { #ident.to_string() })
+        }
+        GenericParam::Lifetime(_) => None,
+    });
+    let short_args = generics.params.iter().filter_map(|param| match param {
+        GenericParam::Type(param) => {
+            let ident = &param.ident;
+            Some(quote! {
+                <#ident as #krate::protocol::TypePath>::short_type_path().to_string()
+            })
+        }
+        GenericParam::Const(param) => {
+            let ident = &param.ident;
+            Some(quote! { #ident.to_string() })
+        }
+        GenericParam::Lifetime(_) => None,
+    });
+    let id = match pinned_id {
+        Some(id) => quote! { #id },
+        None if generic => quote! {
+            static CELL: #krate::protocol::GenericTypePathCell =
+                #krate::protocol::GenericTypePathCell::new();
+            let path = CELL.get_or_insert::<Self, _>(|| {
+                let args: &[String] = &[#(#args),*];
+                format!("{}::{}<{}>", module_path!(), stringify!(#ident), args.join(", "))
+            });
+            #krate::protocol::protocol_id(path)
+        },
+        None => quote! {
+            const ID: u16 = #krate::protocol::protocol_id(
+                concat!(module_path!(), "::", stringify!(#ident))
+            );
+            ID
+        },
+    };
+    let reflect_name = if generic {
+        quote! {
+            static CELL: #krate::protocol::GenericTypePathCell =
+                #krate::protocol::GenericTypePathCell::new();
+            CELL.get_or_insert::<Self, _>(|| {
+                let args: &[String] = &[#(#short_args),*];
+                format!("{}<{}>", stringify!(#ident), args.join(", "))
+            })
+        }
+    } else {
+        let name = ident.to_string();
+        quote! { #name }
+    };
+
+    // Generic parameters must satisfy the bounds of a protocol
+    let mut predicates = where_clause
+        .map(|w| w.predicates.iter().cloned().collect::<Vec<_>>())
+        .unwrap_or_default();
+    if generic {
+        predicates.push(parse_quote! {
+            #ident #ty_generics: #krate::protocol::ProtocolBounds
+        });
+        for param in generics.type_params() {
+            let ident = &param.ident;
+            predicates.push(parse_quote! { #ident: #krate::protocol::TypePath });
+        }
+    }
+    let where_clause = (!predicates.is_empty()).then(|| quote! { where #(#predicates),* });
+
     quote! {
-        impl bevy_rtc::protocol::Protocol for #ident {
+        impl #impl_generics #krate::protocol::Protocol for #ident #ty_generics #where_clause {
             fn id() -> u16 {
                 #id
             }
@@ -27,3 +138,134 @@ pub fn derive_payload_fn(item: TokenStream) -> TokenStream {
     }
     .into()
 }
+
+#[proc_macro_derive(Snapshot, attributes(snapshot))]
+pub fn derive_snapshot_fn(item: TokenStream) -> TokenStream {
+    let DeriveInput {
+        ident,
+        vis,
+        attrs,
+        generics,
+        data,
+    } = parse_macro_input!(item);
+
+    // Parse `#[snapshot(crate = "bevy_rtc")]`
+    let mut krate: Path = parse_quote!(bevy_rtc);
+    for attr in attrs.iter().filter(|attr| attr.path().is_ident("snapshot")) {
+        let parsed = attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("crate") {
+                let lit: LitStr = meta.value()?.parse()?;
+                krate = lit.parse()?;
+                Ok(())
+            } else {
+                Err(meta.error("unsupported snapshot attribute, expected `crate`"))
+            }
+        });
+        if let Err(e) = parsed {
+            return e.to_compile_error().into();
+        }
+    }
+
+    if !generics.params.is_empty() {
+        return syn::Error::new_spanned(generics, "generic snapshots are not supported")
+            .to_compile_error()
+            .into();
+    }
+    let fields = match data {
+        Data::Struct(DataStruct {
+            fields: Fields::Named(fields),
+            ..
+        }) => fields.named,
+        _ => {
+            return syn::Error::new_spanned(ident, "snapshots must be structs with named fields")
+                .to_compile_error()
+                .into()
+        }
+    };
+
+    // Parse `#[snapshot(diff)]` on each field
+    let mut diffed = vec![];
+    for field in fields.iter() {
+        let mut diff = false;
+        for attr in field
+            .attrs
+            .iter()
+            .filter(|attr| attr.path().is_ident("snapshot"))
+        {
+            let parsed = attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("diff") {
+                    diff = true;
+                    Ok(())
+                } else {
+                    Err(meta.error("unsupported snapshot attribute, expected `diff`"))
+                }
+            });
+            if let Err(e) = parsed {
+                return e.to_compile_error().into();
+            }
+        }
+        diffed.push(diff);
+    }
+
+    let delta_ident = format_ident!("{ident}Delta");
+    let delta_fields = fields.iter().zip(&diffed).map(|(field, diff)| {
+        let Field { vis, ident, ty, .. } = field;
+        if *diff {
+            quote! { #vis #ident: ::core::option::Option<#ty> }
+        } else {
+            quote! { #vis #ident: #ty }
+        }
+    });
+    let diffs = fields.iter().zip(&diffed).map(|(field, diff)| {
+        let ident = &field.ident;
+        if *diff {
+            quote! {
+                #ident: (self.#ident != baseline.#ident).then(|| self.#ident.clone())
+            }
+        } else {
+            quote! { #ident: self.#ident.clone() }
+        }
+    });
+    let applies = fields.iter().zip(&diffed).map(|(field, diff)| {
+        let ident = &field.ident;
+        if *diff {
+            quote! {
+                #ident: delta.#ident.clone().unwrap_or_else(|| self.#ident.clone())
+            }
+        } else {
+            quote! { #ident: delta.#ident.clone() }
+        }
+    });
+    let delta_doc = format!("The changes to a [`{ident}`] snapshot since its baseline.");
+    let name = format!("SnapshotPayload<{ident}>");
+
+    quote! {
+        #[doc = #delta_doc]
+        #[derive(::core::fmt::Debug, ::core::clone::Clone, ::serde::Serialize, ::serde::Deserialize)]
+        #vis struct #delta_ident {
+            #(#delta_fields,)*
+        }
+
+        impl #krate::snapshot::Snapshot for #ident {
+            type Delta = #delta_ident;
+
+            const PROTOCOL_ID: u16 = #krate::protocol::protocol_id(
+                concat!(module_path!(), "::", #name)
+            );
+            const PROTOCOL_NAME: &'static str = #name;
+
+            fn diff(&self, baseline: &Self) -> Self::Delta {
+                #delta_ident {
+                    #(#diffs,)*
+                }
+            }
+
+            fn apply(&self, delta: &Self::Delta) -> Self {
+                Self {
+                    #(#applies,)*
+                }
+            }
+        }
+    }
+    .into()
+}
@@ -29,8 +29,14 @@ fn main() {
         }))
         .add_plugins(EguiPlugin)
         .add_plugins(RtcClientPlugin {
-            // CAREFUL: This encoding MUST match the server encoding!
-            encoding: TransportEncoding::Json,
+            // This encoding must match the server, or the connection is refused!
+            encoding: TransportEncoding::Binary,
+            ..default()
+        })
+        // Chat is human readable in network captures
+        .configure_client_protocol::<ChatPayload>(ProtocolOptions {
+            encoding: Some(TransportEncoding::Json),
+            ..default()
         })
         .add_client_rw_unbounded_protocol::<ChatPayload>()
         .add_client_rw_unbounded_protocol::<DrawLinePayload>()
@@ -86,6 +92,10 @@ fn print_events(mut events: EventReader<RtcClientEvent>) {
                 // Disconnected from host
                 warn!("Disconnected from host, reason: {reason:?}");
             }
+            RtcClientEvent::ProtocolMismatch(mismatch) => {
+                // Incompatible with host
+                error!("Incompatible with host: {mismatch}");
+            }
         }
     }
 }
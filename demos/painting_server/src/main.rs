@@ -9,8 +9,14 @@ fn main() {
         .add_plugins(LogPlugin::default())
         .add_plugins(RtcServerPlugin {
             port: 3536,
-            // CAREFUL: This encoding MUST match the client encoding!
-            encoding: TransportEncoding::Json,
+            // This encoding must match the clients, or they are refused!
+            encoding: TransportEncoding::Binary,
+            ..default()
+        })
+        // Chat is human readable in network captures
+        .configure_server_protocol::<ChatPayload>(ProtocolOptions {
+            encoding: Some(TransportEncoding::Json),
+            ..default()
         })
         .add_server_rw_protocol::<ChatPayload>(2)
         .add_server_rw_protocol::<DrawLinePayload>(2)
@@ -52,6 +58,15 @@ fn print_events(mut event_rdr: EventReader<RtcServerEvent>) {
             RtcServerEvent::IdAssigned(id) => {
                 info!("Server ready as {id}");
             }
+            RtcServerEvent::ProtocolMismatch(id, mismatch) => {
+                warn!("Client {id} is incompatible: {mismatch}");
+            }
+            RtcServerEvent::HandshakeTimeout(id) => {
+                warn!("Client {id} never sent its protocols");
+            }
+            RtcServerEvent::ClientTooSlow(id) => {
+                warn!("Client {id} fell too far behind");
+            }
         }
     }
 }
@@ -8,8 +8,9 @@ fn main() {
         .add_plugins(MinimalPlugins)
         .add_plugins(LogPlugin::default())
         .add_plugins(RtcClientPlugin {
-            // CAREFUL: This encoding MUST match the server encoding!
+            // This encoding must match the server, or the connection is refused!
             encoding: TransportEncoding::Json,
+            ..default()
         })
         .add_client_wo_protocol::<PingPayload>()
         .add_client_ro_protocol::<PongPayload>(1)
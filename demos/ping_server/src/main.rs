@@ -8,8 +8,9 @@ fn main() {
         .add_plugins(LogPlugin::default())
         .add_plugins(RtcServerPlugin {
             port: 3536,
-            // CAREFUL: This encoding MUST match the client encoding!
+            // This encoding must match the clients, or they are refused!
             encoding: TransportEncoding::Json,
+            ..default()
         })
         .add_server_ro_protocol::<PingPayload>(1)
         .add_server_wo_protocol::<PongPayload>()